@@ -0,0 +1,43 @@
+//! Measures how block mining scales with the number of mining threads. Threads stride through
+//! disjoint nonces, so the time per block should drop roughly linearly with the thread count, up
+//! to the number of cores.
+//!
+//! Run with `cargo bench --bench mining`.
+
+use KrakenChain::blockchain::{Amount, Blockchain};
+use chrono::Duration;
+use std::time::Instant;
+
+const DIFFICULTY: u32 = 16;
+const BLOCKS: u32 = 20;
+
+fn time_per_block(threads: usize) -> f64 {
+    let mut blockchain = Blockchain::new(DIFFICULTY, Amount::from_base_units(1_000_000_000), Duration::seconds(10));
+    blockchain.mining_threads = threads;
+    // Keep the difficulty fixed so every run solves the same kind of puzzle
+    blockchain.difficulty_adjustment_interval = u64::MAX;
+
+    let started = Instant::now();
+    for _ in 0..BLOCKS {
+        blockchain.mine_pending_transactions("miner").unwrap();
+    }
+    started.elapsed().as_secs_f64() / BLOCKS as f64
+}
+
+fn main() {
+    let mut thread_counts = vec![1];
+    while thread_counts.last().unwrap() * 2 <= num_cpus::get() {
+        thread_counts.push(thread_counts.last().unwrap() * 2);
+    }
+
+    let baseline = time_per_block(1);
+    let mut results = vec![(1, baseline)];
+    for &threads in &thread_counts[1..] {
+        results.push((threads, time_per_block(threads)));
+    }
+
+    println!("{} blocks per run at difficulty {}", BLOCKS, DIFFICULTY);
+    for (threads, seconds) in results {
+        println!("{:>3} threads: {:>8.1} ms per block, {:.2}x speedup", threads, seconds * 1000.0, baseline / seconds);
+    }
+}
@@ -0,0 +1,50 @@
+//! Compares the JSON and binary chain encodings over a 10,000-block chain.
+//!
+//! Run with `cargo bench --bench persistence`.
+
+use KrakenChain::blockchain::{decode_binary, encode_binary, Amount, Block, Transaction};
+use std::time::Instant;
+
+const BLOCKS: u64 = 10_000;
+
+fn build_chain() -> Vec<Block> {
+    let mut chain = vec![Block::new(0, Vec::new(), String::from("0"), 0)];
+    for index in 1..BLOCKS {
+        let transactions = vec![
+            Transaction::new("a".repeat(64), "b".repeat(64), Amount::from_base_units(150_000_000), Amount::from_base_units(10_000_000)),
+            Transaction::new(String::from("Blockchain"), "c".repeat(64), Amount::from_base_units(1_000_000_000), Amount::ZERO),
+        ];
+        let previous_hash = chain.last().unwrap().hash.clone();
+        chain.push(Block::new(index, transactions, previous_hash, 0));
+    }
+    chain
+}
+
+fn main() {
+    let chain = build_chain();
+
+    let started = Instant::now();
+    let json = serde_json::to_vec(&chain).unwrap();
+    let json_encode = started.elapsed();
+    let started = Instant::now();
+    let decoded: Vec<Block> = serde_json::from_slice(&json).unwrap();
+    let json_decode = started.elapsed();
+    assert_eq!(decoded.len(), chain.len());
+
+    let started = Instant::now();
+    let binary = encode_binary(&chain).unwrap();
+    let binary_encode = started.elapsed();
+    let started = Instant::now();
+    let decoded: Vec<Block> = decode_binary(&binary).unwrap();
+    let binary_decode = started.elapsed();
+    assert_eq!(decoded.len(), chain.len());
+
+    println!("{} blocks", BLOCKS);
+    println!("json:   {:>10} bytes, encode {:?}, decode {:?}", json.len(), json_encode, json_decode);
+    println!("binary: {:>10} bytes, encode {:?}, decode {:?}", binary.len(), binary_encode, binary_decode);
+    println!(
+        "binary is {:.1}x smaller and decodes {:.1}x faster",
+        json.len() as f64 / binary.len() as f64,
+        json_decode.as_secs_f64() / binary_decode.as_secs_f64()
+    );
+}
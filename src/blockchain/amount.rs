@@ -0,0 +1,99 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Sub, SubAssign};
+
+/// Number of base units in one whole coin.
+pub const BASE_UNITS_PER_COIN: u64 = 100_000_000;
+
+/// A non-negative quantity of coins, held as an integer number of base units so that sums are exact.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Amount(u64);
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0);
+
+    pub fn from_base_units(units: u64) -> Self {
+        Amount(units)
+    }
+
+    /// Converts whole coins to base units, rounding to the nearest unit (halves round up).
+    pub fn from_coins(coins: f64) -> Result<Self, String> {
+        if !coins.is_finite() || coins < 0.0 {
+            return Err(format!("Invalid coin amount: {}", coins));
+        }
+        let units = (coins * BASE_UNITS_PER_COIN as f64).round();
+        if units > u64::MAX as f64 {
+            return Err(format!("Coin amount out of range: {}", coins));
+        }
+        Ok(Amount(units as u64))
+    }
+
+    pub fn base_units(self) -> u64 {
+        self.0
+    }
+
+    pub fn to_coins(self) -> f64 {
+        self.0 as f64 / BASE_UNITS_PER_COIN as f64
+    }
+
+    pub fn checked_add(self, other: Amount) -> Option<Amount> {
+        self.0.checked_add(other.0).map(Amount)
+    }
+
+    pub fn checked_sub(self, other: Amount) -> Option<Amount> {
+        self.0.checked_sub(other.0).map(Amount)
+    }
+
+    pub fn saturating_sub(self, other: Amount) -> Amount {
+        Amount(self.0.saturating_sub(other.0))
+    }
+
+    /// Sums amounts, returning None on overflow; for totals over untrusted input.
+    pub fn checked_sum<'a>(amounts: impl IntoIterator<Item = &'a Amount>) -> Option<Amount> {
+        amounts.into_iter().try_fold(Amount::ZERO, |total, amount| total.checked_add(*amount))
+    }
+}
+
+// The operators panic on overflow like the integer operators; use the checked forms on
+// amounts that haven't been validated yet
+impl Add for Amount {
+    type Output = Amount;
+
+    fn add(self, other: Amount) -> Amount {
+        Amount(self.0.checked_add(other.0).expect("Amount overflow"))
+    }
+}
+
+impl AddAssign for Amount {
+    fn add_assign(&mut self, other: Amount) {
+        *self = *self + other;
+    }
+}
+
+impl Sub for Amount {
+    type Output = Amount;
+
+    fn sub(self, other: Amount) -> Amount {
+        Amount(self.0.checked_sub(other.0).expect("Amount underflow"))
+    }
+}
+
+impl SubAssign for Amount {
+    fn sub_assign(&mut self, other: Amount) {
+        *self = *self - other;
+    }
+}
+
+impl Sum for Amount {
+    fn sum<I: Iterator<Item = Amount>>(iter: I) -> Amount {
+        iter.fold(Amount::ZERO, Add::add)
+    }
+}
+
+/// Formats as whole coins with all eight decimal places, e.g. `1.50000000`.
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{:08}", self.0 / BASE_UNITS_PER_COIN, self.0 % BASE_UNITS_PER_COIN)
+    }
+}
@@ -1,16 +1,26 @@
 use chrono::{DateTime, Utc};
+use ring::signature::{Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-use uint::construct_uint;
+use std::sync::atomic::{AtomicBool, Ordering};
 use crate::utils::Logger;
 
+use super::codec::binary_size;
 use super::transaction::Transaction;
 use super::merkle_tree::MerkleTree;
+use super::proof_of_work::{compact_for_difficulty, ProofOfWork, Sha256ProofOfWork};
 
-construct_uint! {
-    pub struct U256(4);
+#[allow(clippy::manual_div_ceil, clippy::assign_op_pattern)]
+mod uint_types {
+    use uint::construct_uint;
+
+    construct_uint! {
+        pub struct U256(4);
+    }
 }
 
+pub use uint_types::U256;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Block {
     pub index: u64,
@@ -20,7 +30,54 @@ pub struct Block {
     pub hash: String,
     pub nonce: u64,
     pub difficulty: u32,
+    #[serde(default)]
+    pub bits: u32, // Compact encoding of the proof-of-work target, see `compact_to_target`
     pub merkle_root: Vec<u8>,
+    #[serde(default)]
+    pub producer: Option<String>, // Hex-encoded Ed25519 public key of the block producer
+    #[serde(default)]
+    pub producer_signature: Option<String>,
+}
+
+/// A block without its transactions, which is enough to check linkage and proof-of-work cheaply.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlockHeader {
+    pub index: u64,
+    pub timestamp: DateTime<Utc>,
+    pub previous_hash: String,
+    pub hash: String,
+    pub nonce: u64,
+    pub difficulty: u32,
+    #[serde(default)]
+    pub bits: u32,
+    pub merkle_root: Vec<u8>,
+    #[serde(default)]
+    pub producer: Option<String>,
+    #[serde(default)]
+    pub producer_signature: Option<String>,
+}
+
+impl BlockHeader {
+    pub fn calculate_hash(&self) -> String {
+        hash_header_fields(self.index, &self.timestamp, &self.merkle_root, &self.previous_hash, self.nonce, self.difficulty, self.bits)
+    }
+
+    // A transaction-less block carrying this header, for checks such as `ProofOfWork` that take a block
+    pub(crate) fn to_unfilled_block(&self) -> Block {
+        Block {
+            index: self.index,
+            timestamp: self.timestamp,
+            transactions: Vec::new(),
+            previous_hash: self.previous_hash.clone(),
+            hash: self.hash.clone(),
+            nonce: self.nonce,
+            difficulty: self.difficulty,
+            bits: self.bits,
+            merkle_root: self.merkle_root.clone(),
+            producer: self.producer.clone(),
+            producer_signature: self.producer_signature.clone(),
+        }
+    }
 }
 
 impl Block {
@@ -35,36 +92,95 @@ impl Block {
             hash: String::new(),
             nonce: 0,
             difficulty,
+            bits: compact_for_difficulty(difficulty),
             merkle_root: merkle_tree.root,
+            producer: None,
+            producer_signature: None,
         };
         block.hash = block.calculate_hash();
         Logger::block(&format!("New block created with hash: {}", block.hash));
         block
     }
 
+    /// Size of the block, header and all transactions, in the binary encoding used for persistence.
+    pub fn serialized_size(&self) -> usize {
+        binary_size(self).expect("Blocks always have a binary encoding")
+    }
+
+    pub fn header(&self) -> BlockHeader {
+        BlockHeader {
+            index: self.index,
+            timestamp: self.timestamp,
+            previous_hash: self.previous_hash.clone(),
+            hash: self.hash.clone(),
+            nonce: self.nonce,
+            difficulty: self.difficulty,
+            bits: self.bits,
+            merkle_root: self.merkle_root.clone(),
+            producer: self.producer.clone(),
+            producer_signature: self.producer_signature.clone(),
+        }
+    }
+
     pub fn calculate_hash(&self) -> String {
         Logger::block(&format!("Calculating hash for block: {}", self.index));
-        let mut hasher = Sha256::new();
-        hasher.update(self.index.to_string());
-        hasher.update(self.timestamp.to_string());
-        hasher.update(&self.merkle_root);
-        hasher.update(&self.previous_hash);
-        hasher.update(self.nonce.to_string());
-        hasher.update(self.difficulty.to_string());
-        let hash = format!("{:x}", hasher.finalize());
+        let hash = hash_header_fields(self.index, &self.timestamp, &self.merkle_root, &self.previous_hash, self.nonce, self.difficulty, self.bits);
         Logger::block(&format!("Calculated hash for block {}: {}", self.index, hash));
         hash
     }
 
-    pub fn mine_block(&mut self, difficulty: u32) -> bool {
+    pub fn verify_self_hash(&self) -> bool {
+        let valid = self.hash == self.calculate_hash();
+        if !valid {
+            Logger::error(&format!("Stored hash of block {} does not match its contents", self.index));
+        }
+        valid
+    }
+
+    /// Signs the block hash with the producer's key. Must be called after mining, since the signature covers the final hash.
+    pub fn sign_header(&mut self, key_pair: &Ed25519KeyPair) {
+        Logger::block(&format!("Signing block {} as producer", self.index));
+        self.producer = Some(hex::encode(key_pair.public_key().as_ref()));
+        self.producer_signature = Some(hex::encode(key_pair.sign(self.hash.as_bytes()).as_ref()));
+    }
+
+    pub fn has_valid_producer_signature(&self) -> bool {
+        let (Some(producer), Some(signature)) = (&self.producer, &self.producer_signature) else {
+            return false;
+        };
+        let (Ok(public_key), Ok(signature)) = (hex::decode(producer), hex::decode(signature)) else {
+            return false;
+        };
+        UnparsedPublicKey::new(&ED25519, public_key)
+            .verify(self.hash.as_bytes(), &signature)
+            .is_ok()
+    }
+
+    /// Searches for a nonce meeting `difficulty`, giving up after `max_attempts` hashes or as soon
+    /// as `stop` is set, e.g. because another miner found the block first. Returns true once solved.
+    pub fn mine_block(&mut self, difficulty: u32, stop: &AtomicBool, max_attempts: u64) -> bool {
+        self.mine_block_with(&Sha256ProofOfWork, difficulty, stop, max_attempts)
+    }
+
+    pub fn mine_block_with(&mut self, proof_of_work: &dyn ProofOfWork, difficulty: u32, stop: &AtomicBool, max_attempts: u64) -> bool {
         Logger::mining(&format!("Mining block: {} with difficulty: {}", self.index, difficulty));
-        let target = (1u128 << (128 - difficulty)) - 1;
-        let mut attempts = 0;
-        while u128::from_str_radix(&self.hash[..32], 16).unwrap_or(u128::MAX) > target {
-            self.nonce += 1;
+        self.mine_block_strided(proof_of_work, difficulty, 1, stop, max_attempts)
+    }
+
+    /// Like `mine_block_with`, but only tries every `stride`-th nonce starting from the current one.
+    /// Parallel miners start thread `i` at nonce `i` with a stride of the thread count, so no two
+    /// threads ever hash the same nonce.
+    pub fn mine_block_strided(&mut self, proof_of_work: &dyn ProofOfWork, difficulty: u32, stride: u64, stop: &AtomicBool, max_attempts: u64) -> bool {
+        let mut attempts: u64 = 0;
+        while !proof_of_work.meets_target(self, difficulty) {
+            if attempts == max_attempts || stop.load(Ordering::Relaxed) {
+                Logger::mining(&format!("Stopped mining block {} after {} attempts", self.index, attempts));
+                return false;
+            }
+            self.nonce += stride;
             self.hash = self.calculate_hash();
             attempts += 1;
-            if attempts % 100000 == 0 {
+            if attempts.is_multiple_of(100000) {
                 Logger::mining(&format!("Mining attempt {}: current hash {}", attempts, self.hash));
             }
         }
@@ -79,9 +195,25 @@ impl Block {
         valid
     }
 
-    pub fn hash_to_u256(&self, hash: &str) -> U256 {
-        let u256 = U256::from_big_endian(&hex::decode(hash).unwrap());
+    /// Parses a hash as a big-endian 256-bit number. Returns `None` unless it is 64 hex characters,
+    /// e.g. for a hash read from an untrusted chain file.
+    pub fn hash_to_u256(&self, hash: &str) -> Option<U256> {
+        let bytes = hex::decode(hash).ok().filter(|bytes| bytes.len() == 32)?;
+        let u256 = U256::from_big_endian(&bytes);
         Logger::info(&format!("Converted hash to U256 for block {}: {}", self.index, u256));
-        u256
+        Some(u256)
     }
-}
\ No newline at end of file
+}
+
+// The block hash commits to the header only; transactions are covered through the merkle root
+fn hash_header_fields(index: u64, timestamp: &DateTime<Utc>, merkle_root: &[u8], previous_hash: &str, nonce: u64, difficulty: u32, bits: u32) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(index.to_string());
+    hasher.update(timestamp.to_string());
+    hasher.update(merkle_root);
+    hasher.update(previous_hash);
+    hasher.update(nonce.to_string());
+    hasher.update(difficulty.to_string());
+    hasher.update(bits.to_string());
+    format!("{:x}", hasher.finalize())
+}
@@ -1,40 +1,186 @@
-use super::block::Block;
-use super::transaction::Transaction;
-use crate::blockchain::merkle_tree::MerkleTree;
-use std::collections::HashMap;
-use crate::utils::Logger;
+use super::amount::{Amount, BASE_UNITS_PER_COIN};
+use super::block::{Block, BlockHeader};
+use super::builder::BlockchainBuilder;
+use super::checkpoint::{BalanceSnapshot, SignedCheckpoints};
+use super::error::ChainError;
+use super::codec::{binary_header, check_binary_header, decode_binary, encode_binary, encode_record, read_record};
+use super::proof_of_work::{compact_for_difficulty, ProofOfWork, Sha256ProofOfWork};
+use super::stats::{ChainStats, FeeEfficiencyReport, NodeStatus};
+use super::store::ChainStore;
+use super::subscription::{AddressSubscriptions, ChainEvent, EventBroadcast, TransactionStatus, TransactionUpdate, WaitError};
+use super::transaction::{Transaction, MAX_TRANSACTION_DATA_LEN};
+use super::utxo::{TxInput, TxOutput, UtxoSet};
+use crate::blockchain::merkle_tree::{MerkleProof, MerkleTree};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use crate::utils::{Clock, Logger, SystemClock};
+use chrono::{DateTime, Utc};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use rand::rngs::StdRng;
+use ring::signature::Ed25519KeyPair;
+use rand::{Rng, SeedableRng};
 use serde_json;
-use std::fs::File;
-use std::io::{Read, Write};
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Receiver;
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::Instant;
 
 const MIN_FEE_RATE: f64 = 0.00001; // Satoshis per byte
+const MINING_BATCH_ATTEMPTS: u64 = 10_000; // Hashes a mining thread tries between checks for a stop request
+const MAX_ORPHAN_BLOCKS: usize = 100;
+const MAX_BLOCK_TRANSACTIONS: usize = 1000;
+const BLOCK_TIME_WINDOW_LEN: usize = 10; // Recent block times kept for reporting
+pub(crate) const DEFAULT_CHAIN_ID: u64 = 1; // Mainnet; test networks override `chain_id`
+
+/// How the coinbase transaction of each mined block is funded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RewardPolicy {
+    /// The coinbase pays `mining_reward`, halved every `halving_interval` blocks if set, plus the
+    /// fees collected from the block.
+    #[default]
+    BlockSubsidy,
+    /// No new coins are minted: the coinbase carries only the fees collected from the block,
+    /// which are charged to the senders. Suited to permissioned or test networks.
+    FeesOnly,
+}
+
+/// How the producer of each new block is chosen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Consensus {
+    /// Miners race to find a hash meeting the difficulty target.
+    #[default]
+    ProofOfWork,
+    /// No hashing: a validator is drawn with probability proportional to their balance, seeded
+    /// by the tip hash so every node draws the same one, and the coinbase pays them. Blocks keep
+    /// the chain's difficulty and bits, but their hashes need not meet the target.
+    ProofOfStake,
+}
+
+/// Which ledger model a chain keeps balances in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LedgerModel {
+    /// Each address has a single balance that transactions debit and credit.
+    #[default]
+    Account,
+    /// Transactions spend the unspent outputs of earlier transactions and create new ones, and
+    /// an address's balance is the sum of the unspent outputs paying it.
+    Utxo,
+}
+
+/// How transactions are picked from the mempool when assembling a block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SelectionPolicy {
+    /// Take the highest fee-rate transactions first.
+    #[default]
+    Greedy,
+    /// Sample transactions with probability proportional to their fee rate, making block
+    /// composition harder to predict. The seed is mixed with the chain height, so selection
+    /// is reproducible for a given mempool and height.
+    FeeWeighted { seed: u64 },
+}
+
+/// Scales the recommended confirmation depth with transaction value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConfirmationPolicy {
+    /// Confirmations recommended for amounts below `value_step`.
+    pub base_confirmations: u64,
+    /// Each doubling of the amount beyond this value adds one confirmation.
+    pub value_step: Amount,
+    pub max_confirmations: u64,
+}
+
+impl Default for ConfirmationPolicy {
+    fn default() -> Self {
+        ConfirmationPolicy {
+            base_confirmations: 1,
+            value_step: Amount::from_base_units(100 * BASE_UNITS_PER_COIN),
+            max_confirmations: 100,
+        }
+    }
+}
 
 pub struct Blockchain {
     pub chain: Vec<Block>,
     pub difficulty: u32,
     pub pending_transactions: Vec<Transaction>,
-    pub mining_reward: f64,
-    balances: HashMap<String, f64>,
+    pub mining_reward: Amount,
+    pub coinbase_maturity: u64, // Blocks that must be mined on top of a coinbase before it can be spent
+    pub halving_interval: Option<u64>, // Blocks between reward halvings; the reward never halves when unset
+    balances: HashMap<String, Amount>,
+    account_nonces: HashMap<String, u64>, // Next nonce each address may use on chain
+    utxos: UtxoSet, // Unspent outputs, maintained only under `LedgerModel::Utxo`
     pub target_block_time: chrono::Duration,
     pub mempool: Vec<Transaction>,
-    pub block_time_window: Vec<chrono::Duration>,
+    pub block_time_window: Vec<chrono::Duration>, // Times of the most recent blocks, for reporting only
     pub difficulty_adjustment_interval: u64,
     pub max_mempool_size: usize,
     pub max_mempool_size_bytes: usize,
     pub mempool_size_bytes: usize,
+    pub max_block_size_bytes: usize, // Transactions beyond this size wait for a later block
+    pub max_block_data_bytes: usize, // Caps the transaction data payloads carried by one block
+    pub proof_of_work: Arc<dyn ProofOfWork>,
+    pub checkpoints: HashMap<u64, String>,
+    pub mining_threads: usize,
+    pub measured_hashrate: f64, // Hashes per second per mining thread
+    pub consensus: Consensus,
+    pub ledger_model: LedgerModel, // Change through `with_ledger_model` so the output set is built
+    pub reward_policy: RewardPolicy,
+    pub selection_policy: SelectionPolicy,
+    pub clock: Arc<dyn Clock>,
+    pub mempool_tx_ttl: Option<chrono::Duration>, // Maximum time a transaction may wait in the mempool
+    pub max_expiration_horizon: Option<chrono::Duration>, // How far ahead a mempool transaction may expire, e.g. 72 hours
+    mempool_first_seen: HashMap<String, DateTime<Utc>>,
+    pub authorized_producers: Option<HashSet<String>>, // Permissioned mode when set
+    pub confirmation_policy: ConfirmationPolicy,
+    block_log: Option<(String, File)>, // Path and handle of the append-only log receiving every block accepted by `add_block`
+    store: Option<Box<dyn ChainStore>>, // Write-through storage backend for accepted blocks
+    pub genesis_hash: String, // Hash every validated chain must start from
+    pub chain_id: u64, // Identifies the network; peers on a different chain are refused
+    prune_base: Option<PruneBase>, // Set once `prune_to` has dropped the start of the chain
+    orphans: HashMap<String, (u64, Block)>, // Blocks received ahead of their parents by hash, with their arrival number
+    orphan_arrivals: u64, // Arrival number of the next parked orphan, so the oldest can be evicted first
+    credits: Vec<(u64, TxInput, TxOutput)>, // Funds granted by `add_balance` and the height they were granted at
+    pub max_address_outflow_per_block: Option<Amount>, // Caps amount plus fee sent by one address within a block
+    paused: bool, // Quiesces mining and mempool cleanup, see `pause`
+    subscriptions: AddressSubscriptions,
+    events: EventBroadcast,
+    transaction_index: Mutex<Option<TransactionIndex>>, // Built on first lookup, see `get_transaction_by_id`
+    block_heights: HashMap<String, u64>, // Height of every block by hash, kept up to date as blocks are added
+}
+
+// Block index and position within the block of every confirmed transaction, valid while the tip
+// is still `tip_hash`
+struct TransactionIndex {
+    tip_hash: String,
+    positions: HashMap<String, (u64, usize)>,
+}
+
+// Where a pruned chain now starts, and the balances accumulated by the dropped blocks before it
+struct PruneBase {
+    height: u64,
+    hash: String,
+    balances: HashMap<String, Amount>,
+    nonces: HashMap<String, u64>,
+    utxos: UtxoSet,
 }
 
 impl Blockchain {
-    pub fn new(difficulty: u32, mining_reward: f64, target_block_time: chrono::Duration) -> Self {
+    pub fn new(difficulty: u32, mining_reward: Amount, target_block_time: chrono::Duration) -> Self {
         Logger::info(&format!("Creating new blockchain with difficulty: {}, mining reward: {}, target block time: {:?}", difficulty, mining_reward, target_block_time));
         let mut blockchain = Blockchain {
             chain: Vec::new(),
             difficulty,
             pending_transactions: Vec::new(),
             mining_reward,
+            coinbase_maturity: 0,
+            halving_interval: None,
             balances: HashMap::new(),
+            account_nonces: HashMap::new(),
+            utxos: UtxoSet::default(),
             target_block_time,
             mempool: Vec::new(),
             block_time_window: Vec::new(),
@@ -42,300 +188,1634 @@ impl Blockchain {
             max_mempool_size: 1000, // Adjust this value as needed
             max_mempool_size_bytes: 5_000_000, // 5 MB limit
             mempool_size_bytes: 0,
+            max_block_size_bytes: 1_000_000, // 1 MB limit
+            max_block_data_bytes: 64_000, // 64 KB limit
+            proof_of_work: Arc::new(Sha256ProofOfWork),
+            checkpoints: HashMap::new(),
+            mining_threads: num_cpus::get(),
+            measured_hashrate: 0.0,
+            consensus: Consensus::default(),
+            ledger_model: LedgerModel::default(),
+            reward_policy: RewardPolicy::default(),
+            selection_policy: SelectionPolicy::default(),
+            clock: Arc::new(SystemClock),
+            mempool_tx_ttl: None,
+            max_expiration_horizon: None,
+            mempool_first_seen: HashMap::new(),
+            authorized_producers: None,
+            confirmation_policy: ConfirmationPolicy::default(),
+            block_log: None,
+            store: None,
+            genesis_hash: Blockchain::genesis_block(difficulty).hash,
+            chain_id: DEFAULT_CHAIN_ID,
+            prune_base: None,
+            orphans: HashMap::new(),
+            orphan_arrivals: 0,
+            credits: Vec::new(),
+            max_address_outflow_per_block: None,
+            paused: false,
+            subscriptions: AddressSubscriptions::default(),
+            events: EventBroadcast::default(),
+            transaction_index: Mutex::new(None),
+            block_heights: HashMap::new(),
         };
         blockchain.create_genesis_block();
         blockchain
     }
 
+    /// Starts configuring a blockchain with settings beyond those `new` takes; finish with `build`.
+    pub fn builder(difficulty: u32, mining_reward: Amount, target_block_time: chrono::Duration) -> BlockchainBuilder {
+        BlockchainBuilder::new(difficulty, mining_reward, target_block_time)
+    }
+
+    /// Creates a blockchain producing blocks under the given consensus mode; `new` uses proof-of-work.
+    pub fn with_consensus(difficulty: u32, mining_reward: Amount, target_block_time: chrono::Duration, consensus: Consensus) -> Self {
+        let mut blockchain = Blockchain::new(difficulty, mining_reward, target_block_time);
+        blockchain.consensus = consensus;
+        blockchain
+    }
+
+    /// Creates a blockchain keeping balances under the given ledger model; `new` uses accounts.
+    pub fn with_ledger_model(difficulty: u32, mining_reward: Amount, target_block_time: chrono::Duration, ledger_model: LedgerModel) -> Self {
+        let mut blockchain = Blockchain::new(difficulty, mining_reward, target_block_time);
+        blockchain.ledger_model = ledger_model;
+        blockchain.recalculate_balances();
+        blockchain
+    }
+
+    /// Creates a blockchain whose block subsidy halves every `halving_interval` blocks, so the
+    /// coinbase of the block at height `h` pays `mining_reward / 2^(h / halving_interval)`.
+    pub fn with_halving_interval(difficulty: u32, mining_reward: Amount, target_block_time: chrono::Duration, halving_interval: u64) -> Self {
+        let mut blockchain = Blockchain::new(difficulty, mining_reward, target_block_time);
+        blockchain.halving_interval = Some(halving_interval);
+        blockchain
+    }
+
+    /// Rebuilds a blockchain from existing blocks, e.g. received during sync or read from a storage
+    /// backend. `difficulty` is the chain's starting difficulty and must match the genesis block;
+    /// every later block is validated in order and the first bad index is named in the error.
+    /// Chains created with non-default settings are rebuilt via `BlockchainBuilder::build_from_blocks`.
+    pub fn from_blocks(blocks: Vec<Block>, difficulty: u32, mining_reward: Amount, target_block_time: chrono::Duration) -> Result<Blockchain, ChainError> {
+        Blockchain::builder(difficulty, mining_reward, target_block_time).build_from_blocks(blocks)
+    }
+
+    /// Rebuilds a blockchain from the blocks held in `store`, validating them as `from_blocks` does,
+    /// and keeps the store attached so newly accepted blocks are written through to it.
+    pub fn from_store(store: Box<dyn ChainStore>, difficulty: u32, mining_reward: Amount, target_block_time: chrono::Duration) -> Result<Blockchain, ChainError> {
+        Blockchain::builder(difficulty, mining_reward, target_block_time).build_from_store(store)
+    }
+
+    // Swaps the fresh genesis of a newly built blockchain for `blocks`, validated under its settings
+    pub(crate) fn adopt_blocks(mut self, blocks: Vec<Block>) -> Result<Blockchain, ChainError> {
+        if blocks.first().is_some_and(|genesis| genesis.difficulty != self.difficulty) {
+            return Err(ChainError::InvalidBlock(format!("Genesis block difficulty does not match the starting difficulty {}", self.difficulty)));
+        }
+        self.validate_external_chain(&blocks)?;
+
+        self.chain = blocks;
+        self.reindex_blocks();
+        self.recalculate_balances();
+        self.rebuild_difficulty_state();
+        Ok(self)
+    }
+
+    pub(crate) fn adopt_store(self, store: Box<dyn ChainStore>) -> Result<Blockchain, ChainError> {
+        let blocks = (0..store.len())
+            .map(|index| store.get_block(index).ok_or_else(|| ChainError::Storage(format!("Chain store is missing block {}", index))))
+            .collect::<Result<Vec<Block>, ChainError>>()?;
+        let mut blockchain = self.adopt_blocks(blocks)?;
+        blockchain.store = Some(store);
+        Ok(blockchain)
+    }
+
+    /// Attaches a storage backend. Blocks the store does not hold yet are written to it, and every
+    /// block accepted by `add_block` afterwards is written through; reorgs and reloads rewrite the
+    /// blocks that changed. Blocks dropped by `prune_to` stay in the store. The in-memory chain
+    /// remains the working copy used for validation and queries.
+    pub fn attach_store(&mut self, mut store: Box<dyn ChainStore>) -> Result<(), ChainError> {
+        if store.len() > self.chain.len() as u64 {
+            return Err(ChainError::Storage(format!("Chain store holds {} blocks but the chain only has {}", store.len(), self.chain.len())));
+        }
+        for block in &self.chain[store.len() as usize..] {
+            store.put_block(block).map_err(ChainError::Storage)?;
+        }
+        self.store = Some(store);
+        Ok(())
+    }
+
+    // Rewrites stored blocks that differ from the chain and drops any stored past its tip, after
+    // the chain was replaced rather than extended
+    fn sync_store(&mut self) {
+        let Some(store) = self.store.as_mut() else {
+            return;
+        };
+        for block in &self.chain {
+            if store.get_block(block.index).is_none_or(|stored| stored.hash != block.hash) {
+                if let Err(e) = store.put_block(block) {
+                    Logger::error(&format!("Failed to write block {} to the chain store: {}", block.index, e));
+                    return;
+                }
+            }
+        }
+        let len = self.chain.last().map_or(0, |tip| tip.index + 1);
+        if store.len() > len {
+            if let Err(e) = store.truncate(len) {
+                Logger::error(&format!("Failed to truncate the chain store to {} blocks: {}", len, e));
+            }
+        }
+    }
+
+    pub fn detach_store(&mut self) -> Option<Box<dyn ChainStore>> {
+        self.store.take()
+    }
+
+    pub fn store(&self) -> Option<&dyn ChainStore> {
+        self.store.as_deref()
+    }
+
+    /// The deterministic genesis block for a chain starting at `difficulty`. Its fixed timestamp
+    /// makes the hash reproducible, so independently started nodes agree on it.
+    pub fn genesis_block(difficulty: u32) -> Block {
+        let mut genesis_block = Block::new(0, Vec::new(), String::from("0"), difficulty);
+        genesis_block.timestamp = DateTime::from_timestamp(GENESIS_TIMESTAMP, 0).expect("Genesis timestamp is valid");
+        genesis_block.hash = genesis_block.calculate_hash();
+        genesis_block
+    }
+
     fn create_genesis_block(&mut self) {
-        let genesis_block = Block::new(0, Vec::new(), String::from("0"), self.difficulty);
-        self.chain.push(genesis_block);
+        self.chain.push(Blockchain::genesis_block(self.difficulty));
+        self.reindex_blocks();
+    }
+
+    pub(crate) fn reindex_blocks(&mut self) {
+        self.block_heights = self.chain.iter().map(|block| (block.hash.clone(), block.index)).collect();
+    }
+
+    pub fn get_block_by_index(&self, index: u64) -> Option<&Block> {
+        self.chain.get(self.position_of(index)?)
+    }
+
+    /// Looks a block up by hash in constant time. Blocks pushed onto `chain` directly rather than
+    /// through `add_block` are not indexed and are found by a scan instead.
+    pub fn get_block_by_hash(&self, hash: &str) -> Option<&Block> {
+        let indexed = self.block_heights.get(hash)
+            .and_then(|height| self.get_block_by_index(*height))
+            .filter(|block| block.hash == hash);
+        indexed.or_else(|| self.chain.iter().rev().find(|block| block.hash == hash))
+    }
+
+    // The first stored block is either genesis or, after pruning, the block the chain was pruned to
+    fn check_chain_base(&self, base: &Block) -> Result<(), ChainError> {
+        match &self.prune_base {
+            Some(prune_base) if base.index == prune_base.height && base.hash == prune_base.hash && base.verify_self_hash() => Ok(()),
+            Some(prune_base) => Err(ChainError::InvalidBlock(format!("First block does not match the pruning checkpoint at height {}", prune_base.height))),
+            None => self.check_genesis(base),
+        }
+    }
+
+    // After pruning, the first retarget windows reach into dropped blocks, so the difficulty of
+    // the blocks right after the checkpoint is taken as recorded
+    fn difficulty_window_pruned(&self, position: usize) -> bool {
+        self.prune_base.is_some() && position <= self.difficulty_adjustment_interval as usize
     }
 
+    // Genesis is exempt from proof-of-work but must be exactly the expected block
+    fn check_genesis(&self, genesis: &Block) -> Result<(), ChainError> {
+        if genesis.index != 0 || genesis.previous_hash != "0" || !genesis.transactions.is_empty() {
+            return Err(ChainError::InvalidBlock("Genesis block is malformed".to_string()));
+        }
+        if !genesis.verify_self_hash() {
+            return Err(ChainError::InvalidBlock("Genesis block hash does not match its contents".to_string()));
+        }
+        if genesis.hash != self.genesis_hash {
+            return Err(ChainError::InvalidBlock(format!("Genesis block hash {} does not match the expected {}", genesis.hash, self.genesis_hash)));
+        }
+        Ok(())
+    }
+
+    /// The tip of the chain. Every constructor installs at least a genesis block, so this treats an
+    /// empty chain as a broken invariant and panics; use `try_get_latest_block` where the chain may
+    /// have been emptied.
     pub fn get_latest_block(&self) -> &Block {
         self.chain.last().expect("Blockchain is empty")
     }
 
-    pub fn add_transaction(&mut self, transaction: Transaction) -> Result<(), String> {
+    /// The tip of the chain, or None if it holds no blocks.
+    pub fn try_get_latest_block(&self) -> Option<&Block> {
+        self.chain.last()
+    }
+
+    // The tip for operations that build on it, which fail rather than panic on an empty chain
+    fn tip(&self) -> Result<&Block, ChainError> {
+        self.try_get_latest_block().ok_or_else(|| ChainError::NotFound("Blockchain is empty".to_string()))
+    }
+
+    pub fn add_transaction(&mut self, transaction: Transaction) -> Result<(), ChainError> {
         Logger::transaction(&format!("Adding new transaction: {:?}", transaction));
         if !transaction.is_valid() {
-            return Err("Invalid transaction".to_string());
+            return Err(ChainError::InvalidTransaction("Invalid transaction".to_string()));
         }
 
-        let sender_balance = self.get_balance(&transaction.from);
-        if sender_balance < transaction.amount {
-            return Err("Insufficient balance".to_string());
-        }
+        self.check_spendable(&transaction.from, transaction.amount)?;
 
         self.pending_transactions.push(transaction);
         Ok(())
     }
 
-    pub fn add_balance(&mut self, address: &str, amount: f64) {
-        *self.balances.entry(address.to_string()).or_insert(0.0) += amount;
+    /// Credits `address` off-chain. Under `LedgerModel::Utxo` the credit becomes a new unspent
+    /// output, so it can be spent like any other.
+    pub fn add_balance(&mut self, address: &str, amount: Amount) {
+        let height = self.try_get_latest_block().map_or(0, |tip| tip.index);
+        let input = TxInput { prev_tx_id: uuid::Uuid::new_v4().to_string(), output_index: 0 };
+        let output = TxOutput { address: address.to_string(), amount };
+        *self.balances.entry(address.to_string()).or_default() += amount;
+        if self.ledger_model == LedgerModel::Utxo {
+            self.utxos.insert(input.clone(), output.clone());
+        }
+        // Recorded so replays of the chain grant the funds again right after the same block
+        self.credits.push((height, input, output));
     }
 
-    pub fn mine_pending_transactions(&mut self, miner_address: &str) -> Result<(), String> {
-        Logger::mining(&format!("Mining pending transactions for miner: {}", miner_address));
+    pub fn mine_pending_transactions(&mut self, miner_address: &str) -> Result<(), ChainError> {
+        self.mine_pending_transactions_cancellable(miner_address, Arc::new(AtomicBool::new(false)))
+    }
 
-        let transactions = self.get_transactions_from_mempool(1000);
-        let transactions = if transactions.is_empty() {
-            self.pending_transactions.drain(..).collect()
+    /// Like `mine_pending_transactions`, but gives up once `stop` is set, e.g. by a node shutting
+    /// down. A cancelled attempt returns its transactions to the mempool and adds no block.
+    pub fn mine_pending_transactions_cancellable(&mut self, miner_address: &str, stop: Arc<AtomicBool>) -> Result<(), ChainError> {
+        self.produce_block(miner_address, &stop, None)
+    }
+
+    /// Mines a block and signs its header with the producer's key, as required in permissioned mode.
+    pub fn mine_pending_transactions_as(&mut self, miner_address: &str, producer_key: &Ed25519KeyPair) -> Result<(), ChainError> {
+        self.produce_block(miner_address, &AtomicBool::new(false), Some(producer_key))
+    }
+
+    // Mines and adds a block; if it is cancelled or fails validation, its transactions are given back
+    fn produce_block(&mut self, miner_address: &str, stop: &AtomicBool, producer_key: Option<&Ed25519KeyPair>) -> Result<(), ChainError> {
+        self.ensure_not_paused()?;
+        let producer = self.block_producer(miner_address)?;
+        let first_seen = self.mempool_first_seen.clone();
+        let (mut block, from_mempool) = self.mine_new_block(&producer, stop, &first_seen)?;
+        if let Some(key) = producer_key {
+            block.sign_header(key);
+        }
+        let transactions = block.transactions.clone();
+        let result = self.add_mined_block(block);
+        if result.is_err() {
+            self.return_unmined(transactions, from_mempool, &first_seen);
+        }
+        result
+    }
+
+    // Puts the transactions of a block that was not added back where they were drained from
+    fn return_unmined(&mut self, transactions: Vec<Transaction>, from_mempool: bool, first_seen: &HashMap<String, DateTime<Utc>>) {
+        let transactions = transactions.into_iter().filter(|tx| tx.from != "Blockchain");
+        if from_mempool {
+            for transaction in transactions {
+                if let Some(seen) = first_seen.get(&transaction.id) {
+                    self.mempool_first_seen.insert(transaction.id.clone(), *seen);
+                }
+                self.mempool.push(transaction);
+            }
+            self.sort_mempool();
+            self.repair_mempool_accounting();
         } else {
+            self.pending_transactions.extend(transactions);
+        }
+    }
+
+    // Under proof-of-stake the drawn validator produces the block in place of the caller
+    fn block_producer(&self, miner_address: &str) -> Result<String, ChainError> {
+        match self.consensus {
+            Consensus::ProofOfWork => Ok(miner_address.to_string()),
+            Consensus::ProofOfStake => self.next_validator().ok_or_else(|| ChainError::Mining("No validator has stake to produce the block".to_string())),
+        }
+    }
+
+    /// The validator entitled to produce the block after the current tip under proof-of-stake:
+    /// an address drawn with probability proportional to its balance, using a generator seeded
+    /// by the tip hash. Returns `None` if no address holds a positive balance.
+    pub fn next_validator(&self) -> Option<String> {
+        draw_validator(&self.balances, &self.try_get_latest_block()?.hash)
+    }
+
+    /// Quiesces the node, e.g. before taking a snapshot: mining is refused and mempool cleanup is
+    /// skipped until `resume` is called. Blocks and transactions from peers are still accepted.
+    pub fn pause(&mut self) {
+        self.paused = true;
+        Logger::info("Paused mining and mempool cleanup");
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+        Logger::info("Resumed mining and mempool cleanup");
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    fn ensure_not_paused(&self) -> Result<(), ChainError> {
+        if self.paused {
+            return Err(ChainError::Mining("Mining is paused".to_string()));
+        }
+        Ok(())
+    }
+
+    fn add_mined_block(&mut self, block: Block) -> Result<(), ChainError> {
+        match self.add_block(block) {
+            Ok(()) => {
+                Logger::mining("Successfully mined and added new block");
+                Ok(())
+            }
+            Err(e) => {
+                Logger::error(&format!("Failed to mine block: {}", e));
+                Err(e)
+            }
+        }
+    }
+
+    pub fn add_block(&mut self, block: Block) -> Result<(), ChainError> {
+        self.extend_chain(block)?;
+        self.update_balances();
+
+        let latest = self.tip()?.clone();
+        for transaction in &latest.transactions {
+            self.subscriptions.notify(transaction, TransactionStatus::Confirmed(1));
+        }
+        self.events.publish(ChainEvent::NewBlock(latest.clone()));
+
+        if self.block_log.is_some() {
+            if let Err(e) = self.append_block_to_log(&latest) {
+                Logger::error(&format!("Failed to append block {} to the block log: {}", latest.index, e));
+            }
+        }
+        if let Some(store) = self.store.as_mut() {
+            if let Err(e) = store.put_block(&latest) {
+                Logger::error(&format!("Failed to write block {} to the chain store: {}", latest.index, e));
+            }
+        }
+        Ok(())
+    }
+
+    /// Accepts a block received from elsewhere. A block extending the tip is added, followed by any
+    /// parked orphans that now connect; a block further ahead is parked in the orphan pool until
+    /// its parent arrives. Returns the number of blocks connected to the chain.
+    pub fn submit_block(&mut self, block: Block) -> Result<usize, ChainError> {
+        let tip = self.tip()?;
+        if block.previous_hash != tip.hash {
+            if block.index <= tip.index + 1 {
+                return Err(ChainError::InvalidBlock(format!("Block {} does not extend the current tip", block.index)));
+            }
+            // The block's own difficulty can't be trusted before its parent is known, so demand
+            // at least the work the chain currently requires
+            if block.difficulty < self.difficulty {
+                return Err(ChainError::InvalidBlock(format!("Orphan block difficulty {} is below the chain difficulty {}", block.difficulty, self.difficulty)));
+            }
+            if !block.verify_self_hash() || !self.meets_work_requirement(&block) {
+                return Err(ChainError::InvalidBlock("Orphan block fails its own hash or proof-of-work".to_string()));
+            }
+            if self.orphans.len() >= MAX_ORPHAN_BLOCKS {
+                self.evict_stale_orphans();
+            }
+            if self.orphans.len() >= MAX_ORPHAN_BLOCKS {
+                let oldest = self.orphans.iter().min_by_key(|(_, (arrival, _))| *arrival).map(|(hash, _)| hash.clone());
+                if let Some(hash) = oldest {
+                    self.orphans.remove(&hash);
+                    Logger::info(&format!("Orphan pool is full; evicted oldest orphan block {}", hash));
+                }
+            }
+            Logger::info(&format!("Parked orphan block {} awaiting parent {}", block.index, block.previous_hash));
+            self.orphans.insert(block.hash.clone(), (self.orphan_arrivals, block));
+            self.orphan_arrivals += 1;
+            return Ok(0);
+        }
+
+        self.add_block(block)?;
+        let mut connected = 1;
+        loop {
+            let tip_hash = self.tip()?.hash.clone();
+            let Some(hash) = self.orphans.values().find(|(_, orphan)| orphan.previous_hash == tip_hash).map(|(_, orphan)| orphan.hash.clone()) else {
+                break;
+            };
+            let (_, orphan) = self.orphans.remove(&hash).expect("Orphan was just found");
+            match self.add_block(orphan) {
+                Ok(()) => connected += 1,
+                Err(e) => Logger::error(&format!("Discarded orphan block {}: {}", hash, e)),
+            }
+        }
+        self.evict_stale_orphans();
+        Ok(connected)
+    }
+
+    // Orphans at or below the tip height can no longer connect
+    fn evict_stale_orphans(&mut self) {
+        let Some(tip_index) = self.try_get_latest_block().map(|tip| tip.index) else {
+            return;
+        };
+        let before = self.orphans.len();
+        self.orphans.retain(|_, (_, orphan)| orphan.index > tip_index);
+        if self.orphans.len() < before {
+            Logger::info(&format!("Evicted {} stale orphan blocks", before - self.orphans.len()));
+        }
+    }
+
+    /// Cheaply checks headers received ahead of their blocks: they must extend the current tip one
+    /// by one, hash to their stated hash, carry the difficulty the retarget schedule expects after
+    /// the headers before them, and meet proof-of-work at it. Transactions are verified once the
+    /// full blocks arrive.
+    pub fn validate_headers(&self, headers: &[BlockHeader]) -> Result<(), ChainError> {
+        let tip = self.tip()?;
+        let (mut previous_index, mut previous_hash) = (tip.index, tip.hash.as_str());
+        // The latest retarget window, extended header by header, so the expected difficulty can be
+        // replayed as `validate_external_chain` does for full blocks
+        let window_start = self.chain.len().saturating_sub(self.difficulty_adjustment_interval as usize + 1);
+        let mut window: Vec<Block> = self.chain[window_start..].iter().map(|block| block.header().to_unfilled_block()).collect();
+        let mut expected_difficulty = self.difficulty;
+        for header in headers {
+            if header.index != previous_index + 1 || header.previous_hash != previous_hash {
+                return Err(ChainError::InvalidBlock(format!("Header {} does not link to the previous header", header.index)));
+            }
+            if !is_well_formed_hash(&header.hash) || header.hash != header.calculate_hash() {
+                return Err(ChainError::InvalidBlock(format!("Header {} hash does not match its contents", header.index)));
+            }
+            if header.difficulty != expected_difficulty {
+                return Err(ChainError::InvalidBlock(format!("Header {} has difficulty {} but {} was expected", header.index, header.difficulty, expected_difficulty)));
+            }
+            if header.bits != compact_for_difficulty(header.difficulty) {
+                return Err(ChainError::InvalidBlock(format!("Header {} bits do not encode its difficulty", header.index)));
+            }
+            let block = header.to_unfilled_block();
+            if !self.meets_work_requirement(&block) {
+                return Err(ChainError::InvalidBlock(format!("Header {} does not meet the proof-of-work target", header.index)));
+            }
+            (previous_index, previous_hash) = (header.index, header.hash.as_str());
+            window.push(block);
+            expected_difficulty = self.next_difficulty(expected_difficulty, &window);
+        }
+        Ok(())
+    }
+
+    /// Blocks parked in the orphan pool awaiting their parents, lowest index first.
+    pub fn orphan_blocks(&self) -> Vec<&Block> {
+        let mut orphans: Vec<&Block> = self.orphans.values().map(|(_, orphan)| orphan).collect();
+        orphans.sort_by_key(|block| block.index);
+        orphans
+    }
+
+    // Fails if any sender, other than the coinbase source, spends more within the block than it
+    // held before it. Funds received within the same block don't count, so two spends of the same
+    // coins can't both confirm.
+    fn check_block_balances(&self, block: &Block) -> Result<(), ChainError> {
+        if self.ledger_model == LedgerModel::Utxo {
+            return self.check_block_inputs(block);
+        }
+        check_block_spends(&self.balances, block)
+    }
+
+    // Under the UTXO model, every input in the block must spend an output that was unspent before
+    // the block and is spent only once within it
+    fn check_block_inputs(&self, block: &Block) -> Result<(), ChainError> {
+        for transaction in &block.transactions {
+            self.check_unused_txid(transaction)?;
+        }
+        let mut unspent = self.utxos.clone();
+        for transaction in block.transactions.iter().filter(|tx| tx.from != "Blockchain") {
+            let spent = self.utxo_input_total(&unspent, transaction)?;
+            check_utxo_balance(transaction, spent)?;
+            for input in &transaction.inputs {
+                unspent.remove(input);
+            }
+        }
+        Ok(())
+    }
+
+    // Outputs are keyed by the id of the transaction creating them, so one reusing a confirmed id
+    // would overwrite the original's unspent outputs
+    fn check_unused_txid(&self, transaction: &Transaction) -> Result<(), ChainError> {
+        if self.utxos.contains_transaction(&transaction.id) || self.get_transaction_by_id(&transaction.id).is_some() {
+            return Err(ChainError::InvalidTransaction(format!("Transaction id {} is already used on chain", transaction.id)));
+        }
+        Ok(())
+    }
+
+    // Sums the outputs a transaction spends, failing if any is unknown, already spent, listed
+    // twice or paid to someone other than the sender
+    fn utxo_input_total(&self, unspent: &UtxoSet, transaction: &Transaction) -> Result<Amount, ChainError> {
+        if transaction.inputs.is_empty() {
+            return Err(ChainError::InvalidTransaction(format!("Transaction {} spends no outputs", transaction.id)));
+        }
+        let mut seen = HashSet::new();
+        let mut total = Amount::ZERO;
+        for input in &transaction.inputs {
+            if !seen.insert(input) {
+                return Err(ChainError::DoubleSpend(format!("Transaction {} spends output {}:{} more than once", transaction.id, input.prev_tx_id, input.output_index)));
+            }
+            let output = unspent.get(input).ok_or_else(|| {
+                ChainError::DoubleSpend(format!("Transaction {} spends output {}:{} which is missing or already spent", transaction.id, input.prev_tx_id, input.output_index))
+            })?;
+            if output.address != transaction.from {
+                return Err(ChainError::InvalidTransaction(format!("Transaction {} spends output {}:{} which belongs to {}", transaction.id, input.prev_tx_id, input.output_index, output.address)));
+            }
+            total = total.checked_add(output.amount)
+                .ok_or_else(|| ChainError::InvalidTransaction(format!("Transaction {} spends more than an amount can hold", transaction.id)))?;
+        }
+        Ok(total)
+    }
+
+    // Validates a block against the tip and appends it, leaving balances to the caller
+    fn extend_chain(&mut self, block: Block) -> Result<(), ChainError> {
+        self.check_new_block(&block, self.tip()?, &self.balances)
+            .map_err(|e| ChainError::InvalidBlock(format!("Invalid block: {}", e)))?;
+        if block.difficulty != self.difficulty {
+            return Err(ChainError::InvalidBlock(format!("Invalid block: difficulty {} does not match the current difficulty {}", block.difficulty, self.difficulty)));
+        }
+        self.check_block_balances(&block).map_err(|e| ChainError::InvalidBlock(format!("Invalid block: {}", e)))?;
+        self.block_heights.insert(block.hash.clone(), block.index);
+        self.chain.push(block);
+        self.adjust_difficulty();
+        Ok(())
+    }
+
+    // Fails if the chain has no tip to build on or `stop` was raised before a solution was found.
+    // Also returns whether the transactions were drained from the mempool.
+    fn mine_new_block(&mut self, miner_address: &str, stop: &AtomicBool, first_seen: &HashMap<String, DateTime<Utc>>) -> Result<(Block, bool), ChainError> {
+        Logger::mining(&format!("Mining pending transactions for miner: {}", miner_address));
+        let previous = self.tip()?.clone();
+
+        let transactions = self.get_transactions_from_mempool(MAX_BLOCK_TRANSACTIONS);
+        let from_mempool = !transactions.is_empty();
+        let transactions = if from_mempool {
             transactions
+        } else {
+            self.pending_transactions.drain(..).collect()
         };
 
-        let reward_transaction = Transaction::new(
+        let reward_amount = self.subsidy_at_height(previous.index + 1)
+            + transactions.iter().map(|tx| tx.fee).sum::<Amount>();
+        let mut reward_transaction = Transaction::new(
             String::from("Blockchain"),
             miner_address.to_string(),
-            self.mining_reward,
-            0.0,
+            reward_amount,
+            Amount::ZERO,
         );
+        reward_transaction.target_chain_id = self.chain_id;
 
         let mut all_transactions = transactions;
         all_transactions.push(reward_transaction);
 
-        let new_block = Block::new(
-            self.chain.len() as u64,
+        let mut new_block = Block::new(
+            previous.index + 1,
             all_transactions,
-            self.get_latest_block().hash.clone(),
+            previous.hash.clone(),
             self.difficulty,
         );
 
-        let mineable_block = Arc::new(Mutex::new(new_block));
-        let found = Arc::new(Mutex::new(false));
-        let num_threads = num_cpus::get();
+        // A coarse or stepped-back system clock can repeat the previous timestamp even at
+        // nanosecond resolution; nudge it forward so back-to-back blocks remain valid
+        if new_block.timestamp <= previous.timestamp {
+            new_block.timestamp = previous.timestamp + chrono::Duration::nanoseconds(1);
+            new_block.hash = new_block.calculate_hash();
+        }
+
+        if self.consensus == Consensus::ProofOfStake {
+            Logger::mining(&format!("Block {} assigned to validator {}", new_block.index, miner_address));
+            return Ok((new_block, from_mempool));
+        }
 
-        let threads: Vec<_> = (0..num_threads)
-            .map(|_| {
-                let block = Arc::clone(&mineable_block);
-                let found = Arc::clone(&found);
+        let mineable_block = Mutex::new(new_block);
+        let found = AtomicBool::new(false); // Raised by the winner so the other threads give up
+        let num_threads = self.mining_threads.max(1);
+        let started = Instant::now();
+
+        thread::scope(|scope| {
+            for thread_index in 0..num_threads {
+                let (block, found) = (&mineable_block, &found);
                 let difficulty = self.difficulty;
+                let proof_of_work = self.proof_of_work.as_ref();
 
-                thread::spawn(move || {
+                scope.spawn(move || {
+                    // Thread i walks nonces i, i + n, i + 2n, ... so threads never repeat each other's work
                     let mut local_block = block.lock().unwrap().clone();
-                    while !*found.lock().unwrap() {
-                        if local_block.mine_block(difficulty) {
-                            let mut found_lock = found.lock().unwrap();
-                            if !*found_lock {
-                                *found_lock = true;
-                                let mut block_lock = block.lock().unwrap();
-                                *block_lock = local_block;
+                    local_block.nonce = thread_index as u64;
+                    local_block.hash = local_block.calculate_hash();
+                    // Mine in batches so an external stop request is noticed between them
+                    while !found.load(Ordering::Relaxed) && !stop.load(Ordering::Relaxed) {
+                        if local_block.mine_block_strided(proof_of_work, difficulty, num_threads as u64, found, MINING_BATCH_ATTEMPTS) {
+                            if !found.swap(true, Ordering::Relaxed) {
+                                *block.lock().unwrap() = local_block;
                             }
                             break;
                         }
                     }
-                })
-            })
-            .collect();
+                });
+            }
+        });
 
-        for thread in threads {
-            thread.join().unwrap();
+        if !found.load(Ordering::Relaxed) {
+            Logger::mining("Mining cancelled; returning transactions to the pool");
+            let transactions = mineable_block.into_inner().unwrap().transactions;
+            self.return_unmined(transactions, from_mempool, first_seen);
+            return Err(ChainError::Mining("Mining was cancelled".to_string()));
         }
 
-        let mined_block = mineable_block.lock().unwrap().clone();
+        let mined_block = mineable_block.into_inner().unwrap();
 
-        if self.is_valid_new_block(&mined_block, self.get_latest_block()) {
-            self.chain.push(mined_block);
-            self.update_balances();
-            self.adjust_difficulty();
-            Logger::mining("Successfully mined and added new block");
-            Ok(())
-        } else {
-            Logger::error("Failed to mine block: Invalid block");
-            Err("Invalid block".to_string())
+        // The threads stride through the nonces together, so each hashed about nonce / threads of them
+        let elapsed = started.elapsed().as_secs_f64();
+        if elapsed > 0.0 {
+            self.measured_hashrate = (mined_block.nonce / num_threads as u64 + 1) as f64 / elapsed;
+        }
+        Ok((mined_block, from_mempool))
+    }
+
+    pub fn recommended_confirmations(&self, amount: Amount) -> u64 {
+        let policy = &self.confirmation_policy;
+        let ratio = amount.base_units() as f64 / policy.value_step.base_units() as f64;
+        let extra = if ratio >= 1.0 { ratio.log2().floor() as u64 + 1 } else { 0 };
+        (policy.base_confirmations + extra).min(policy.max_confirmations)
+    }
+
+    pub fn estimated_next_block_eta(&self) -> chrono::Duration {
+        if self.measured_hashrate <= 0.0 {
+            return self.target_block_time;
         }
+        let expected_hashes = 2f64.powi(self.difficulty as i32);
+        let total_hashrate = self.measured_hashrate * self.mining_threads.max(1) as f64;
+        chrono::Duration::milliseconds((expected_hashes / total_hashrate * 1000.0).round() as i64)
     }
 
-    fn is_valid_new_block(&self, new_block: &Block, previous_block: &Block) -> bool {
+    fn is_valid_new_block(&self, new_block: &Block, previous_block: &Block, balances: &HashMap<String, Amount>) -> bool {
+        match self.check_new_block(new_block, previous_block, balances) {
+            Ok(()) => true,
+            Err(e) => {
+                Logger::error(&format!("Block {} rejected: {}", new_block.index, e));
+                false
+            }
+        }
+    }
+
+    // `balances` are those before the block, which the proof-of-stake producer is drawn from
+    fn check_new_block(&self, new_block: &Block, previous_block: &Block, balances: &HashMap<String, Amount>) -> Result<(), ChainError> {
         Logger::validation(&format!("Validating new block: {:?}", new_block));
+        if !is_well_formed_hash(&new_block.hash) {
+            return Err(ChainError::InvalidBlock("Block hash is not a 64-character hex string".to_string()));
+        }
+        if !is_well_formed_hash(&new_block.previous_hash) {
+            return Err(ChainError::InvalidBlock("Previous hash is not a 64-character hex string".to_string()));
+        }
         if new_block.index != previous_block.index + 1 {
-            return false;
+            return Err(ChainError::InvalidBlock(format!("Expected index {} but got {}", previous_block.index + 1, new_block.index)));
         }
         if new_block.previous_hash != previous_block.hash {
-            return false;
+            return Err(ChainError::InvalidBlock("Previous hash does not match the previous block".to_string()));
         }
-        if new_block.calculate_hash() != new_block.hash {
-            return false;
+        if !new_block.verify_self_hash() {
+            return Err(ChainError::InvalidBlock("Stored hash does not match block contents".to_string()));
+        }
+        if let Some(producers) = &self.authorized_producers {
+            let authorized = new_block.producer.as_ref().is_some_and(|producer| producers.contains(producer));
+            if !authorized || !new_block.has_valid_producer_signature() {
+                return Err(ChainError::InvalidBlock("Block is not signed by an authorized producer".to_string()));
+            }
+        }
+        if self.consensus == Consensus::ProofOfStake {
+            let coinbase = new_block.transactions.iter().find(|tx| tx.from == "Blockchain");
+            let validator = draw_validator(balances, &previous_block.hash);
+            if validator.is_none() || coinbase.map(|tx| &tx.to) != validator.as_ref() {
+                return Err(ChainError::InvalidBlock("Block is not produced by the selected validator".to_string()));
+            }
+        }
+        if self.checkpoints.get(&new_block.index).is_some_and(|hash| *hash != new_block.hash) {
+            return Err(ChainError::InvalidBlock("Block does not match the installed checkpoint".to_string()));
         }
         if !new_block.has_valid_transactions() {
-            return false;
+            return Err(ChainError::InvalidBlock("Block contains invalid transactions".to_string()));
+        }
+        let mut ids = HashSet::new();
+        if let Some(tx) = new_block.transactions.iter().find(|tx| !ids.insert(tx.id.as_str())) {
+            return Err(ChainError::InvalidBlock(format!("Block contains transaction {} more than once", tx.id)));
+        }
+        // Time locks are judged by the block's own timestamp, height locks by its index
+        if let Some(tx) = new_block.transactions.iter().find(|tx| !tx.is_final(new_block.index, new_block.timestamp.timestamp())) {
+            return Err(ChainError::InvalidBlock(format!("Transaction {} is locked until {}", tx.id, tx.locktime)));
         }
         let merkle_tree = MerkleTree::new(&new_block.transactions);
         if new_block.merkle_root != merkle_tree.root {
-            return false;
+            return Err(ChainError::InvalidBlock("Merkle root does not match transactions".to_string()));
         }
+        // Timestamps carry nanosecond precision, so blocks mined within the same second still order strictly
         if new_block.timestamp <= previous_block.timestamp {
-            return false;
+            return Err(ChainError::InvalidBlock("Timestamp is not after the previous block".to_string()));
         }
-        if new_block.transactions.len() > 1000 {  // Arbitrary limit, adjust as needed
-            return false;
+        if new_block.transactions.len() > MAX_BLOCK_TRANSACTIONS {
+            return Err(ChainError::InvalidBlock("Block contains too many transactions".to_string()));
         }
-        let total_value: f64 = new_block.transactions.iter().map(|tx| tx.amount).sum();
-        if total_value > 1_000_000.0 {  // Arbitrary limit, adjust as needed
-            return false;
+        // Bounding the total first keeps every later sum over the block's amounts from overflowing
+        let total_value = Amount::checked_sum(new_block.transactions.iter().flat_map(|tx| [&tx.amount, &tx.fee]));
+        if total_value.is_none_or(|total| total > Amount::from_base_units(1_000_000 * BASE_UNITS_PER_COIN)) {  // Arbitrary limit, adjust as needed
+            return Err(ChainError::InvalidBlock("Block total value is out of range".to_string()));
+        }
+        let coinbase_count = new_block.transactions.iter().filter(|tx| tx.from == "Blockchain").count();
+        if coinbase_count != 1 {
+            return Err(ChainError::InvalidBlock(format!("Block must contain exactly one coinbase transaction but has {}", coinbase_count)));
+        }
+        // A zero-value coinbase is only meaningful on fee-only chains
+        let coinbase_allows_zero = self.reward_policy == RewardPolicy::FeesOnly;
+        if new_block.transactions.iter().any(|tx| {
+            tx.from == "Blockchain" && tx.amount == Amount::ZERO && !coinbase_allows_zero
+        }) {
+            return Err(ChainError::InvalidBlock("Coinbase amount is not allowed by the reward policy".to_string()));
         }
-        // Check if the hash meets the difficulty requirement
-        let target = (1u128 << (128 - self.difficulty)) - 1;
-        let hash_value = u128::from_str_radix(&new_block.hash[..32], 16).unwrap_or(u128::MAX);
-        hash_value <= target
+        // Beyond that, it must pay exactly what the reward policy schedules for this height
+        let expected_reward = self.expected_coinbase_amount(new_block)?;
+        if let Some(tx) = new_block.transactions.iter().find(|tx| tx.from == "Blockchain" && tx.amount != expected_reward) {
+            return Err(ChainError::InvalidBlock(format!("Block coinbase pays {} but the expected reward is {}", tx.amount, expected_reward)));
+        }
+        if let Some(cap) = self.max_address_outflow_per_block {
+            let mut outflows: HashMap<&str, Amount> = HashMap::new();
+            for tx in new_block.transactions.iter().filter(|tx| tx.from != "Blockchain") {
+                let outflow = outflows.entry(tx.from.as_str()).or_default();
+                *outflow += tx.amount + tx.fee;
+                if *outflow > cap {
+                    return Err(ChainError::InvalidBlock(format!("Address {} sends more than {} in this block", tx.from, cap)));
+                }
+            }
+        }
+        let data_bytes: usize = new_block.transactions.iter().filter_map(|tx| tx.data.as_ref()).map(Vec::len).sum();
+        if data_bytes > self.max_block_data_bytes {
+            return Err(ChainError::InvalidBlock(format!("Block carries {} bytes of transaction data; at most {} are allowed", data_bytes, self.max_block_data_bytes)));
+        }
+        if new_block.bits != compact_for_difficulty(new_block.difficulty) {
+            return Err(ChainError::InvalidBlock("Block bits do not encode its difficulty".to_string()));
+        }
+        // Check if the hash meets the difficulty the block was mined at; callers check that difficulty is the expected one
+        if !self.meets_work_requirement(new_block) {
+            return Err(ChainError::InvalidBlock("Block hash does not meet the proof-of-work target".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Whether the block's hash meets the difficulty it claims; always true under proof-of-stake,
+    /// where blocks are not hashed against a target.
+    pub(crate) fn meets_work_requirement(&self, block: &Block) -> bool {
+        self.consensus == Consensus::ProofOfStake || self.proof_of_work.meets_target(block, block.difficulty)
     }
 
     pub fn is_chain_valid(&self) -> bool {
         Logger::validation("Validating entire blockchain");
+        if self.chain.first().is_some_and(|base| self.check_chain_base(base).is_err()) {
+            return false;
+        }
+        let mut expected_difficulty = self.chain.first().map_or(self.difficulty, |genesis| genesis.difficulty);
+        let mut balances = self.balances_after_first_block();
+        for i in 1..self.chain.len() {
+            let current_block = &self.chain[i];
+            let previous_block = &self.chain[i - 1];
+
+            if self.difficulty_window_pruned(i) {
+                expected_difficulty = current_block.difficulty;
+            }
+            if !self.is_valid_new_block(current_block, previous_block, &balances) || current_block.difficulty != expected_difficulty {
+                return false;
+            }
+            expected_difficulty = self.next_difficulty(expected_difficulty, &self.chain[..=i]);
+            self.replay_block_balances(current_block, &mut balances);
+        }
+        true
+    }
+
+    pub fn validate_external_chain(&self, chain: &[Block]) -> Result<(), ChainError> {
+        Logger::validation(&format!("Validating external chain of {} blocks", chain.len()));
+        let genesis = chain.first().ok_or_else(|| ChainError::InvalidBlock("External chain is empty".to_string()))?;
+        self.check_genesis(genesis)
+            .map_err(|e| ChainError::InvalidBlock(format!("External chain has an invalid genesis block: {}", e)))?;
+
+        // Balances are replayed from the chain and the funds granted here via add_balance
+        let mut balances: HashMap<String, Amount> = HashMap::new();
+        self.replay_block_balances(genesis, &mut balances);
+        let mut expected_difficulty = genesis.difficulty;
+        for i in 1..chain.len() {
+            let block = &chain[i];
+            self.check_new_block(block, &chain[i - 1], &balances)
+                .map_err(|e| ChainError::InvalidBlock(format!("Invalid block at index {}: {}", i, e)))?;
+            if block.difficulty != expected_difficulty {
+                return Err(ChainError::InvalidBlock(format!("Block {} has difficulty {} but {} was expected", i, block.difficulty, expected_difficulty)));
+            }
+            expected_difficulty = self.next_difficulty(expected_difficulty, &chain[..=i]);
+
+            check_block_spends(&balances, block).map_err(|e| ChainError::InvalidBlock(format!("Invalid block at index {}: {}", i, e)))?;
+            self.replay_block_balances(block, &mut balances);
+        }
+        Ok(())
+    }
+
+    pub fn replace_chain(&mut self, new_chain: Vec<Block>) -> Result<(), ChainError> {
+        if new_chain.len() <= self.chain.len() {
+            return Err(ChainError::InvalidBlock("Received chain is not longer than the current chain".to_string()));
+        }
+        if let Err(e) = self.validate_external_chain(&new_chain) {
+            Logger::error(&format!("Rejected external chain: {}", e));
+            return Err(e);
+        }
+
+        let old_chain = std::mem::replace(&mut self.chain, new_chain);
+        // The new chain was validated from genesis, so balances no longer start from a pruning checkpoint
+        self.prune_base = None;
+        self.reindex_blocks();
+        self.recalculate_balances();
+        self.rebuild_difficulty_state();
+        self.notify_reorg(&old_chain);
+        self.requeue_dropped_transactions(&old_chain);
+        self.sync_store();
+        self.sync_block_log();
+        Logger::info(&format!("Replaced chain with external chain of length {}", self.chain.len()));
+        Ok(())
+    }
+
+    // Transactions only the old chain confirmed go back to the mempool with their fee intact, so
+    // the miner that includes them on the new chain collects it once. The old coinbases are gone
+    // with their blocks, and pending transactions the new chain already confirms leave the mempool.
+    fn requeue_dropped_transactions(&mut self, old_chain: &[Block]) {
+        let confirmed: HashSet<String> = self.chain.iter()
+            .flat_map(|block| block.transactions.iter().map(|tx| tx.id.clone()))
+            .collect();
+        self.mempool.retain(|tx| !confirmed.contains(&tx.id));
+        self.forget_departed_transactions();
+        self.repair_mempool_accounting();
+
+        let dropped = old_chain.iter()
+            .flat_map(|block| &block.transactions)
+            .filter(|tx| tx.from != "Blockchain" && !confirmed.contains(&tx.id));
+        for transaction in dropped {
+            if let Err(e) = self.add_to_mempool(transaction.clone()) {
+                Logger::info(&format!("Dropped reorged transaction {}: {}", transaction.id, e));
+            }
+        }
+    }
+
+    // Tells subscribers which of their transactions the new chain dropped, and where the ones it
+    // newly includes now sit
+    fn notify_reorg(&mut self, old_chain: &[Block]) {
+        let confirmed_in = |chain: &[Block]| -> HashMap<String, (Transaction, u64)> {
+            chain.iter().enumerate()
+                .flat_map(|(position, block)| {
+                    let depth = (chain.len() - position) as u64;
+                    block.transactions.iter().map(move |tx| (tx.id.clone(), (tx.clone(), depth)))
+                })
+                .filter(|(_, (tx, _))| self.subscriptions.is_watching(tx))
+                .collect()
+        };
+        let before = confirmed_in(old_chain);
+        let after = confirmed_in(&self.chain);
+
+        for (id, (transaction, _)) in &before {
+            if !after.contains_key(id) {
+                self.subscriptions.notify(transaction, TransactionStatus::Reorged);
+            }
+        }
+        for (id, (transaction, depth)) in &after {
+            if !before.contains_key(id) {
+                self.subscriptions.notify(transaction, TransactionStatus::Confirmed(*depth));
+            }
+        }
+    }
+
+    /// Subscribes to status changes of every transaction sent from or to `address`: `Pending` when
+    /// it enters the mempool, `Confirmed` when a block includes it, and `Reorged` when a chain
+    /// replacement drops that block again. The subscription ends when the receiver is dropped.
+    pub fn subscribe_address(&mut self, address: &str) -> Receiver<TransactionUpdate> {
+        self.subscriptions.subscribe(address)
+    }
+
+    /// Subscribes to every block added to the chain and every transaction accepted into the
+    /// mempool. Each subscriber receives its own copy of each event.
+    pub fn subscribe_events(&mut self) -> Receiver<ChainEvent> {
+        self.events.subscribe()
+    }
+
+    /// How deep a confirmed transaction sits: 1 in the tip block, one more for each block on
+    /// top. `None` while it is pending or unknown.
+    pub fn confirmation_depth(&self, txid: &str) -> Option<u64> {
+        let (block_index, _) = self.get_transaction_by_id(txid)?;
+        Some(self.tip().ok()?.index - block_index + 1)
+    }
+
+    /// Blocks until transaction `txid` is at least `confirmations` deep in the shared chain, and
+    /// returns the depth it reached. Takes the lock rather than `&self` so other threads can keep
+    /// adding blocks: it is held only to check the depth each time `subscribe_events` reports one.
+    pub fn wait_for_confirmation(blockchain: &Mutex<Blockchain>, txid: &str, confirmations: u64, timeout: std::time::Duration) -> Result<u64, WaitError> {
+        let deadline = Instant::now() + timeout;
+        // Subscribing under the same lock as the first check, so no block slips in between
+        let (mut depth, events) = {
+            let mut chain = blockchain.lock().unwrap();
+            (chain.confirmation_depth(txid).unwrap_or(0), chain.subscribe_events())
+        };
+        while depth < confirmations {
+            match events.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(ChainEvent::NewBlock(_)) => depth = blockchain.lock().unwrap().confirmation_depth(txid).unwrap_or(0),
+                Ok(ChainEvent::NewTransaction(_)) => {}
+                Err(_) => return Err(WaitError::Timeout(depth)),
+            }
+        }
+        Ok(depth)
+    }
+
+    /// The block subsidy at `height` under the halving schedule: `mining_reward` halved once per
+    /// full `halving_interval` blocks, reaching zero after 64 halvings.
+    pub fn reward_at_height(&self, height: u64) -> Amount {
+        let halvings = match self.halving_interval {
+            Some(interval) if interval > 0 => height / interval,
+            _ => 0,
+        };
+        if halvings >= 64 {
+            return Amount::ZERO;
+        }
+        // Halving drops any odd base unit, as integer division does
+        Amount::from_base_units(self.mining_reward.base_units() >> halvings)
+    }
+
+    // Newly minted coins in the coinbase at `height`, before fees
+    fn subsidy_at_height(&self, height: u64) -> Amount {
+        match self.reward_policy {
+            RewardPolicy::BlockSubsidy => self.reward_at_height(height),
+            RewardPolicy::FeesOnly => Amount::ZERO,
+        }
+    }
+
+    // The coinbase pays the subsidy plus every fee collected from the block's transactions
+    fn expected_coinbase_amount(&self, block: &Block) -> Result<Amount, ChainError> {
+        Amount::checked_sum(block.transactions.iter().filter(|tx| tx.from != "Blockchain").map(|tx| &tx.fee))
+            .and_then(|fees| self.subsidy_at_height(block.index).checked_add(fees))
+            .ok_or_else(|| ChainError::InvalidBlock("Block fees overflow an amount".to_string()))
+    }
+
+    // The sender pays the fee, which reaches the miner through the coinbase. Coinbases mint their
+    // amount rather than debiting anyone, and blocks reaching here have passed the balance checks,
+    // so a sender's debit never needs to go below zero.
+    fn apply_transaction(&self, balances: &mut HashMap<String, Amount>, transaction: &Transaction) {
+        if transaction.from != "Blockchain" {
+            let balance = balances.entry(transaction.from.clone()).or_default();
+            *balance = balance.saturating_sub(transaction.amount + transaction.fee);
+        }
+        for payment in transaction.payments() {
+            *balances.entry(payment.address).or_default() += payment.amount;
+        }
+    }
+
+    // Account balances are tracked under both models, but under the UTXO model `get_balance`
+    // reads the output set instead
+    fn apply_utxos(&self, utxos: &mut UtxoSet, transaction: &Transaction) {
+        if self.ledger_model == LedgerModel::Utxo {
+            utxos.apply(transaction);
+        }
+    }
+
+    // Grants the `add_balance` funds recorded at `height` again, as replays do right after that block
+    fn apply_credits(&self, height: u64, balances: &mut HashMap<String, Amount>, mut utxos: Option<&mut UtxoSet>) {
+        for (_, input, output) in self.credits.iter().filter(|(granted_at, _, _)| *granted_at == height) {
+            *balances.entry(output.address.clone()).or_default() += output.amount;
+            if let Some(utxos) = utxos.as_deref_mut().filter(|_| self.ledger_model == LedgerModel::Utxo) {
+                utxos.insert(input.clone(), output.clone());
+            }
+        }
+    }
+
+    // Replays a block's effect on account balances during validation
+    fn replay_block_balances(&self, block: &Block, balances: &mut HashMap<String, Amount>) {
+        for transaction in &block.transactions {
+            self.apply_transaction(balances, transaction);
+        }
+        self.apply_credits(block.index, balances, None);
+    }
+
+    // The balances the second block of the local chain was built on
+    fn balances_after_first_block(&self) -> HashMap<String, Amount> {
+        let mut balances = self.prune_base.as_ref().map(|base| base.balances.clone()).unwrap_or_default();
+        if let Some(first) = self.chain.first() {
+            self.replay_block_balances(first, &mut balances);
+        }
+        balances
+    }
+
+    // Applies only the newly appended tip block; `recalculate_balances` re-walks the whole chain
+    fn update_balances(&mut self) {
+        Logger::info("Updating balances");
+        let mut balances = std::mem::take(&mut self.balances);
+        let mut nonces = std::mem::take(&mut self.account_nonces);
+        let mut utxos = std::mem::take(&mut self.utxos);
+        for transaction in self.try_get_latest_block().map(|tip| tip.transactions.as_slice()).unwrap_or_default() {
+            self.apply_transaction(&mut balances, transaction);
+            record_nonce(&mut nonces, transaction);
+            self.apply_utxos(&mut utxos, transaction);
+        }
+        self.balances = balances;
+        self.account_nonces = nonces;
+        self.utxos = utxos;
+    }
+
+    /// Drops all but the most recent `keep_last` blocks (at least one is kept). The balances of the
+    /// dropped blocks are kept as a checkpoint, so balances and later validation of the retained
+    /// blocks are unaffected. Returns the number of blocks dropped.
+    pub fn prune_to(&mut self, keep_last: usize) -> usize {
+        let dropped = self.chain.len().saturating_sub(keep_last.max(1));
+        if dropped == 0 {
+            return 0;
+        }
+
+        let (mut balances, mut nonces, mut utxos) = self.prune_base.take()
+            .map(|base| (base.balances, base.nonces, base.utxos))
+            .unwrap_or_default();
+        for block in &self.chain[..dropped] {
+            for transaction in &block.transactions {
+                self.apply_transaction(&mut balances, transaction);
+                record_nonce(&mut nonces, transaction);
+                self.apply_utxos(&mut utxos, transaction);
+            }
+            self.apply_credits(block.index, &mut balances, Some(&mut utxos));
+        }
+        self.chain.drain(..dropped);
+        self.reindex_blocks();
+
+        let base = &self.chain[0];
+        self.checkpoints.insert(base.index, base.hash.clone());
+        self.prune_base = Some(PruneBase { height: base.index, hash: base.hash.clone(), balances, nonces, utxos });
+        self.recalculate_balances();
+        self.sync_store();
+        self.sync_block_log();
+        Logger::info(&format!("Pruned {} blocks; chain now starts at height {}", dropped, self.chain[0].index));
+        dropped
+    }
+
+    fn position_of(&self, index: u64) -> Option<usize> {
+        let position = index.checked_sub(self.chain.first()?.index)? as usize;
+        (position < self.chain.len()).then_some(position)
+    }
+
+    pub fn get_balance(&self, address: &str) -> Amount {
+        if self.ledger_model == LedgerModel::Utxo {
+            return self.utxos.balance_of(address);
+        }
+        self.balances.get(address).copied().unwrap_or_default()
+    }
+
+    /// The part of an address's balance it may spend now: coinbase rewards from the most recent
+    /// `coinbase_maturity` blocks are held back, as a reorg could still take them away.
+    pub fn spendable_balance(&self, address: &str) -> Amount {
+        let immature: Amount = self.chain.iter()
+            .rev()
+            .take(self.coinbase_maturity as usize)
+            .flat_map(|block| &block.transactions)
+            .filter(|tx| tx.from == "Blockchain" && tx.to == address)
+            .map(|tx| tx.amount)
+            .sum();
+        self.get_balance(address).saturating_sub(immature)
+    }
+
+    fn check_spendable(&self, address: &str, amount: Amount) -> Result<(), ChainError> {
+        if self.get_balance(address) < amount {
+            return Err(ChainError::InsufficientBalance("Insufficient balance".to_string()));
+        }
+        if self.spendable_balance(address) < amount {
+            return Err(ChainError::InsufficientBalance(format!("Insufficient balance: coinbase rewards need {} confirmations before they can be spent", self.coinbase_maturity)));
+        }
+        Ok(())
+    }
+
+    /// True if the address has ever held a balance on this chain.
+    pub fn is_known_address(&self, address: &str) -> bool {
+        self.balances.contains_key(address)
+    }
+
+    /// Looks up several balances at once; unknown addresses map to 0.
+    pub fn get_balances(&self, addresses: &[String]) -> HashMap<String, Amount> {
+        addresses.iter().map(|address| (address.clone(), self.get_balance(address))).collect()
+    }
+
+    fn adjust_difficulty(&mut self) {
+        if let [.., previous, last] = self.chain.as_slice() {
+            if previous.index != 0 {
+                self.block_time_window.push(last.timestamp - previous.timestamp);
+            }
+        }
+        let excess = self.block_time_window.len().saturating_sub(BLOCK_TIME_WINDOW_LEN);
+        self.block_time_window.drain(..excess);
+
+        if let Some(difficulty) = self.retarget(self.difficulty, &self.chain) {
+            Logger::info(&format!("Difficulty adjusted from {} to {}", self.difficulty, difficulty));
+            self.difficulty = difficulty;
+        }
+    }
+
+    /// Computes the difficulty that follows `difficulty` once the last block of `chain` has been
+    /// appended. The difficulty only changes at the end of each `difficulty_adjustment_interval`
+    /// blocks, to `difficulty * expected_time / actual_time` over that window, clamped to between
+    /// a quarter and four times its previous value. Returns `None` at every other height.
+    fn retarget(&self, difficulty: u32, chain: &[Block]) -> Option<u32> {
+        let interval = self.difficulty_adjustment_interval;
+        let last = chain.last()?;
+        // The genesis timestamp is fixed rather than when mining started, so the first window,
+        // which would be measured from it, is skipped
+        if interval == 0 || last.index <= interval || last.index % interval != 0 {
+            return None;
+        }
+
+        // Windows are located by height so they line up on pruned chains too
+        let window_start = chain.get((last.index - interval).checked_sub(chain[0].index)? as usize)?;
+        let expected_ms = self.target_block_time.num_milliseconds().checked_mul(interval.try_into().ok()?)?;
+        // A chain without proof-of-work, or a target too short to measure, keeps its difficulty
+        if difficulty == 0 || expected_ms <= 0 || self.consensus == Consensus::ProofOfStake {
+            return None;
+        }
+
+        let actual_ms = (last.timestamp - window_start.timestamp).num_milliseconds().max(1);
+        let current = difficulty as f64;
+        let new_difficulty = (current * expected_ms as f64 / actual_ms as f64).clamp(current / 4.0, current * 4.0);
+        // Rounding can never disable proof-of-work on a chain that has it
+        Some((new_difficulty.round() as u32).max(1))
+    }
+
+    /// The difficulty expected of the block after the tip of `chain`, given the difficulty the tip
+    /// was mined at. Unchanged except at the end of an adjustment window.
+    pub fn next_difficulty(&self, difficulty: u32, chain: &[Block]) -> u32 {
+        self.retarget(difficulty, chain).unwrap_or(difficulty)
+    }
+
+    /// Replays difficulty adjustments over the whole chain, e.g. after loading it from disk,
+    /// so mining continues exactly as if the node had never stopped.
+    fn rebuild_difficulty_state(&mut self) {
+        let Some(mut difficulty) = self.chain.first().map(|genesis| genesis.difficulty) else {
+            return;
+        };
+        for end in 2..=self.chain.len() {
+            difficulty = self.next_difficulty(difficulty, &self.chain[..end]);
+        }
+        self.difficulty = difficulty;
+
+        let block_times: Vec<chrono::Duration> = self.chain
+            .windows(2)
+            .filter(|pair| pair[0].index != 0)
+            .map(|pair| pair[1].timestamp - pair[0].timestamp)
+            .collect();
+        let excess = block_times.len().saturating_sub(BLOCK_TIME_WINDOW_LEN);
+        self.block_time_window = block_times[excess..].to_vec();
+    }
+
+    pub fn validate_chain(&self) -> bool {
+        if let Some(Err(e)) = self.chain.first().map(|base| self.check_chain_base(base)) {
+            Logger::error(&format!("Invalid first block: {}", e));
+            return false;
+        }
+        let mut expected_difficulty = self.chain.first().map_or(self.difficulty, |genesis| genesis.difficulty);
+        let mut balances = self.balances_after_first_block();
         for i in 1..self.chain.len() {
             let current_block = &self.chain[i];
             let previous_block = &self.chain[i - 1];
 
-            if !self.is_valid_new_block(current_block, previous_block) {
+            Logger::validation(&format!("Validating block {} of {}", i, self.chain.len() - 1));
+
+            if !self.is_valid_new_block(current_block, previous_block, &balances) {
+                Logger::error(&format!("Invalid block found at index {}", i));
                 return false;
             }
+
+            if self.difficulty_window_pruned(i) {
+                expected_difficulty = current_block.difficulty;
+            }
+            if current_block.difficulty != expected_difficulty {
+                Logger::error(&format!("Block {} has difficulty {} but {} was expected", i, current_block.difficulty, expected_difficulty));
+                return false;
+            }
+            expected_difficulty = self.next_difficulty(expected_difficulty, &self.chain[..=i]);
+
+            // Validate all transactions in the block
+            for (j, transaction) in current_block.transactions.iter().enumerate() {
+                if !transaction.is_valid() {
+                    Logger::error(&format!("Invalid transaction found in block {} at index {}", i, j));
+                    return false;
+                }
+            }
+            self.replay_block_balances(current_block, &mut balances);
         }
+        Logger::validation("Blockchain is valid");
         true
     }
 
-    fn update_balances(&mut self) {
-        Logger::info("Updating balances");
-        for block in &self.chain {
-            for transaction in &block.transactions {
-                *self.balances.entry(transaction.from.clone()).or_insert(0.0) -= transaction.amount;
-                *self.balances.entry(transaction.to.clone()).or_insert(0.0) += transaction.amount;
+    pub fn recalculate_balances(&mut self) {
+        let (mut balances, mut nonces, mut utxos) = self.prune_base.as_ref()
+            .map(|base| (base.balances.clone(), base.nonces.clone(), base.utxos.clone()))
+            .unwrap_or_default();
+        for block in &self.chain {
+            for transaction in &block.transactions {
+                self.apply_transaction(&mut balances, transaction);
+                record_nonce(&mut nonces, transaction);
+                self.apply_utxos(&mut utxos, transaction);
+            }
+            self.apply_credits(block.index, &mut balances, Some(&mut utxos));
+        }
+        self.balances = balances;
+        self.account_nonces = nonces;
+        self.utxos = utxos;
+    }
+
+    /// The nonce the next transaction from `address` must carry: one past the highest nonce it
+    /// has confirmed or waiting in the mempool, or 0 for an address that has never sent.
+    pub fn next_nonce(&self, address: &str) -> u64 {
+        let confirmed = self.account_nonces.get(address).copied().unwrap_or(0);
+        self.mempool.iter()
+            .filter(|tx| tx.from == address)
+            .map(|tx| tx.nonce + 1)
+            .fold(confirmed, u64::max)
+    }
+
+    /// The unspent outputs paying `address`, for building UTXO transactions. Empty unless the
+    /// chain uses `LedgerModel::Utxo`.
+    pub fn unspent_outputs(&self, address: &str) -> Vec<(TxInput, TxOutput)> {
+        self.utxos.unspent_for(address)
+    }
+
+    /// Captures the current balances at the tip height.
+    pub fn snapshot_balances(&self) -> BalanceSnapshot {
+        BalanceSnapshot {
+            height: self.try_get_latest_block().map_or(0, |tip| tip.index),
+            balances: self.balances.clone(),
+        }
+    }
+
+    /// Restores balances from a snapshot and replays only the blocks above its height, avoiding a
+    /// full `recalculate_balances` walk. The snapshot must have been taken on this chain.
+    pub fn restore_from_snapshot(&mut self, snapshot: BalanceSnapshot) -> Result<(), ChainError> {
+        let tip = self.tip()?.index;
+        if snapshot.height > tip {
+            return Err(ChainError::InvalidCheckpoint(format!("Snapshot height {} is above the chain tip {}", snapshot.height, tip)));
+        }
+        let start = self.position_of(snapshot.height + 1).unwrap_or(self.chain.len());
+        if start == 0 {
+            return Err(ChainError::InvalidCheckpoint(format!("Snapshot height {} predates the retained chain", snapshot.height)));
+        }
+
+        let mut balances = snapshot.balances;
+        for block in &self.chain[start..] {
+            for transaction in &block.transactions {
+                self.apply_transaction(&mut balances, transaction);
+            }
+        }
+        self.balances = balances;
+        Logger::info(&format!("Restored balances from snapshot at height {}, replayed {} blocks", snapshot.height, self.chain.len() - start));
+        Ok(())
+    }
+
+    pub fn import_signed_checkpoints(&mut self, data: &[u8], authority_pubkey: &[u8]) -> Result<usize, ChainError> {
+        let signed: SignedCheckpoints = serde_json::from_slice(data)
+            .map_err(|e| ChainError::InvalidCheckpoint(format!("Malformed checkpoint data: {}", e)))?;
+
+        if !signed.verify(authority_pubkey) {
+            Logger::error("Rejected checkpoints with an invalid authority signature");
+            return Err(ChainError::InvalidCheckpoint("Invalid checkpoint signature".to_string()));
+        }
+
+        let count = signed.checkpoints.len();
+        for checkpoint in signed.checkpoints {
+            self.checkpoints.insert(checkpoint.height, checkpoint.hash);
+        }
+        Logger::info(&format!("Imported {} signed checkpoints", count));
+        Ok(count)
+    }
+
+    pub fn all_addresses(&self) -> HashSet<String> {
+        // The balance map still knows addresses whose blocks are no longer held in `chain`
+        self.chain
+            .iter()
+            .flat_map(|block| &block.transactions)
+            .flat_map(|tx| tx.payments().into_iter().map(|payment| payment.address).chain([tx.from.clone()]))
+            .chain(self.balances.keys().cloned())
+            .filter(|address| address != "Blockchain")
+            .collect()
+    }
+
+    pub fn get_transactions_for_address(&self, address: &str) -> Vec<&Transaction> {
+        self.chain
+            .iter()
+            .flat_map(|block| &block.transactions)
+            .filter(|tx| tx.from == address || tx.pays(address))
+            .collect()
+    }
+
+    /// Finds a confirmed transaction by id, returning the index of the block containing it. The
+    /// lookup table is built on first use and rebuilt whenever the tip has changed since.
+    pub fn get_transaction_by_id(&self, id: &str) -> Option<(u64, &Transaction)> {
+        let tip_hash = &self.chain.last()?.hash;
+        let (block_index, position) = {
+            let mut index = self.transaction_index.lock().unwrap();
+            if index.as_ref().is_none_or(|index| index.tip_hash != *tip_hash) {
+                let positions = self.chain.iter()
+                    .flat_map(|block| block.transactions.iter().enumerate().map(|(i, tx)| (tx.id.clone(), (block.index, i))))
+                    .collect();
+                *index = Some(TransactionIndex { tip_hash: tip_hash.clone(), positions });
             }
-        }
+            *index.as_ref()?.positions.get(id)?
+        };
+        let transaction = self.chain.get(self.position_of(block_index)?)?.transactions.get(position)?;
+        Some((block_index, transaction))
     }
 
-    pub fn get_balance(&self, address: &str) -> f64 {
-        *self.balances.get(address).unwrap_or(&0.0)
+    /// Everything a light client needs to check that a confirmed transaction is in the chain: the
+    /// header of its block and a Merkle proof against that header's `merkle_root`.
+    pub fn transaction_proof(&self, txid: &str) -> Option<(BlockHeader, MerkleProof)> {
+        let (block_index, transaction) = self.get_transaction_by_id(txid)?;
+        let block = self.get_block_by_index(block_index)?;
+        let proof = MerkleTree::new(&block.transactions).get_proof(transaction)?;
+        Some((block.header(), proof))
     }
 
-    fn adjust_difficulty(&mut self) {
-        Logger::info(&format!("Adjusting difficulty. Current difficulty: {}", self.difficulty));
-        if self.chain.len() < self.difficulty_adjustment_interval as usize {
-            return;
-        }
-
-        let last_adjusted_block = &self.chain[self.chain.len() - self.difficulty_adjustment_interval as usize];
-        let expected_time = self.target_block_time * self.difficulty_adjustment_interval.try_into().unwrap();
-        let actual_time = self.get_latest_block().timestamp - last_adjusted_block.timestamp;
-
-        // Calculate the average block time for the last difficulty adjustment interval
-        let avg_block_time = actual_time / self.difficulty_adjustment_interval as i32;
-
-        // Calculate the ratio of actual time to expected time
-        let time_ratio = actual_time.num_seconds() as f64 / expected_time.num_seconds() as f64;
-
-        // Adjust difficulty based on the time ratio, but limit the change to 25% in either direction
-        let adjustment_factor = time_ratio.max(0.75).min(1.25);
-        let new_difficulty = (self.difficulty as f64 / adjustment_factor).max(1.0);
+    pub fn recent_fee_rates(&self, n: usize) -> f64 {
+        let start = self.chain.len().saturating_sub(n);
+        self.average_fee_rate(&self.chain[start..])
+    }
 
-        // Smooth out difficulty changes by averaging with the previous difficulty
-        self.difficulty = ((self.difficulty as f64 + new_difficulty) / 2.0).round() as u32;
+    fn average_fee_rate(&self, blocks: &[Block]) -> f64 {
+        let fee_rates: Vec<f64> = blocks
+            .iter()
+            .flat_map(|block| &block.transactions)
+            .filter(|tx| tx.from != "Blockchain")
+            .map(|tx| tx.fee.to_coins() / self.calculate_transaction_size(tx) as f64)
+            .collect();
 
-        // Update the block time window
-        self.block_time_window.push(avg_block_time);
-        if self.block_time_window.len() > 10 {
-            self.block_time_window.remove(0);
+        if fee_rates.is_empty() {
+            return 0.0;
         }
-
-        Logger::info(&format!("Difficulty adjusted to: {}", self.difficulty));
+        fee_rates.iter().sum::<f64>() / fee_rates.len() as f64
     }
 
-    pub fn validate_chain(&self) -> bool {
-        for i in 1..self.chain.len() {
-            let current_block = &self.chain[i];
-            let previous_block = &self.chain[i - 1];
-
-            Logger::validation(&format!("Validating block {} of {}", i, self.chain.len() - 1));
+    /// Aggregates the figures a health check needs. Validates the whole chain, so it is not free.
+    /// An empty chain reports height 0, an empty tip hash and the Unix epoch as its last block time.
+    pub fn status(&self) -> NodeStatus {
+        let tip = self.try_get_latest_block();
+        NodeStatus {
+            height: tip.map_or(0, |tip| tip.index),
+            tip_hash: tip.map(|tip| tip.hash.clone()).unwrap_or_default(),
+            difficulty: self.difficulty,
+            mempool_transactions: self.mempool.len(),
+            mempool_bytes: self.mempool_size_bytes,
+            last_block_time: tip.map_or(DateTime::UNIX_EPOCH, |tip| tip.timestamp),
+            chain_valid: self.is_chain_valid(),
+            peer_count: None,
+        }
+    }
 
-            if !self.is_valid_new_block(current_block, previous_block) {
-                Logger::error(&format!("Invalid block found at index {}", i));
-                return false;
+    pub fn chain_stats(&self, start: u64, end: u64) -> ChainStats {
+        let blocks: &[Block] = match (self.chain.first(), self.chain.last()) {
+            (Some(first), Some(tip)) => {
+                let (start, end) = (start.max(first.index), end.min(tip.index));
+                match (self.position_of(start), self.position_of(end)) {
+                    (Some(start), Some(end)) if start <= end => &self.chain[start..=end],
+                    _ => &[],
+                }
             }
+            _ => &[],
+        };
 
-            // Validate all transactions in the block
-            for (j, transaction) in current_block.transactions.iter().enumerate() {
-                if !transaction.is_valid() {
-                    Logger::error(&format!("Invalid transaction found in block {} at index {}", i, j));
-                    return false;
-                }
+        let average_block_time = match (blocks.first(), blocks.last()) {
+            (Some(first), Some(last)) if blocks.len() > 1 => {
+                (last.timestamp - first.timestamp) / (blocks.len() - 1) as i32
             }
-        }
-        Logger::validation("Blockchain is valid");
-        true
+            _ => chrono::Duration::zero(),
+        };
+        self.stats_over(blocks, average_block_time)
     }
 
-    pub fn recalculate_balances(&mut self) {
-        self.balances.clear();
-        for block in &self.chain {
-            for transaction in &block.transactions {
-                *self.balances.entry(transaction.from.clone()).or_insert(0.0) -= transaction.amount;
-                *self.balances.entry(transaction.to.clone()).or_insert(0.0) += transaction.amount;
-            }
+    /// Whole-chain statistics for a dashboard. The average block time is taken over the most recent
+    /// blocks rather than the whole chain.
+    pub fn stats(&self) -> ChainStats {
+        let average_block_time = self.window_average_block_time().unwrap_or_else(chrono::Duration::zero);
+        self.stats_over(&self.chain, average_block_time)
+    }
+
+    // Mean of the block times in the recent retargeting window, if any blocks have been timed
+    fn window_average_block_time(&self) -> Option<chrono::Duration> {
+        match self.block_time_window.len() {
+            0 => None,
+            len => Some(self.block_time_window.iter().fold(chrono::Duration::zero(), |sum, time| sum + *time) / len as i32),
         }
     }
 
-    pub fn get_transactions_for_address(&self, address: &str) -> Vec<&Transaction> {
-        self.chain
+    fn stats_over(&self, blocks: &[Block], average_block_time: chrono::Duration) -> ChainStats {
+        let (coinbases, transactions): (Vec<&Transaction>, Vec<&Transaction>) = blocks
             .iter()
             .flat_map(|block| &block.transactions)
-            .filter(|tx| tx.from == address || tx.to == address)
-            .collect()
+            .partition(|tx| tx.from == "Blockchain");
+
+        // Coinbases also pass on the fees they collect, which were not newly issued
+        let total_fees: Amount = transactions.iter().map(|tx| tx.fee).sum();
+        ChainStats {
+            block_count: blocks.len(),
+            transaction_count: transactions.len(),
+            total_volume: transactions.iter().map(|tx| tx.amount).sum(),
+            total_fees,
+            total_issued: coinbases.iter().map(|tx| tx.amount).sum::<Amount>().saturating_sub(total_fees),
+            average_fee_rate: self.average_fee_rate(blocks),
+            average_block_time,
+            difficulty: self.difficulty,
+            mempool_transactions: self.mempool.len(),
+            mempool_bytes: self.mempool_size_bytes,
+        }
+    }
+
+    /// Estimates how long mining will take to clear the current mempool, assuming no new arrivals:
+    /// the number of blocks needed to fit it within `max_block_size_bytes` and the per-block
+    /// transaction limit, at the recent average block time (or `target_block_time` before any
+    /// blocks have been timed).
+    pub fn mempool_clearance_estimate(&self) -> chrono::Duration {
+        let by_bytes = self.mempool_size_bytes.div_ceil(self.max_block_size_bytes.max(1));
+        let by_count = self.mempool.len().div_ceil(MAX_BLOCK_TRANSACTIONS);
+        let block_time = self.window_average_block_time().unwrap_or(self.target_block_time);
+        block_time * by_bytes.max(by_count) as i32
     }
 
-    pub fn add_to_mempool(&mut self, transaction: Transaction) -> Result<(), String> {
+    /// Flags transactions still waiting in the mempool that were admitted before the block at
+    /// `index` was created and pay a higher fee rate than the cheapest transaction it included.
+    pub fn analyze_block_fee_efficiency(&self, index: u64) -> Option<FeeEfficiencyReport> {
+        let block = &self.chain[self.position_of(index)?];
+        let fee_rate = |tx: &Transaction| tx.fee.to_coins() / self.calculate_transaction_size(tx) as f64;
+
+        let lowest_included_fee_rate = block.transactions
+            .iter()
+            .filter(|tx| tx.from != "Blockchain")
+            .map(fee_rate)
+            .min_by(|a, b| a.total_cmp(b));
+
+        let skipped_transactions = match lowest_included_fee_rate {
+            Some(lowest) => self.mempool
+                .iter()
+                .filter(|tx| self.first_seen(&tx.id).is_some_and(|seen| seen <= block.timestamp))
+                .filter(|tx| fee_rate(tx) > lowest)
+                .map(|tx| tx.id.clone())
+                .collect(),
+            None => Vec::new(),
+        };
+
+        Some(FeeEfficiencyReport {
+            block_index: block.index,
+            lowest_included_fee_rate,
+            skipped_transactions,
+        })
+    }
+
+    pub fn add_to_mempool(&mut self, transaction: Transaction) -> Result<(), ChainError> {
+        if transaction.amount == Amount::ZERO {
+            return Err(ChainError::InvalidTransaction("Transaction amount must be greater than zero".to_string()));
+        }
+        if transaction.is_self_send() {
+            return Err(ChainError::InvalidTransaction("Transaction sends to its own address".to_string()));
+        }
         if !transaction.is_valid() {
-            return Err("Invalid transaction".to_string());
+            return Err(ChainError::InvalidTransaction("Invalid transaction".to_string()));
         }
-
-        let sender_balance = self.get_balance(&transaction.from);
-        if sender_balance < transaction.amount + transaction.fee {
-            return Err("Insufficient balance".to_string());
+        self.check_target_chain(&transaction)?;
+        if let Some(data) = transaction.data.as_ref().filter(|data| data.len() > MAX_TRANSACTION_DATA_LEN) {
+            return Err(ChainError::InvalidTransaction(format!("Transaction data is {} bytes; at most {} are allowed", data.len(), MAX_TRANSACTION_DATA_LEN)));
+        }
+        if self.ledger_model == LedgerModel::Utxo {
+            self.check_mempool_inputs(&transaction)?;
         }
 
-        // Check for double-spend
-        if self.mempool.iter().any(|tx| tx.from == transaction.from && tx.amount + tx.fee > sender_balance - (transaction.amount + transaction.fee)) {
-            return Err("Potential double-spend detected".to_string());
+        self.check_spendable(&transaction.from, transaction.amount + transaction.fee)?;
+        let sender_balance = self.spendable_balance(&transaction.from);
+
+        // Check for double-spend: the sender's pending spends together must fit its balance, as they
+        // must within a block. Under the UTXO model conflicting inputs are checked instead.
+        if self.ledger_model == LedgerModel::Account {
+            let pending = Amount::checked_sum(self.mempool.iter().filter(|tx| tx.from == transaction.from).flat_map(|tx| [&tx.amount, &tx.fee]));
+            let total = pending.and_then(|pending| pending.checked_add(transaction.amount + transaction.fee));
+            if total.is_none_or(|total| total > sender_balance) {
+                return Err(ChainError::DoubleSpend("Potential double-spend detected".to_string()));
+            }
         }
 
         // Check if the transaction is already in the mempool
         if self.mempool.iter().any(|tx| tx.id == transaction.id) {
-            return Err("Transaction already in mempool".to_string());
+            return Err(ChainError::InvalidTransaction("Transaction already in mempool".to_string()));
+        }
+
+        // Nonces must be used in order, so a signed transaction can't be replayed or skipped ahead.
+        // Spent outputs already rule out replays under the UTXO model.
+        if self.ledger_model == LedgerModel::Account {
+            let expected_nonce = self.next_nonce(&transaction.from);
+            if transaction.nonce < expected_nonce {
+                return Err(ChainError::InvalidTransaction(format!("Transaction nonce {} was already used; expected {}", transaction.nonce, expected_nonce)));
+            }
+            if transaction.nonce > expected_nonce {
+                return Err(ChainError::InvalidTransaction(format!("Transaction nonce {} leaves a gap; expected {}", transaction.nonce, expected_nonce)));
+            }
         }
 
         // Check expiration
-        let current_time = chrono::Utc::now().timestamp();
-        if transaction.expiration < current_time {
-            return Err("Transaction has expired".to_string());
+        let now = self.clock.now();
+        if transaction.expiration < now.timestamp() {
+            return Err(ChainError::Expired("Transaction has expired".to_string()));
+        }
+        // Only transactions the next block could include are admitted
+        if !transaction.is_final(self.tip()?.index + 1, now.timestamp()) {
+            return Err(ChainError::InvalidTransaction(format!("Transaction is locked until {}", transaction.locktime)));
+        }
+        if let Some(horizon) = self.max_expiration_horizon {
+            if transaction.expiration > (now + horizon).timestamp() {
+                return Err(ChainError::InvalidTransaction("Transaction expiration is too far in the future".to_string()));
+            }
         }
 
         // Calculate transaction size (simplified, you may want to implement a more accurate size calculation)
         let tx_size = self.calculate_transaction_size(&transaction);
-        let fee_rate = transaction.fee / tx_size as f64;
+        let fee_rate = transaction.fee.to_coins() / tx_size as f64;
 
         if fee_rate < MIN_FEE_RATE {
-            return Err("Transaction fee rate is too low".to_string());
+            return Err(ChainError::FeeTooLow("Transaction fee rate is too low".to_string()));
         }
 
         // Check if adding this transaction would exceed the mempool size limit
-        if self.mempool_size_bytes + tx_size > self.max_mempool_size_bytes {
+        if tx_size > self.max_mempool_size_bytes {
+            return Err(ChainError::MempoolFull(format!("Transaction of {} bytes exceeds the mempool size limit", tx_size)));
+        }
+        // A full mempool only makes room for a transaction paying more than the cheapest it holds
+        if self.mempool.len() >= self.max_mempool_size {
+            let lowest_fee_rate = self.mempool.last()
+                .map_or(f64::INFINITY, |tx| tx.fee.to_coins() / self.calculate_transaction_size(tx) as f64);
+            if fee_rate <= lowest_fee_rate {
+                return Err(ChainError::MempoolFull(format!("Mempool already holds {} transactions paying at least this fee rate", self.max_mempool_size)));
+            }
+        }
+        if self.mempool_size_bytes + tx_size > self.max_mempool_size_bytes || self.mempool.len() >= self.max_mempool_size {
             self.evict_transactions(tx_size);
         }
 
         // Add transaction to mempool
+        self.mempool_first_seen.insert(transaction.id.clone(), now);
         self.mempool.push(transaction.clone());
         self.mempool_size_bytes += tx_size;
 
         // Sort mempool by fee rate (fee per byte)
         self.sort_mempool();
+        self.subscriptions.notify(&transaction, TransactionStatus::Pending);
+        self.events.publish(ChainEvent::NewTransaction(transaction));
 
         Logger::info(&format!("Transaction added to mempool. Mempool size: {} bytes", self.mempool_size_bytes));
         Ok(())
     }
 
+    /// Test helper exercising conflict detection end to end: signs two transactions from `from`,
+    /// each spending `amount` to a different recipient at the minimum fee rate, and submits both
+    /// to the mempool. Returns whether the payments to `to1` and `to2` were admitted.
+    pub fn attempt_double_spend(&mut self, key: &Ed25519KeyPair, from: &str, to1: &str, to2: &str, amount: Amount) -> (bool, bool) {
+        let conflicting = [to1, to2].map(|to| {
+            let mut transaction = Transaction::new(from.to_string(), to.to_string(), amount, Amount::ZERO);
+            transaction.sign(key);
+            // The signature's length is fixed, so the size is known once signed
+            let minimum_fee = MIN_FEE_RATE * self.calculate_transaction_size(&transaction) as f64;
+            transaction.fee = Amount::from_base_units((minimum_fee * BASE_UNITS_PER_COIN as f64).ceil() as u64);
+            transaction.sign(key);
+            transaction
+        });
+        let [first, second] = conflicting.map(|transaction| {
+            let to = transaction.to.clone();
+            match self.add_to_mempool(transaction) {
+                Ok(()) => true,
+                Err(e) => {
+                    Logger::info(&format!("Double-spend attempt paying {} rejected: {}", to, e));
+                    false
+                }
+            }
+        });
+        (first, second)
+    }
+
+    // Inputs must be unspent on chain and not already claimed by a waiting transaction
+    fn check_mempool_inputs(&self, transaction: &Transaction) -> Result<(), ChainError> {
+        self.check_unused_txid(transaction)?;
+        let spent = self.utxo_input_total(&self.utxos, transaction)?;
+        check_utxo_balance(transaction, spent)?;
+        let claimed: HashSet<&TxInput> = self.mempool.iter().flat_map(|tx| &tx.inputs).collect();
+        if let Some(input) = transaction.inputs.iter().find(|input| claimed.contains(input)) {
+            return Err(ChainError::DoubleSpend(format!("Potential double-spend detected: output {}:{} is already spent by a mempool transaction", input.prev_tx_id, input.output_index)));
+        }
+        Ok(())
+    }
+
+    fn check_target_chain(&self, transaction: &Transaction) -> Result<(), ChainError> {
+        if transaction.target_chain_id != self.chain_id {
+            return Err(ChainError::InvalidTransaction(format!(
+                "Transaction is for chain {} but this is chain {}",
+                transaction.target_chain_id, self.chain_id
+            )));
+        }
+        Ok(())
+    }
+
     fn evict_transactions(&mut self, required_space: usize) {
-        while self.mempool_size_bytes + required_space > self.max_mempool_size_bytes {
+        while self.mempool_size_bytes + required_space > self.max_mempool_size_bytes || self.mempool.len() >= self.max_mempool_size {
             if let Some(tx) = self.mempool.pop() {
                 self.mempool_size_bytes -= self.calculate_transaction_size(&tx);
+                self.mempool_first_seen.remove(&tx.id);
                 Logger::info(&format!("Evicted transaction {} from mempool", tx.id));
             } else {
                 break;
@@ -343,36 +1823,149 @@ impl Blockchain {
         }
     }
 
+    pub fn clear_mempool(&mut self) {
+        let cleared = self.mempool.len();
+        self.mempool.clear();
+        self.mempool_first_seen.clear();
+        self.mempool_size_bytes = 0;
+        Logger::info(&format!("Cleared {} transactions from mempool", cleared));
+    }
+
+    /// Returns when the node admitted a mempool transaction, as opposed to the sender-set `timestamp`.
+    pub fn first_seen(&self, txid: &str) -> Option<DateTime<Utc>> {
+        self.mempool_first_seen.get(txid).copied()
+    }
+
     pub fn get_transactions_from_mempool(&mut self, max_transactions: usize) -> Vec<Transaction> {
-        let current_time = chrono::Utc::now().timestamp();
-        self.mempool.retain(|tx| tx.expiration > current_time);
+        let now = self.clock.now();
+        let stale: HashSet<String> = self.mempool
+            .iter()
+            .filter(|tx| tx.expiration <= now.timestamp() || self.outlived_mempool_ttl(tx, now))
+            .map(|tx| tx.id.clone())
+            .collect();
+        self.mempool.retain(|tx| !stale.contains(&tx.id));
 
-        let transactions: Vec<Transaction> = self.mempool.drain(..std::cmp::min(max_transactions, self.mempool.len())).collect();
+        let count = std::cmp::min(max_transactions, self.mempool.len());
+        let mut transactions: Vec<Transaction> = match self.selection_policy {
+            SelectionPolicy::Greedy => self.mempool.drain(..count).collect(),
+            SelectionPolicy::FeeWeighted { seed } => self.take_fee_weighted(count, seed),
+        };
+        if let Some(cap) = self.max_address_outflow_per_block {
+            // Transactions that would push their sender past the cap wait for a later block
+            let mut outflows: HashMap<String, Amount> = HashMap::new();
+            let (selected, deferred): (Vec<Transaction>, Vec<Transaction>) = transactions.into_iter().partition(|tx| {
+                let outflow = outflows.entry(tx.from.clone()).or_default();
+                let fits = *outflow + tx.amount + tx.fee <= cap;
+                if fits {
+                    *outflow += tx.amount + tx.fee;
+                }
+                fits
+            });
+            transactions = selected;
+            self.mempool.extend(deferred);
+            self.sort_mempool();
+        }
+        // Locked transactions wait too; blocks are stamped with the system time, so that is what
+        // time locks are measured against here
+        let (height, block_time) = (self.try_get_latest_block().map_or(0, |tip| tip.index + 1), Utc::now().timestamp());
+        let (selected, locked): (Vec<Transaction>, Vec<Transaction>) = transactions.into_iter().partition(|tx| tx.is_final(height, block_time));
+        transactions = selected;
+        if !locked.is_empty() {
+            self.mempool.extend(locked);
+            self.sort_mempool();
+        }
+        // A sender's spends in one block may not exceed what it held before the block, which
+        // `check_block_spends` enforces; the rest wait for a later block
+        if self.ledger_model == LedgerModel::Account {
+            let mut spends: HashMap<String, Amount> = HashMap::new();
+            let (selected, deferred): (Vec<Transaction>, Vec<Transaction>) = transactions.into_iter().partition(|tx| {
+                let held = self.balances.get(&tx.from).copied().unwrap_or_default();
+                let spent = spends.entry(tx.from.clone()).or_default();
+                let fits = spent.checked_add(tx.amount + tx.fee).is_some_and(|total| total <= held);
+                if fits {
+                    *spent += tx.amount + tx.fee;
+                }
+                fits
+            });
+            transactions = selected;
+            if !deferred.is_empty() {
+                self.mempool.extend(deferred);
+                self.sort_mempool();
+            }
+        }
+        // Data payloads have their own budget, so memos cannot crowd out payments
+        let (mut block_bytes, mut data_bytes) = (0, 0);
+        let (selected, deferred): (Vec<Transaction>, Vec<Transaction>) = transactions.into_iter().partition(|tx| {
+            let size = self.calculate_transaction_size(tx);
+            let data_len = tx.data.as_ref().map_or(0, Vec::len);
+            let fits = block_bytes + size <= self.max_block_size_bytes && data_bytes + data_len <= self.max_block_data_bytes;
+            if fits {
+                block_bytes += size;
+                data_bytes += data_len;
+            }
+            fits
+        });
+        transactions = selected;
+        if !deferred.is_empty() {
+            self.mempool.extend(deferred);
+            self.sort_mempool();
+        }
+        self.repair_mempool_accounting();
+        self.forget_departed_transactions();
         Logger::info(&format!("Retrieved {} transactions from mempool. Remaining mempool size: {}", transactions.len(), self.mempool.len()));
         transactions
     }
 
-    pub fn replace_transaction(&mut self, new_transaction: Transaction) -> Result<(), String> {
-        if !new_transaction.is_valid() {
-            return Err("Invalid transaction".to_string());
+    fn take_fee_weighted(&mut self, count: usize, seed: u64) -> Vec<Transaction> {
+        let mut rng = StdRng::seed_from_u64(seed ^ self.chain.len() as u64);
+        let mut weights: Vec<f64> = self.mempool.iter()
+            .map(|tx| tx.fee.to_coins() / self.calculate_transaction_size(tx) as f64)
+            .collect();
+
+        let mut selected = Vec::with_capacity(count);
+        for _ in 0..count {
+            let total: f64 = weights.iter().sum();
+            let mut point = rng.gen::<f64>() * total;
+            // Fall back to the front of the mempool if every remaining weight is zero
+            let mut index = 0;
+            if total > 0.0 {
+                for (i, weight) in weights.iter().enumerate() {
+                    if point < *weight {
+                        index = i;
+                        break;
+                    }
+                    point -= weight;
+                    index = i;
+                }
+            }
+            weights.remove(index);
+            selected.push(self.mempool.remove(index));
         }
+        selected
+    }
 
-        let sender_balance = self.get_balance(&new_transaction.from);
-        if sender_balance < new_transaction.amount + new_transaction.fee {
-            return Err("Insufficient balance".to_string());
+    /// Replaces a mempool transaction with a higher-fee version carrying the same id. Mempool
+    /// descendants of the original (spends by its recipient that relied on its output) are
+    /// re-evaluated, and the ids of those no longer funded are evicted and returned.
+    pub fn replace_transaction(&mut self, new_transaction: Transaction) -> Result<Vec<String>, ChainError> {
+        if !new_transaction.is_valid() {
+            return Err(ChainError::InvalidTransaction("Invalid transaction".to_string()));
         }
+        self.check_target_chain(&new_transaction)?;
+
+        self.check_spendable(&new_transaction.from, new_transaction.amount + new_transaction.fee)?;
 
         let old_tx_index = self.mempool.iter().position(|tx| tx.id == new_transaction.id);
 
         if let Some(index) = old_tx_index {
             let old_tx = &self.mempool[index];
             if new_transaction.fee <= old_tx.fee {
-                return Err("New transaction must have a higher fee for RBF".to_string());
+                return Err(ChainError::FeeTooLow("New transaction must have a higher fee for RBF".to_string()));
             }
 
             // Remove old transaction and update mempool size
             let old_tx_size = self.calculate_transaction_size(old_tx);
-            self.mempool.remove(index);
+            let old_tx = self.mempool.remove(index);
             self.mempool_size_bytes -= old_tx_size;
 
             // Add new transaction
@@ -383,18 +1976,57 @@ impl Blockchain {
             // Re-sort mempool
             self.sort_mempool();
 
-            Logger::info(&format!("Transaction replaced in mempool. New mempool size: {} bytes", self.mempool_size_bytes));
-            Ok(())
+            let evicted = old_tx.payments()
+                .into_iter()
+                .flat_map(|payment| self.evict_unfunded_descendants(&payment.address))
+                .collect::<Vec<String>>();
+            Logger::info(&format!("Transaction replaced in mempool, evicting {} descendants. New mempool size: {} bytes", evicted.len(), self.mempool_size_bytes));
+            Ok(evicted)
         } else {
-            Err("Original transaction not found in mempool".to_string())
+            Err(ChainError::NotFound("Original transaction not found in mempool".to_string()))
+        }
+    }
+
+    // Starting from `address`, evicts mempool spends that its confirmed balance plus pending incoming
+    // amounts can no longer cover, keeping the highest fee-rate spends first. Recipients of evicted
+    // transactions lose that income, so their own spends are re-checked in turn.
+    fn evict_unfunded_descendants(&mut self, address: &str) -> Vec<String> {
+        let mut evicted = Vec::new();
+        let mut pending = vec![address.to_string()];
+
+        while let Some(address) = pending.pop() {
+            let incoming: Amount = self.mempool.iter()
+                .flat_map(|tx| tx.payments())
+                .filter(|payment| payment.address == address)
+                .map(|payment| payment.amount)
+                .sum();
+            let available = self.get_balance(&address) + incoming;
+
+            let mut spent = Amount::ZERO;
+            let mut unfunded = Vec::new();
+            for tx in self.mempool.iter().filter(|tx| tx.from == address) {
+                if spent + tx.amount + tx.fee <= available {
+                    spent += tx.amount + tx.fee;
+                } else {
+                    unfunded.push(tx.clone());
+                }
+            }
+
+            for tx in unfunded {
+                self.mempool.retain(|t| t.id != tx.id);
+                self.mempool_size_bytes -= self.calculate_transaction_size(&tx);
+                self.mempool_first_seen.remove(&tx.id);
+                Logger::info(&format!("Evicted descendant transaction {} left unfunded by a replacement", tx.id));
+                pending.extend(tx.payments().into_iter().map(|payment| payment.address));
+                evicted.push(tx.id);
+            }
         }
+        evicted
     }
 
     pub fn save_mempool(&self, file_path: &str) -> std::io::Result<()> {
         let serialized = serde_json::to_string(&self.mempool)?;
-        let mut file = File::create(file_path)?;
-        file.write_all(serialized.as_bytes())?;
-        Ok(())
+        write_atomically(file_path, serialized.as_bytes())
     }
 
     pub fn load_mempool(&mut self, file_path: &str) -> std::io::Result<()> {
@@ -402,22 +2034,295 @@ impl Blockchain {
         let mut contents = String::new();
         file.read_to_string(&mut contents)?;
         self.mempool = serde_json::from_str(&contents)?;
-        self.mempool_size_bytes = self.mempool.iter().map(|tx| self.calculate_transaction_size(tx)).sum();
+        let now = self.clock.now();
+        for tx in &self.mempool {
+            self.mempool_first_seen.entry(tx.id.clone()).or_insert(now);
+        }
+        self.forget_departed_transactions();
+        self.repair_mempool_accounting();
+        self.sort_mempool();
+        Ok(())
+    }
+
+    pub fn save_chain(&self, path: &str) -> std::io::Result<()> {
+        let serialized = serde_json::to_string(&self.chain)?;
+        write_atomically(path, serialized.as_bytes())?;
+        Logger::info(&format!("Saved chain of {} blocks to {}", self.chain.len(), path));
+        Ok(())
+    }
+
+    /// Loads a chain saved with `save_chain` or `save_chain_gzip`; compressed files are detected by
+    /// their gzip magic bytes. The loaded chain is validated before it replaces the current one, so a
+    /// corrupted file leaves the existing state untouched.
+    pub fn load_chain(&mut self, path: &str) -> std::io::Result<()> {
+        let loaded = read_chain_file(path)?;
+        self.install_loaded_chain(loaded, path)
+    }
+
+    /// Replays a chain file written by `save_chain` or `save_chain_gzip` under this node's rules and
+    /// compares the result with the running state, to detect corruption of either. Every divergence
+    /// in tip hash, height, or balance is reported. Funds credited off-chain with `add_balance` are
+    /// not in the file, so they show up as balance divergences too.
+    pub fn verify_against(&self, path: &str) -> Result<(), Vec<String>> {
+        let blocks = read_chain_file(path).map_err(|e| vec![format!("Failed to read chain file {}: {}", path, e)])?;
+        self.validate_external_chain(&blocks).map_err(|e| vec![format!("Chain file {} is invalid: {}", path, e)])?;
+
+        let mut replayed: HashMap<String, Amount> = HashMap::new();
+        for transaction in blocks.iter().flat_map(|block| &block.transactions) {
+            self.apply_transaction(&mut replayed, transaction);
+        }
+
+        let mut divergences = Vec::new();
+        let file_tip = blocks.last().expect("validated chain is not empty");
+        let Some(node_tip) = self.try_get_latest_block() else {
+            return Err(vec!["Node chain is empty".to_string()]);
+        };
+        if file_tip.hash != node_tip.hash {
+            divergences.push(format!("Tip hash differs: file has {}, node has {}", file_tip.hash, node_tip.hash));
+        }
+        if file_tip.index != node_tip.index {
+            divergences.push(format!("Height differs: file has {}, node has {}", file_tip.index, node_tip.index));
+        }
+        let addresses: BTreeSet<&String> = replayed.keys().chain(self.balances.keys()).collect();
+        for address in addresses {
+            let file_balance = replayed.get(address).copied().unwrap_or_default();
+            let node_balance = self.balances.get(address).copied().unwrap_or_default();
+            if file_balance != node_balance {
+                divergences.push(format!("Balance of {} differs: file has {}, node has {}", address, file_balance, node_balance));
+            }
+        }
+
+        if divergences.is_empty() {
+            Logger::validation(&format!("Chain file {} matches the running node", path));
+            Ok(())
+        } else {
+            Logger::error(&format!("Chain file {} diverges from the running node in {} ways", path, divergences.len()));
+            Err(divergences)
+        }
+    }
+
+    pub fn save_chain_gzip(&self, path: &str) -> std::io::Result<()> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        serde_json::to_writer(&mut encoder, &self.chain)?;
+        let compressed = encoder.finish()?;
+        write_atomically(path, &compressed)?;
+        Logger::info(&format!("Saved chain of {} blocks to {} ({} bytes compressed)", self.chain.len(), path, compressed.len()));
+        Ok(())
+    }
+
+    /// Same as `load_chain`, which already accepts gzip-compressed files.
+    pub fn load_chain_gzip(&mut self, path: &str) -> std::io::Result<()> {
+        self.load_chain(path)
+    }
+
+    pub fn save_chain_binary(&self, path: &str) -> std::io::Result<()> {
+        let encoded = encode_binary(&self.chain)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        write_atomically(path, &encoded)?;
+        Logger::info(&format!("Saved chain of {} blocks to {} ({} bytes)", self.chain.len(), path, encoded.len()));
+        Ok(())
+    }
+
+    /// Binary counterpart of `load_chain`; files with a foreign header or format version are rejected.
+    pub fn load_chain_binary(&mut self, path: &str) -> std::io::Result<()> {
+        let mut file = File::open(path)?;
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+        let loaded: Vec<Block> = decode_binary(&contents)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        self.install_loaded_chain(loaded, path)
+    }
+
+    /// Writes every address in `balances` with its balance as CSV, largest balance first.
+    pub fn export_balances_csv(&self, path: &str) -> std::io::Result<()> {
+        let mut balances: Vec<(&String, &Amount)> = self.balances.iter().collect();
+        balances.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+
+        let mut csv = String::from("address,balance\n");
+        for (address, balance) in &balances {
+            csv.push_str(&format!("{},{}\n", csv_field(address), balance));
+        }
+        write_atomically(path, csv.as_bytes())?;
+        Logger::info(&format!("Exported {} balances to {}", balances.len(), path));
+        Ok(())
+    }
+
+    /// Writes one CSV row per transaction in the chain, in chain order.
+    pub fn export_transactions_csv(&self, path: &str) -> std::io::Result<()> {
+        let mut csv = String::from("block_index,id,from,to,amount,fee,timestamp\n");
+        let mut rows = 0;
+        for block in &self.chain {
+            for tx in &block.transactions {
+                csv.push_str(&format!(
+                    "{},{},{},{},{},{},{}\n",
+                    block.index, csv_field(&tx.id), csv_field(&tx.from), csv_field(&tx.to), tx.amount, tx.fee, tx.timestamp
+                ));
+                rows += 1;
+            }
+        }
+        write_atomically(path, csv.as_bytes())?;
+        Logger::info(&format!("Exported {} transactions to {}", rows, path));
+        Ok(())
+    }
+
+    /// Streams the chain as newline-delimited JSON, one block per line in chain order, so consumers
+    /// can process it without loading the whole export.
+    pub fn export_ndjson<W: Write>(&self, writer: W) -> std::io::Result<()> {
+        let mut writer = std::io::BufWriter::new(writer);
+        for block in &self.chain {
+            serde_json::to_writer(&mut writer, block)?;
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()?;
+        Logger::info(&format!("Exported {} blocks as NDJSON", self.chain.len()));
+        Ok(())
+    }
+
+    /// Opens an append-only block log at `path`. From then on every block accepted by `add_block`
+    /// is appended as a single length-prefixed record, so persisting a block costs O(1) rather than
+    /// rewriting the whole chain. A new log is seeded with the current chain; an existing one is
+    /// assumed to already hold it, e.g. when continuing from `replay_log`. Reorgs and reloads rewrite
+    /// the log to match the new chain; records of blocks dropped by `prune_to` are kept, so the log
+    /// still replays from genesis.
+    pub fn open_block_log(&mut self, path: &str) -> std::io::Result<()> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        if file.metadata()?.len() == 0 {
+            let mut bytes = binary_header().to_vec();
+            for block in &self.chain {
+                bytes.extend(encode_record(block).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?);
+            }
+            file.write_all(&bytes)?;
+            file.sync_data()?;
+        }
+        self.block_log = Some((path.to_string(), file));
+        Logger::info(&format!("Appending blocks to log {}", path));
+        Ok(())
+    }
+
+    // Rewrites the open block log if its records no longer match the chain, after the chain was
+    // replaced rather than extended
+    fn sync_block_log(&mut self) {
+        let Some((path, _)) = &self.block_log else {
+            return;
+        };
+        let path = path.clone();
+        if let Err(e) = self.rewrite_block_log(&path) {
+            Logger::error(&format!("Failed to rewrite block log {}: {}", path, e));
+        }
+    }
+
+    fn rewrite_block_log(&mut self, path: &str) -> std::io::Result<()> {
+        let invalid_data = |e: String| std::io::Error::new(std::io::ErrorKind::InvalidData, e);
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut header = [0u8; 6];
+        reader.read_exact(&mut header)?;
+        check_binary_header(&header).map_err(invalid_data)?;
+
+        let first_index = self.chain.first().map_or(0, |block| block.index);
+        let mut pruned = Vec::new();
+        let mut logged_hashes = Vec::new();
+        while let Some(block) = read_record::<Block, _>(&mut reader).map_err(invalid_data)? {
+            if block.index < first_index {
+                pruned.push(block);
+            } else {
+                logged_hashes.push(block.hash);
+            }
+        }
+        if logged_hashes.len() == self.chain.len() && logged_hashes.iter().zip(&self.chain).all(|(hash, block)| *hash == block.hash) {
+            return Ok(());
+        }
+
+        let mut bytes = binary_header().to_vec();
+        for block in pruned.iter().chain(&self.chain) {
+            bytes.extend(encode_record(block).map_err(invalid_data)?);
+        }
+        write_atomically(path, &bytes)?;
+        // The rename left the old handle pointing at the replaced file
+        let file = OpenOptions::new().append(true).open(path)?;
+        self.block_log = Some((path.to_string(), file));
+        Logger::info(&format!("Rewrote block log {} with {} blocks", path, pruned.len() + self.chain.len()));
+        Ok(())
+    }
+
+    /// Appends a single block record to the open block log.
+    pub fn append_block_to_log(&mut self, block: &Block) -> std::io::Result<()> {
+        let record = encode_record(block).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        let (_, file) = self.block_log.as_mut()
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "No block log is open"))?;
+        file.write_all(&record)?;
+        file.sync_data()
+    }
+
+    /// Rebuilds a chain from a block log written via `open_block_log`, validating each block's
+    /// linkage, proof-of-work, and difficulty as it is streamed in. The log does not record
+    /// consensus parameters, so the reward and target block time must match the writer's; logs of
+    /// chains created with other non-default settings are replayed via `BlockchainBuilder::build_from_log`.
+    pub fn replay_log(path: &str, mining_reward: Amount, target_block_time: chrono::Duration) -> Result<Blockchain, ChainError> {
+        let (reader, genesis) = open_log_for_replay(path)?;
+        Blockchain::builder(genesis.difficulty, mining_reward, target_block_time)
+            .build()
+            .replay_records(reader, genesis, path)
+    }
+
+    pub(crate) fn replay_records(mut self, mut reader: BufReader<File>, genesis: Block, path: &str) -> Result<Blockchain, ChainError> {
+        self.check_genesis(&genesis)
+            .map_err(|e| ChainError::InvalidBlock(format!("Block log does not start with a valid genesis block: {}", e)))?;
+        self.chain = vec![genesis];
+        self.reindex_blocks();
+        while let Some(block) = read_record::<Block, _>(&mut reader).map_err(ChainError::Storage)? {
+            let index = block.index;
+            self.extend_chain(block).map_err(|e| ChainError::InvalidBlock(format!("Block log record {}: {}", index, e)))?;
+            self.update_balances();
+        }
+
+        Logger::info(&format!("Replayed {} blocks from block log {}", self.chain.len(), path));
+        Ok(self)
+    }
+
+    fn install_loaded_chain(&mut self, loaded: Vec<Block>, path: &str) -> std::io::Result<()> {
+        // A file starting from genesis replaces a pruned chain outright instead of building on its checkpoint
+        let prune_base = match loaded.first() {
+            Some(first) if first.index == 0 => self.prune_base.take(),
+            _ => None,
+        };
+        let previous = std::mem::replace(&mut self.chain, loaded);
+        if self.chain.is_empty() || !self.validate_chain() {
+            self.chain = previous;
+            if prune_base.is_some() {
+                self.prune_base = prune_base;
+            }
+            Logger::error(&format!("Rejected invalid chain file {}", path));
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Loaded chain failed validation"));
+        }
+        self.reindex_blocks();
+
+        self.recalculate_balances();
+        self.rebuild_difficulty_state();
+        self.sync_store();
+        self.sync_block_log();
+        Logger::info(&format!("Loaded chain of {} blocks from {}", self.chain.len(), path));
         Ok(())
     }
 
-    fn calculate_transaction_size(&self, transaction: &Transaction) -> usize {
+    pub fn calculate_transaction_size(&self, transaction: &Transaction) -> usize {
         // This is a simplified calculation and should be adjusted based on your actual transaction structure
         let base_size = std::mem::size_of::<Transaction>();
-        let variable_size = transaction.from.len() + transaction.to.len() + transaction.signature.as_ref().map_or(0, |s| s.len());
+        let variable_size = transaction.from.len() + transaction.to.len() + transaction.signature.as_ref().map_or(0, |s| s.len())
+            + transaction.inputs.iter().map(|input| input.prev_tx_id.len() + std::mem::size_of::<u32>()).sum::<usize>()
+            + transaction.outputs.iter().map(|output| output.address.len() + std::mem::size_of::<Amount>()).sum::<usize>()
+            + transaction.signatures.iter().map(|s| s.len()).sum::<usize>()
+            + transaction.data.as_ref().map_or(0, |data| data.len());
         base_size + variable_size
     }
 
     pub fn clean_expired_transactions(&mut self) {
-        let current_time = chrono::Utc::now().timestamp();
+        if self.paused {
+            return;
+        }
+        let now = self.clock.now();
         let expired_transactions: Vec<_> = self.mempool
             .iter()
-            .filter(|tx| tx.expiration < current_time)
+            .filter(|tx| tx.expiration < now.timestamp() || self.outlived_mempool_ttl(tx, now))
             .cloned()
             .collect();
 
@@ -428,24 +2333,169 @@ impl Blockchain {
             Logger::info(&format!("Removed expired transaction {} from mempool", tx.id));
         }
 
+        self.repair_mempool_accounting();
+        self.forget_departed_transactions();
         self.sort_mempool();
     }
 
+    fn outlived_mempool_ttl(&self, transaction: &Transaction, now: DateTime<Utc>) -> bool {
+        match (self.mempool_tx_ttl, self.mempool_first_seen.get(&transaction.id)) {
+            (Some(ttl), Some(first_seen)) => now - *first_seen > ttl,
+            _ => false,
+        }
+    }
+
+    fn forget_departed_transactions(&mut self) {
+        let ids: HashSet<&String> = self.mempool.iter().map(|tx| &tx.id).collect();
+        self.mempool_first_seen.retain(|id, _| ids.contains(id));
+    }
+
+    pub fn repair_mempool_accounting(&mut self) {
+        let actual_size: usize = self.mempool.iter().map(|tx| self.calculate_transaction_size(tx)).sum();
+        if actual_size != self.mempool_size_bytes {
+            Logger::info(&format!("Repaired mempool size accounting: {} bytes recorded, {} bytes actual", self.mempool_size_bytes, actual_size));
+            self.mempool_size_bytes = actual_size;
+        }
+    }
+
     fn sort_mempool(&mut self) {
         let tx_sizes: Vec<_> = self.mempool.iter()
             .map(|tx| self.calculate_transaction_size(tx))
             .collect();
         
+        let fee_rates: Vec<f64> = self.mempool.iter()
+            .zip(&tx_sizes)
+            .map(|(tx, size)| tx.fee.to_coins() / *size as f64)
+            .collect();
+
         let mut indices: Vec<usize> = (0..self.mempool.len()).collect();
         
-        indices.sort_by(|&a, &b| {
-            let a_fee_rate = self.mempool[a].fee / tx_sizes[a] as f64;
-            let b_fee_rate = self.mempool[b].fee / tx_sizes[b] as f64;
-            b_fee_rate.partial_cmp(&a_fee_rate).unwrap_or(std::cmp::Ordering::Equal)
-        });
+        // Highest fee rate first
+        indices.sort_by(|&a, &b| fee_rates[b].total_cmp(&fee_rates[a]));
         
         // Reorder the mempool based on the sorted indices
         let sorted_mempool: Vec<_> = indices.into_iter().map(|i| self.mempool[i].clone()).collect();
         self.mempool = sorted_mempool;
     }
-}
\ No newline at end of file
+}
+
+// The proof-of-stake producer of the block after `previous_hash`: an address drawn from `balances`
+// with probability proportional to its balance, by a generator seeded with that hash
+fn draw_validator(balances: &HashMap<String, Amount>, previous_hash: &str) -> Option<String> {
+    let mut stakes: Vec<(&String, u64)> = balances
+        .iter()
+        .filter(|(_, balance)| **balance > Amount::ZERO)
+        .map(|(address, balance)| (address, balance.base_units()))
+        .collect();
+    // Balances live in a HashMap, so fix the order before drawing
+    stakes.sort_by(|a, b| a.0.cmp(b.0));
+
+    let previous_hash = hex::decode(previous_hash).unwrap_or_default();
+    let seed = previous_hash.iter().take(8).fold(0u64, |seed, byte| (seed << 8) | *byte as u64);
+    let total = stakes.iter().try_fold(0u64, |total, (_, stake)| total.checked_add(*stake))?;
+    if total == 0 {
+        return None;
+    }
+    let mut point = StdRng::seed_from_u64(seed).gen_range(0..total);
+    for (address, stake) in &stakes {
+        if point < *stake {
+            return Some(address.to_string());
+        }
+        point -= stake;
+    }
+    unreachable!("the drawn point lies below the total stake")
+}
+
+// Fails if any sender, other than the coinbase source, spends more within the block than
+// `balances` held for it before the block
+fn check_block_spends(balances: &HashMap<String, Amount>, block: &Block) -> Result<(), ChainError> {
+    let mut spends: HashMap<&str, Amount> = HashMap::new();
+    for transaction in block.transactions.iter().filter(|tx| tx.from != "Blockchain") {
+        let spent = spends.entry(transaction.from.as_str()).or_default();
+        *spent = spent.checked_add(transaction.amount)
+            .and_then(|spent| spent.checked_add(transaction.fee))
+            .ok_or_else(|| ChainError::InvalidBlock(format!("Spends of {} in this block overflow an amount", transaction.from)))?;
+        let held = balances.get(&transaction.from).copied().unwrap_or_default();
+        if *spent > held {
+            return Err(ChainError::InvalidBlock(format!(
+                "Transaction {} overdraws {}: it spends {} in this block but held only {}",
+                transaction.id, transaction.from, spent, held
+            )));
+        }
+    }
+    Ok(())
+}
+
+// The outputs a transaction spends must cover exactly its amount and fee
+fn check_utxo_balance(transaction: &Transaction, spent: Amount) -> Result<(), ChainError> {
+    if spent != transaction.amount + transaction.fee {
+        return Err(ChainError::InvalidTransaction(format!(
+            "Transaction {} spends outputs worth {} but pays out {} plus a fee of {}",
+            transaction.id, spent, transaction.amount, transaction.fee
+        )));
+    }
+    Ok(())
+}
+
+// Advances the sender's next nonce past a confirmed transaction; the coinbase has no sender
+fn record_nonce(nonces: &mut HashMap<String, u64>, transaction: &Transaction) {
+    if transaction.from != "Blockchain" {
+        let next = nonces.entry(transaction.from.clone()).or_insert(0);
+        *next = (*next).max(transaction.nonce + 1);
+    }
+}
+
+// Reads a JSON chain file, gzip-compressed or not
+fn read_chain_file(path: &str) -> std::io::Result<Vec<Block>> {
+    let mut file = File::open(path)?;
+    let mut contents = Vec::new();
+    file.read_to_end(&mut contents)?;
+    if contents.starts_with(&GZIP_MAGIC) {
+        Ok(serde_json::from_reader(GzDecoder::new(contents.as_slice()))?)
+    } else {
+        Ok(serde_json::from_slice(&contents)?)
+    }
+}
+
+// Quotes a CSV field when it contains a delimiter, quote, or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// 2024-01-01T00:00:00Z
+const GENESIS_TIMESTAMP: i64 = 1_704_067_200;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+fn is_well_formed_hash(hash: &str) -> bool {
+    hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Opens a block log for `replay_records`, checking its header and reading the genesis record.
+pub(crate) fn open_log_for_replay(path: &str) -> Result<(BufReader<File>, Block), ChainError> {
+    let file = File::open(path).map_err(|e| ChainError::Storage(format!("Failed to open block log {}: {}", path, e)))?;
+    let mut reader = BufReader::new(file);
+
+    let mut header = [0u8; 6];
+    reader.read_exact(&mut header).map_err(|_| ChainError::Storage("Block log is missing its header".to_string()))?;
+    check_binary_header(&header).map_err(ChainError::Storage)?;
+
+    let genesis: Block = read_record(&mut reader)
+        .map_err(ChainError::Storage)?
+        .ok_or_else(|| ChainError::Storage("Block log is empty".to_string()))?;
+    Ok((reader, genesis))
+}
+
+/// Writes `contents` to `<path>.tmp` and renames it over `path`, so a crash mid-write never leaves
+/// a truncated file behind: readers see either the previous file or the complete new one.
+fn write_atomically(path: &str, contents: &[u8]) -> std::io::Result<()> {
+    let temp_path = format!("{}.tmp", path);
+    let mut file = File::create(&temp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    std::fs::rename(&temp_path, path)
+}
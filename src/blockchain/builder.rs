@@ -0,0 +1,152 @@
+use super::amount::Amount;
+use super::block::Block;
+use super::blockchain::{open_log_for_replay, Blockchain, Consensus, LedgerModel};
+use super::error::ChainError;
+use super::store::ChainStore;
+use chrono::{DateTime, Utc};
+
+/// Configures a `Blockchain` before it is created, for settings `Blockchain::new` otherwise
+/// hard-codes. Obtained from `Blockchain::builder`; anything not set keeps the value `new` uses.
+#[derive(Debug, Clone)]
+pub struct BlockchainBuilder {
+    difficulty: u32,
+    mining_reward: Amount,
+    target_block_time: chrono::Duration,
+    difficulty_adjustment_interval: Option<u64>,
+    max_mempool_size: Option<usize>,
+    max_mempool_size_bytes: Option<usize>,
+    genesis_timestamp: Option<DateTime<Utc>>,
+    chain_id: Option<u64>,
+    coinbase_maturity: Option<u64>,
+    halving_interval: Option<u64>,
+    consensus: Option<Consensus>,
+    ledger_model: Option<LedgerModel>,
+}
+
+impl BlockchainBuilder {
+    pub(crate) fn new(difficulty: u32, mining_reward: Amount, target_block_time: chrono::Duration) -> Self {
+        BlockchainBuilder {
+            difficulty,
+            mining_reward,
+            target_block_time,
+            difficulty_adjustment_interval: None,
+            max_mempool_size: None,
+            max_mempool_size_bytes: None,
+            genesis_timestamp: None,
+            chain_id: None,
+            coinbase_maturity: None,
+            halving_interval: None,
+            consensus: None,
+            ledger_model: None,
+        }
+    }
+
+    /// Blocks between difficulty retargets.
+    pub fn difficulty_adjustment_interval(mut self, blocks: u64) -> Self {
+        self.difficulty_adjustment_interval = Some(blocks);
+        self
+    }
+
+    /// Maximum number of transactions the mempool holds; the lowest-fee one is evicted to admit a
+    /// better-paying transaction beyond it.
+    pub fn max_mempool_size(mut self, transactions: usize) -> Self {
+        self.max_mempool_size = Some(transactions);
+        self
+    }
+
+    /// Maximum total size of the mempool; the lowest-fee transactions are evicted beyond it.
+    pub fn max_mempool_size_bytes(mut self, bytes: usize) -> Self {
+        self.max_mempool_size_bytes = Some(bytes);
+        self
+    }
+
+    /// Stamps the genesis block with `timestamp`, giving the network its own genesis hash.
+    pub fn genesis_timestamp(mut self, timestamp: DateTime<Utc>) -> Self {
+        self.genesis_timestamp = Some(timestamp);
+        self
+    }
+
+    /// The network the chain belongs to; transactions and peers for other chains are refused.
+    pub fn chain_id(mut self, chain_id: u64) -> Self {
+        self.chain_id = Some(chain_id);
+        self
+    }
+
+    /// Blocks that must be mined on top of a coinbase before it can be spent.
+    pub fn coinbase_maturity(mut self, blocks: u64) -> Self {
+        self.coinbase_maturity = Some(blocks);
+        self
+    }
+
+    /// Blocks between halvings of the block subsidy.
+    pub fn halving_interval(mut self, blocks: u64) -> Self {
+        self.halving_interval = Some(blocks);
+        self
+    }
+
+    /// How block producers are chosen.
+    pub fn consensus(mut self, consensus: Consensus) -> Self {
+        self.consensus = Some(consensus);
+        self
+    }
+
+    /// Whether balances are kept as accounts or as unspent outputs.
+    pub fn ledger_model(mut self, ledger_model: LedgerModel) -> Self {
+        self.ledger_model = Some(ledger_model);
+        self
+    }
+
+    pub fn build(self) -> Blockchain {
+        let mut blockchain = Blockchain::new(self.difficulty, self.mining_reward, self.target_block_time);
+        if let Some(interval) = self.difficulty_adjustment_interval {
+            blockchain.difficulty_adjustment_interval = interval;
+        }
+        if let Some(size) = self.max_mempool_size {
+            blockchain.max_mempool_size = size;
+        }
+        if let Some(bytes) = self.max_mempool_size_bytes {
+            blockchain.max_mempool_size_bytes = bytes;
+        }
+        if let Some(timestamp) = self.genesis_timestamp {
+            let mut genesis = Blockchain::genesis_block(self.difficulty);
+            genesis.timestamp = timestamp;
+            genesis.hash = genesis.calculate_hash();
+            blockchain.genesis_hash = genesis.hash.clone();
+            blockchain.chain = vec![genesis];
+            blockchain.reindex_blocks();
+        }
+        if let Some(chain_id) = self.chain_id {
+            blockchain.chain_id = chain_id;
+        }
+        if let Some(maturity) = self.coinbase_maturity {
+            blockchain.coinbase_maturity = maturity;
+        }
+        blockchain.halving_interval = self.halving_interval;
+        if let Some(consensus) = self.consensus {
+            blockchain.consensus = consensus;
+        }
+        if let Some(ledger_model) = self.ledger_model {
+            // Rebuilt so the output set matches the model, as `with_ledger_model` does
+            blockchain.ledger_model = ledger_model;
+            blockchain.recalculate_balances();
+        }
+        blockchain
+    }
+    /// Builds the blockchain and adopts `blocks` as `Blockchain::from_blocks` does, validating them
+    /// under the configured settings. Reloading a chain created with this builder this way keeps
+    /// its genesis, retarget interval, reward policy, chain id and maturity.
+    pub fn build_from_blocks(self, blocks: Vec<Block>) -> Result<Blockchain, ChainError> {
+        self.build().adopt_blocks(blocks)
+    }
+
+    /// Builds the blockchain from the blocks held in `store`, as `Blockchain::from_store` does.
+    pub fn build_from_store(self, store: Box<dyn ChainStore>) -> Result<Blockchain, ChainError> {
+        self.build().adopt_store(store)
+    }
+
+    /// Builds the blockchain by replaying a block log, as `Blockchain::replay_log` does.
+    pub fn build_from_log(self, path: &str) -> Result<Blockchain, ChainError> {
+        let (reader, genesis) = open_log_for_replay(path)?;
+        self.build().replay_records(reader, genesis, path)
+    }
+}
@@ -0,0 +1,53 @@
+use ring::signature::{Ed25519KeyPair, UnparsedPublicKey, ED25519};
+use serde::{Deserialize, Serialize};
+use super::amount::Amount;
+use std::collections::HashMap;
+
+/// A block hash that the chain must contain at the given height.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub height: u64,
+    pub hash: String,
+}
+
+/// The balances map as of the block at `height`, so a node can restore it on startup and replay
+/// only the blocks after it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BalanceSnapshot {
+    pub height: u64,
+    pub balances: HashMap<String, Amount>,
+}
+
+/// A list of checkpoints signed by a trusted authority's Ed25519 key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedCheckpoints {
+    pub checkpoints: Vec<Checkpoint>,
+    pub signature: String,
+}
+
+impl SignedCheckpoints {
+    pub fn sign(checkpoints: Vec<Checkpoint>, key_pair: &Ed25519KeyPair) -> Self {
+        let signature = key_pair.sign(&Self::signing_payload(&checkpoints));
+        SignedCheckpoints {
+            checkpoints,
+            signature: hex::encode(signature.as_ref()),
+        }
+    }
+
+    pub fn verify(&self, authority_pubkey: &[u8]) -> bool {
+        let Ok(signature) = hex::decode(&self.signature) else {
+            return false;
+        };
+        UnparsedPublicKey::new(&ED25519, authority_pubkey)
+            .verify(&Self::signing_payload(&self.checkpoints), &signature)
+            .is_ok()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("checkpoints are always serializable")
+    }
+
+    fn signing_payload(checkpoints: &[Checkpoint]) -> Vec<u8> {
+        serde_json::to_vec(checkpoints).expect("checkpoints are always serializable")
+    }
+}
@@ -0,0 +1,91 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::io::Read;
+
+/// Magic bytes identifying a KrakenChain binary file.
+pub const BINARY_MAGIC: &[u8; 4] = b"KRKN";
+/// Version of the binary layout; bump whenever the encoding of persisted types changes.
+pub const BINARY_FORMAT_VERSION: u16 = 12;
+
+const HEADER_LEN: usize = BINARY_MAGIC.len() + 2;
+
+/// Largest record `read_record` accepts; a bigger length prefix means the input is corrupt.
+pub(crate) const MAX_RECORD_LEN: usize = 16 * 1024 * 1024;
+
+/// Encodes a value with bincode, prefixed by the magic bytes and a big-endian format version.
+pub fn encode_binary<T: Serialize>(value: &T) -> Result<Vec<u8>, String> {
+    let body = bincode::serialize(value).map_err(|e| format!("Binary encoding failed: {}", e))?;
+    let mut bytes = Vec::with_capacity(HEADER_LEN + body.len());
+    bytes.extend_from_slice(&binary_header());
+    bytes.extend_from_slice(&body);
+    Ok(bytes)
+}
+
+/// Size in bytes of a value's bincode body, i.e. `encode_binary` output without the header.
+pub fn binary_size<T: Serialize>(value: &T) -> Result<usize, String> {
+    bincode::serialized_size(value)
+        .map(|size| size as usize)
+        .map_err(|e| format!("Binary encoding failed: {}", e))
+}
+
+/// Decodes bytes produced by `encode_binary`, rejecting unknown magic bytes or format versions.
+pub fn decode_binary<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, String> {
+    check_binary_header(bytes)?;
+    bincode::deserialize(&bytes[HEADER_LEN..]).map_err(|e| format!("Binary decoding failed: {}", e))
+}
+
+/// The magic bytes followed by the big-endian format version.
+pub(crate) fn binary_header() -> [u8; HEADER_LEN] {
+    let mut header = [0u8; HEADER_LEN];
+    header[..BINARY_MAGIC.len()].copy_from_slice(BINARY_MAGIC);
+    header[BINARY_MAGIC.len()..].copy_from_slice(&BINARY_FORMAT_VERSION.to_be_bytes());
+    header
+}
+
+pub(crate) fn check_binary_header(bytes: &[u8]) -> Result<(), String> {
+    if bytes.len() < HEADER_LEN || &bytes[..BINARY_MAGIC.len()] != BINARY_MAGIC {
+        return Err("Not a KrakenChain binary file".to_string());
+    }
+    let version = u16::from_be_bytes([bytes[4], bytes[5]]);
+    if version != BINARY_FORMAT_VERSION {
+        return Err(format!("Unsupported binary format version {} (expected {})", version, BINARY_FORMAT_VERSION));
+    }
+    Ok(())
+}
+
+/// Encodes a value as a record for an append-only log: a big-endian `u32` length, then the bincode body.
+pub(crate) fn encode_record<T: Serialize>(value: &T) -> Result<Vec<u8>, String> {
+    let body = bincode::serialize(value).map_err(|e| format!("Binary encoding failed: {}", e))?;
+    if body.len() > MAX_RECORD_LEN {
+        return Err("Record too large".to_string());
+    }
+    let len = body.len() as u32;
+    let mut bytes = Vec::with_capacity(4 + body.len());
+    bytes.extend_from_slice(&len.to_be_bytes());
+    bytes.extend_from_slice(&body);
+    Ok(bytes)
+}
+
+/// Reads the next record written by `encode_record`. Returns `Ok(None)` at a clean end of input and
+/// an error if the input ends partway through a record.
+pub(crate) fn read_record<T: DeserializeOwned, R: Read>(reader: &mut R) -> Result<Option<T>, String> {
+    let mut len_bytes = [0u8; 4];
+    let mut filled = 0;
+    while filled < len_bytes.len() {
+        match reader.read(&mut len_bytes[filled..]) {
+            Ok(0) if filled == 0 => return Ok(None),
+            Ok(0) => return Err("Truncated record length".to_string()),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(format!("Failed to read record: {}", e)),
+        }
+    }
+
+    let len = u32::from_be_bytes(len_bytes) as usize;
+    if len > MAX_RECORD_LEN {
+        return Err(format!("Record length {} exceeds the maximum of {} bytes", len, MAX_RECORD_LEN));
+    }
+    let mut body = vec![0u8; len];
+    reader.read_exact(&mut body).map_err(|_| "Truncated record body".to_string())?;
+    bincode::deserialize(&body).map(Some).map_err(|e| format!("Binary decoding failed: {}", e))
+}
@@ -0,0 +1,56 @@
+use std::fmt;
+
+/// Why a `Blockchain` operation failed. Each variant carries a human-readable message, which is
+/// what `Display` prints; callers that need to react to a failure should match on the variant.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChainError {
+    /// The transaction is malformed, badly signed, or not acceptable in its current form.
+    InvalidTransaction(String),
+    /// The sender cannot cover the amount and fee from their spendable balance.
+    InsufficientBalance(String),
+    /// The transaction spends funds or outputs that another transaction already spends.
+    DoubleSpend(String),
+    /// The transaction's expiration has passed.
+    Expired(String),
+    /// The fee is below the node's minimum, or too low to replace a pending transaction.
+    FeeTooLow(String),
+    /// The mempool has no room for the transaction even after evicting others.
+    MempoolFull(String),
+    /// A block, transaction, or stored record that was asked for does not exist.
+    NotFound(String),
+    /// A block, header, or chain fails validation.
+    InvalidBlock(String),
+    /// A checkpoint or balance snapshot is malformed, unsigned, or does not fit the chain.
+    InvalidCheckpoint(String),
+    /// The node could not produce a block: mining is paused, was cancelled, or has no producer.
+    Mining(String),
+    /// Reading or writing a chain store or block log failed.
+    Storage(String),
+}
+
+impl ChainError {
+    /// The message describing the failure, without the variant.
+    pub fn message(&self) -> &str {
+        match self {
+            ChainError::InvalidTransaction(message)
+            | ChainError::InsufficientBalance(message)
+            | ChainError::DoubleSpend(message)
+            | ChainError::Expired(message)
+            | ChainError::FeeTooLow(message)
+            | ChainError::MempoolFull(message)
+            | ChainError::NotFound(message)
+            | ChainError::InvalidBlock(message)
+            | ChainError::InvalidCheckpoint(message)
+            | ChainError::Mining(message)
+            | ChainError::Storage(message) => message,
+        }
+    }
+}
+
+impl fmt::Display for ChainError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+impl std::error::Error for ChainError {}
@@ -1,31 +1,92 @@
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use super::transaction::Transaction;
+use std::thread;
+
+// Levels with at least this many nodes are hashed across threads
+const PARALLEL_THRESHOLD: usize = 2048;
+
+/// Proof that a transaction is included under a Merkle root: the sibling hash at each level from
+/// the leaf up, and the leaf's position, whose bits say on which side each sibling is hashed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    pub leaf_index: usize,
+    pub siblings: Vec<Vec<u8>>,
+}
 
 pub struct MerkleTree {
     pub root: Vec<u8>,
-    nodes: Vec<Vec<u8>>,
+    // levels[0] holds the leaf hashes, the last level holds the root
+    levels: Vec<Vec<Vec<u8>>>,
 }
 
 impl MerkleTree {
     pub fn new(transactions: &[Transaction]) -> Self {
-        let mut nodes: Vec<Vec<u8>> = transactions.iter().map(|tx| tx.calculate_hash()).collect();
-        
-        // If there's an odd number of transactions, duplicate the last one
-        if nodes.len() % 2 != 0 {
-            nodes.push(nodes.last().unwrap().clone());
-        }
+        MerkleTree::from_leaves(transactions.iter().map(|tx| tx.calculate_hash()).collect())
+    }
+
+    /// Builds a tree from precomputed leaf hashes, e.g. cached transaction hashes. Large levels are
+    /// hashed in parallel on all available CPUs.
+    pub fn from_leaves(leaves: Vec<Vec<u8>>) -> Self {
+        MerkleTree::from_leaves_with_threads(leaves, num_cpus::get())
+    }
 
-        while nodes.len() > 1 {
-            nodes = MerkleTree::pair_and_hash(nodes);
+    /// Like `from_leaves`, with an explicit thread count; `1` builds the tree serially. The root
+    /// is identical for any thread count.
+    pub fn from_leaves_with_threads(leaves: Vec<Vec<u8>>, threads: usize) -> Self {
+        let mut tree = MerkleTree {
+            root: Vec::new(),
+            levels: vec![leaves],
+        };
+
+        while tree.needs_parent(tree.levels.len() - 1) {
+            let next_level = MerkleTree::pair_and_hash_parallel(tree.levels.last().unwrap(), threads);
+            tree.levels.push(next_level);
         }
+        tree.update_root();
+        tree
+    }
+
+    /// Appends a transaction, recomputing only the hashes on the path from the new leaf to the root.
+    pub fn append(&mut self, transaction: &Transaction) {
+        self.levels[0].push(transaction.calculate_hash());
+
+        let mut index = self.levels[0].len() - 1;
+        let mut level = 0;
+        while self.needs_parent(level) {
+            let parent_index = index / 2;
+            let left = &self.levels[level][parent_index * 2];
+            let right = self.levels[level].get(parent_index * 2 + 1).unwrap_or(left);
+            let parent = MerkleTree::hash_pair(left, right);
+
+            if self.levels.len() == level + 1 {
+                self.levels.push(Vec::new());
+            }
+            let parents = &mut self.levels[level + 1];
+            if parent_index < parents.len() {
+                parents[parent_index] = parent;
+            } else {
+                parents.push(parent);
+            }
 
-        MerkleTree {
-            root: nodes.first().cloned().unwrap_or_default(),
-            nodes,
+            index = parent_index;
+            level += 1;
         }
+        self.update_root();
+    }
+
+    // A level needs a parent until it is a single hash; a lone leaf is still hashed with itself once
+    fn needs_parent(&self, level: usize) -> bool {
+        let size = self.levels[level].len();
+        size > 1 || (level == 0 && size == 1)
     }
 
-    fn pair_and_hash(nodes: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+    fn update_root(&mut self) {
+        self.root = self.levels.last().and_then(|level| level.first()).cloned().unwrap_or_default();
+    }
+
+    // If there's an odd number of nodes, the last one is paired with itself
+    fn pair_and_hash(nodes: &[Vec<u8>]) -> Vec<Vec<u8>> {
         nodes.chunks(2).map(|chunk| {
             let left = &chunk[0];
             let right = chunk.get(1).unwrap_or(left);
@@ -33,6 +94,23 @@ impl MerkleTree {
         }).collect()
     }
 
+    // Splits the level on pair boundaries so each thread hashes whole pairs, then concatenates the
+    // results in order; only the final chunk can have an odd node, exactly as in the serial case
+    fn pair_and_hash_parallel(nodes: &[Vec<u8>], threads: usize) -> Vec<Vec<u8>> {
+        if threads <= 1 || nodes.len() < PARALLEL_THRESHOLD {
+            return MerkleTree::pair_and_hash(nodes);
+        }
+
+        let chunk_size = nodes.len().div_ceil(2).div_ceil(threads) * 2;
+        thread::scope(|scope| {
+            let handles: Vec<_> = nodes
+                .chunks(chunk_size)
+                .map(|chunk| scope.spawn(move || MerkleTree::pair_and_hash(chunk)))
+                .collect();
+            handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect()
+        })
+    }
+
     fn hash_pair(left: &[u8], right: &[u8]) -> Vec<u8> {
         let mut hasher = Sha256::new();
         hasher.update(left);
@@ -40,33 +118,33 @@ impl MerkleTree {
         hasher.finalize().to_vec()
     }
 
-    pub fn get_proof(&self, transaction: &Transaction) -> Option<Vec<Vec<u8>>> {
+    pub fn get_proof(&self, transaction: &Transaction) -> Option<MerkleProof> {
         let tx_hash = transaction.calculate_hash();
-        let mut index = self.nodes.iter().position(|hash| hash == &tx_hash)?;
-        let mut proof = Vec::new();
-        let mut level_size = self.nodes.len() / 2;
+        let leaf_index = self.levels[0].iter().position(|hash| hash == &tx_hash)?;
+        let mut index = leaf_index;
+        let mut siblings = Vec::new();
 
-        while level_size > 0 {
+        for level in &self.levels[..self.levels.len() - 1] {
             let sibling_index = if index % 2 == 0 { index + 1 } else { index - 1 };
-            if sibling_index < self.nodes.len() {
-                proof.push(self.nodes[sibling_index].clone());
-            }
+            siblings.push(level.get(sibling_index).unwrap_or(&level[index]).clone());
             index /= 2;
-            level_size /= 2;
         }
 
-        Some(proof)
+        Some(MerkleProof { leaf_index, siblings })
     }
 
-    pub fn verify_proof(root: &[u8], transaction: &Transaction, proof: &[Vec<u8>]) -> bool {
+    // Pairs are hashed left to right by position in the tree, exactly as when it was built
+    pub fn verify_proof(root: &[u8], transaction: &Transaction, proof: &MerkleProof) -> bool {
         let mut hash = transaction.calculate_hash();
-        for sibling in proof {
-            hash = if hash < *sibling {
+        let mut index = proof.leaf_index;
+        for sibling in &proof.siblings {
+            hash = if index.is_multiple_of(2) {
                 MerkleTree::hash_pair(&hash, sibling)
             } else {
                 MerkleTree::hash_pair(sibling, &hash)
             };
+            index /= 2;
         }
         hash == root
     }
-}
\ No newline at end of file
+}
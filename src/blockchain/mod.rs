@@ -1,8 +1,34 @@
+mod amount;
 mod block;
+mod builder;
+mod checkpoint;
+mod codec;
+mod error;
 mod transaction;
+#[allow(clippy::module_inception)]
 mod blockchain;
 mod merkle_tree;
+mod multisig;
+mod proof_of_work;
+mod signature_cache;
+mod stats;
+mod store;
+mod subscription;
+mod utxo;
 
-pub use block::Block;
-pub use transaction::Transaction;
-pub use blockchain::Blockchain;
\ No newline at end of file
+pub use amount::{Amount, BASE_UNITS_PER_COIN};
+pub use block::{Block, BlockHeader, U256};
+pub use builder::BlockchainBuilder;
+pub use checkpoint::{BalanceSnapshot, Checkpoint, SignedCheckpoints};
+pub use error::ChainError;
+pub use codec::{binary_size, decode_binary, encode_binary, BINARY_FORMAT_VERSION, BINARY_MAGIC};
+pub use transaction::{Transaction, LOCKTIME_THRESHOLD, MAX_TRANSACTION_DATA_LEN};
+pub use blockchain::{Blockchain, ConfirmationPolicy, Consensus, LedgerModel, RewardPolicy, SelectionPolicy};
+pub use merkle_tree::{MerkleProof, MerkleTree};
+pub use multisig::{multisig_address, MultisigPolicy};
+pub use proof_of_work::{compact_for_difficulty, compact_to_target, target_to_compact, ProofOfWork, Sha256ProofOfWork};
+pub use signature_cache::{clear_signature_cache, set_signature_cache_capacity, signature_verifications, DEFAULT_SIGNATURE_CACHE_CAPACITY};
+pub use stats::{ChainStats, FeeEfficiencyReport, NodeStatus};
+pub use store::{ChainStore, MemoryChainStore};
+pub use subscription::{ChainEvent, TransactionStatus, TransactionUpdate, WaitError};
+pub use utxo::{TxInput, TxOutput};
\ No newline at end of file
@@ -0,0 +1,55 @@
+use ring::signature::{UnparsedPublicKey, ED25519};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// The keys controlling a shared address and how many of them must sign to spend from it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MultisigPolicy {
+    pub threshold: usize,
+    pub public_keys: Vec<String>, // Hex Ed25519 public keys, kept sorted
+}
+
+impl MultisigPolicy {
+    /// Creates an M-of-N policy. The keys are sorted and deduplicated, so the same set always
+    /// yields the same address whatever order it is given in.
+    pub fn new(threshold: usize, mut public_keys: Vec<String>) -> Result<Self, String> {
+        public_keys.sort();
+        public_keys.dedup();
+        if threshold == 0 || threshold > public_keys.len() {
+            return Err(format!("Multisig threshold {} must be between 1 and the {} distinct keys", threshold, public_keys.len()));
+        }
+        Ok(MultisigPolicy { threshold, public_keys })
+    }
+
+    /// The address funds controlled by this policy are held at: the hex SHA-256 of the threshold
+    /// followed by the sorted public keys.
+    pub fn address(&self) -> String {
+        multisig_address(self.threshold, &self.public_keys)
+    }
+
+    // Counts the distinct keys with a valid signature over `message` among `signatures`
+    pub(crate) fn signers(&self, message: &[u8], signatures: &[String]) -> usize {
+        let signatures: Vec<Vec<u8>> = signatures.iter().filter_map(|signature| hex::decode(signature).ok()).collect();
+        self.public_keys
+            .iter()
+            .filter_map(|key| hex::decode(key).ok())
+            .filter(|key| {
+                let key = UnparsedPublicKey::new(&ED25519, key);
+                signatures.iter().any(|signature| key.verify(message, signature).is_ok())
+            })
+            .count()
+    }
+}
+
+/// Derives the multisig address for an M-of-N set of hex public keys; the keys are sorted first.
+pub fn multisig_address(threshold: usize, public_keys: &[String]) -> String {
+    let mut sorted = public_keys.to_vec();
+    sorted.sort();
+    sorted.dedup();
+    let mut hasher = Sha256::new();
+    hasher.update((threshold as u64).to_le_bytes());
+    for key in &sorted {
+        hasher.update(key.as_bytes());
+    }
+    hex::encode(hasher.finalize())
+}
@@ -0,0 +1,84 @@
+use super::block::{Block, U256};
+
+/// A pluggable proof-of-work scheme, consulted both when mining and when validating blocks.
+pub trait ProofOfWork: Send + Sync {
+    /// Returns the numeric target a block must meet at the given difficulty.
+    fn target_for(&self, difficulty: u32) -> U256;
+
+    /// Returns true if the block satisfies the proof-of-work at the given difficulty.
+    fn meets_target(&self, block: &Block, difficulty: u32) -> bool;
+}
+
+/// The default scheme: the full 256-bit SHA-256 block hash must not exceed the target. A block
+/// carrying compact `bits` must additionally meet the target they encode.
+///
+/// A difficulty of 0 means no proof-of-work is required, so any hash is accepted.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sha256ProofOfWork;
+
+impl ProofOfWork for Sha256ProofOfWork {
+    fn target_for(&self, difficulty: u32) -> U256 {
+        // Equivalent to `(1 << (256 - difficulty)) - 1`, without overflowing the shift at difficulty 0
+        if difficulty >= 256 { U256::zero() } else { U256::MAX >> difficulty as usize }
+    }
+
+    fn meets_target(&self, block: &Block, difficulty: u32) -> bool {
+        // A hash that is not 64 hex characters cannot meet any target
+        let hash = match hex::decode(&block.hash) {
+            Ok(bytes) if bytes.len() == 32 => U256::from_big_endian(&bytes),
+            _ => return false,
+        };
+        if hash > self.target_for(difficulty) {
+            return false;
+        }
+        block.bits == 0 || compact_to_target(block.bits).is_some_and(|target| hash <= target)
+    }
+}
+
+/// Expands a compact "bits" target, as in Bitcoin's `nBits`: the high byte is the length of the
+/// target in bytes and the low three bytes are its most significant bytes. Returns `None` for
+/// encodings with the sign bit set or that overflow 256 bits.
+pub fn compact_to_target(bits: u32) -> Option<U256> {
+    let size = bits >> 24;
+    let mantissa = bits & 0x007f_ffff;
+    if bits & 0x0080_0000 != 0 && mantissa != 0 {
+        return None;
+    }
+    if size <= 3 {
+        return Some(U256::from(mantissa >> (8 * (3 - size))));
+    }
+    let shift = 8 * (size - 3);
+    let target = U256::from(mantissa);
+    // The mantissa's significant bits must still fit once shifted into place
+    if mantissa != 0 && target.bits() as u32 + shift > 256 {
+        return None;
+    }
+    Some(target << shift as usize)
+}
+
+/// Encodes a target in compact form, keeping its three most significant bytes. The encoded
+/// target is never larger than the original, so meeting it also meets the original.
+pub fn target_to_compact(target: U256) -> u32 {
+    let mut size = target.bits().div_ceil(8) as u32;
+    let mut mantissa = if size <= 3 {
+        target.low_u32() << (8 * (3 - size))
+    } else {
+        (target >> (8 * (size - 3)) as usize).low_u32()
+    };
+    // The top mantissa bit is a sign bit, so move a set bit into the exponent instead
+    if mantissa & 0x0080_0000 != 0 {
+        mantissa >>= 8;
+        size += 1;
+    }
+    (size << 24) | mantissa
+}
+
+/// The compact bits stored in blocks mined at `difficulty` by the default scheme: the full-hash
+/// equivalent of `Sha256ProofOfWork::target_for`, rounded down to compact precision. Difficulty 0
+/// requires no proof-of-work, so it stores no target at all.
+pub fn compact_for_difficulty(difficulty: u32) -> u32 {
+    match difficulty {
+        0 => 0,
+        _ => target_to_compact(Sha256ProofOfWork.target_for(difficulty)),
+    }
+}
@@ -0,0 +1,94 @@
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Mutex, OnceLock};
+
+/// Verified signatures remembered by default.
+pub const DEFAULT_SIGNATURE_CACHE_CAPACITY: usize = 10_000;
+
+// (transaction id, hex signature, signed message hash)
+type CacheKey = (String, String, Vec<u8>);
+
+/// A least-recently-used cache of signature verification results. The key includes the signed
+/// message hash, so a transaction whose contents change after signing misses the cache rather than
+/// inheriting its old verdict.
+pub(crate) struct SignatureCache {
+    capacity: usize,
+    entries: HashMap<CacheKey, (bool, u64)>, // Verdict and the tick it was last used at
+    recency: BTreeMap<u64, CacheKey>,
+    tick: u64,
+}
+
+impl SignatureCache {
+    fn new(capacity: usize) -> Self {
+        SignatureCache { capacity, entries: HashMap::new(), recency: BTreeMap::new(), tick: 0 }
+    }
+
+    fn get(&mut self, key: &CacheKey) -> Option<bool> {
+        let (valid, last_used) = self.entries.get_mut(key)?;
+        let key = self.recency.remove(last_used).expect("Every entry has a recency tick");
+        self.tick += 1;
+        *last_used = self.tick;
+        let valid = *valid;
+        self.recency.insert(self.tick, key);
+        Some(valid)
+    }
+
+    fn insert(&mut self, key: CacheKey, valid: bool) {
+        if self.capacity == 0 {
+            return;
+        }
+        if let Some((_, last_used)) = self.entries.remove(&key) {
+            self.recency.remove(&last_used);
+        }
+        while self.entries.len() >= self.capacity {
+            let (_, oldest) = self.recency.pop_first().expect("A full cache has entries");
+            self.entries.remove(&oldest);
+        }
+        self.tick += 1;
+        self.entries.insert(key.clone(), (valid, self.tick));
+        self.recency.insert(self.tick, key);
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+    }
+}
+
+thread_local! {
+    static VERIFICATIONS: Cell<u64> = const { Cell::new(0) };
+}
+
+fn cache() -> &'static Mutex<SignatureCache> {
+    static CACHE: OnceLock<Mutex<SignatureCache>> = OnceLock::new();
+    CACHE.get_or_init(|| Mutex::new(SignatureCache::new(DEFAULT_SIGNATURE_CACHE_CAPACITY)))
+}
+
+/// Returns the cached verdict for a signature, or runs `verify` and caches its result.
+pub(crate) fn verify_cached(id: &str, signature: &str, message: &[u8], verify: impl FnOnce() -> bool) -> bool {
+    let key = (id.to_string(), signature.to_string(), message.to_vec());
+    if let Some(valid) = cache().lock().unwrap().get(&key) {
+        return valid;
+    }
+    VERIFICATIONS.with(|count| count.set(count.get() + 1));
+    let valid = verify();
+    cache().lock().unwrap().insert(key, valid);
+    valid
+}
+
+/// Sets how many verification results are remembered; 0 disables the cache. Clears the cache.
+pub fn set_signature_cache_capacity(capacity: usize) {
+    let mut cache = cache().lock().unwrap();
+    cache.clear();
+    cache.capacity = capacity;
+}
+
+/// Forgets every cached verification result.
+pub fn clear_signature_cache() {
+    cache().lock().unwrap().clear();
+}
+
+/// Number of signature verifications actually performed, i.e. cache misses, on the calling thread.
+pub fn signature_verifications() -> u64 {
+    VERIFICATIONS.with(Cell::get)
+}
@@ -0,0 +1,61 @@
+use super::amount::Amount;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Serialize, Serializer};
+
+/// Aggregate statistics over a window of blocks, similar to Bitcoin's `getchaintxstats`, or over
+/// the whole chain from `Blockchain::stats`.
+///
+/// Coinbase transactions are excluded from the transaction, volume, and fee figures; what they
+/// paid out is `total_issued`. Difficulty and mempool figures are always current.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChainStats {
+    pub block_count: usize,
+    pub transaction_count: usize,
+    pub total_volume: Amount,
+    pub total_fees: Amount,
+    pub total_issued: Amount,
+    pub average_fee_rate: f64,
+    #[serde(rename = "average_block_time_ms", serialize_with = "serialize_millis")]
+    pub average_block_time: Duration,
+    pub difficulty: u32,
+    pub mempool_transactions: usize,
+    pub mempool_bytes: usize,
+}
+
+fn serialize_millis<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_i64(duration.num_milliseconds())
+}
+
+/// Result of `Blockchain::analyze_block_fee_efficiency`. Purely analytical; nothing here affects
+/// block validity.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeeEfficiencyReport {
+    pub block_index: u64,
+    /// Fee rate of the cheapest non-coinbase transaction in the block, if it has any.
+    pub lowest_included_fee_rate: Option<f64>,
+    /// Mempool transactions that were already known when the block was assembled and pay a higher
+    /// fee rate than something the block included.
+    pub skipped_transactions: Vec<String>,
+}
+
+impl FeeEfficiencyReport {
+    /// True when the block included a cheaper transaction over a better-paying one, which may
+    /// indicate censorship.
+    pub fn has_inversions(&self) -> bool {
+        !self.skipped_transactions.is_empty()
+    }
+}
+
+/// Node health at a glance, as returned by `Blockchain::status` for a monitoring endpoint.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeStatus {
+    pub height: u64,
+    pub tip_hash: String,
+    pub difficulty: u32,
+    pub mempool_transactions: usize,
+    pub mempool_bytes: usize,
+    pub last_block_time: DateTime<Utc>,
+    pub chain_valid: bool,
+    /// Connected peers; only known when the status comes from `Node::status`.
+    pub peer_count: Option<usize>,
+}
@@ -0,0 +1,58 @@
+use super::block::Block;
+
+/// A storage backend for blocks, so a node can keep its chain in a database such as sled or
+/// RocksDB instead of only in memory.
+pub trait ChainStore: Send {
+    /// Stores the block at its index, replacing any block already stored there.
+    fn put_block(&mut self, block: &Block) -> Result<(), String>;
+
+    fn get_block(&self, index: u64) -> Option<Block>;
+
+    /// Drops every block stored at index `len` or above, e.g. after the chain was replaced by a
+    /// shorter one.
+    fn truncate(&mut self, len: u64) -> Result<(), String>;
+
+    /// Number of consecutive blocks stored, starting from the genesis block.
+    fn len(&self) -> u64;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// The default in-memory store, backed by a `Vec`.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryChainStore {
+    blocks: Vec<Block>,
+}
+
+impl MemoryChainStore {
+    pub fn new() -> Self {
+        MemoryChainStore::default()
+    }
+}
+
+impl ChainStore for MemoryChainStore {
+    fn put_block(&mut self, block: &Block) -> Result<(), String> {
+        let index = block.index as usize;
+        if index > self.blocks.len() {
+            return Err(format!("Cannot store block {} before block {}", index, self.blocks.len()));
+        }
+        self.blocks.truncate(index);
+        self.blocks.push(block.clone());
+        Ok(())
+    }
+
+    fn get_block(&self, index: u64) -> Option<Block> {
+        self.blocks.get(index as usize).cloned()
+    }
+
+    fn truncate(&mut self, len: u64) -> Result<(), String> {
+        self.blocks.truncate(len as usize);
+        Ok(())
+    }
+
+    fn len(&self) -> u64 {
+        self.blocks.len() as u64
+    }
+}
@@ -0,0 +1,104 @@
+use super::block::Block;
+use super::transaction::Transaction;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::mpsc::{self, Receiver, Sender};
+
+/// Where a transaction stands, as pushed to the subscribers of its sender and recipient.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionStatus {
+    /// Accepted into the mempool.
+    Pending,
+    /// Included in the chain; a depth of 1 means it is in the tip block.
+    Confirmed(u64),
+    /// Was confirmed, but a reorg replaced the block containing it. It may be double-spent.
+    Reorged,
+}
+
+/// A status change of one transaction, as received from `Blockchain::subscribe_address`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransactionUpdate {
+    pub transaction_id: String,
+    pub status: TransactionStatus,
+}
+
+// Open subscriptions by watched address; subscribers that dropped their receiver are forgotten
+#[derive(Default)]
+pub(crate) struct AddressSubscriptions {
+    senders: HashMap<String, Vec<Sender<TransactionUpdate>>>,
+}
+
+impl AddressSubscriptions {
+    pub(crate) fn subscribe(&mut self, address: &str) -> Receiver<TransactionUpdate> {
+        let (sender, receiver) = mpsc::channel();
+        self.senders.entry(address.to_string()).or_default().push(sender);
+        receiver
+    }
+
+    pub(crate) fn is_watching(&self, transaction: &Transaction) -> bool {
+        self.senders.contains_key(&transaction.from)
+            || transaction.payments().iter().any(|payment| self.senders.contains_key(&payment.address))
+    }
+
+    pub(crate) fn notify(&mut self, transaction: &Transaction, status: TransactionStatus) {
+        let update = TransactionUpdate { transaction_id: transaction.id.clone(), status };
+        let mut addresses = vec![transaction.from.clone()];
+        for payment in transaction.payments() {
+            if !addresses.contains(&payment.address) {
+                addresses.push(payment.address);
+            }
+        }
+        for address in &addresses {
+            if let Some(senders) = self.senders.get_mut(address) {
+                senders.retain(|sender| sender.send(update.clone()).is_ok());
+                if senders.is_empty() {
+                    self.senders.remove(address);
+                }
+            }
+        }
+    }
+}
+
+/// Something that happened to the chain or mempool, as received from `Blockchain::subscribe_events`.
+#[derive(Debug, Clone)]
+pub enum ChainEvent {
+    /// A block was added to the chain, whether mined locally or received.
+    NewBlock(Block),
+    /// A transaction was accepted into the mempool.
+    NewTransaction(Transaction),
+}
+
+/// Why `Blockchain::wait_for_confirmation` returned before the transaction was deep enough.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitError {
+    /// The timeout passed first; carries the depth reached by then, 0 if still unconfirmed.
+    Timeout(u64),
+}
+
+impl fmt::Display for WaitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WaitError::Timeout(depth) => write!(f, "Timed out at a depth of {}", depth),
+        }
+    }
+}
+
+impl std::error::Error for WaitError {}
+
+// Fans every event out to all open subscribers, each receiving its own copy
+#[derive(Default)]
+pub(crate) struct EventBroadcast {
+    senders: Vec<Sender<ChainEvent>>,
+}
+
+impl EventBroadcast {
+    pub(crate) fn subscribe(&mut self) -> Receiver<ChainEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.senders.push(sender);
+        receiver
+    }
+
+    pub(crate) fn publish(&mut self, event: ChainEvent) {
+        self.senders.retain(|sender| sender.send(event.clone()).is_ok());
+    }
+}
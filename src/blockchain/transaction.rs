@@ -3,21 +3,74 @@ use sha2::{Digest, Sha256};
 use ring::signature::Ed25519KeyPair;
 
 use uuid::Uuid;
+use super::amount::Amount;
 use crate::utils::Logger;
+use super::blockchain::DEFAULT_CHAIN_ID;
+use super::signature_cache::verify_cached;
+use super::multisig::MultisigPolicy;
+use super::utxo::{TxInput, TxOutput};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Transaction {
     pub id: String,
     pub from: String,
     pub to: String,
-    pub amount: f64,
-    pub fee: f64,
+    pub amount: Amount,
+    pub fee: Amount,
     pub timestamp: i64,
     pub expiration: i64,
+    #[serde(default)]
+    pub nonce: u64, // Position among the sender's transactions; each value may be used once
+    #[serde(default)]
+    pub locktime: i64, // Earliest block height, or Unix timestamp from `LOCKTIME_THRESHOLD` up, that may include it
+    #[serde(default = "default_chain_id")]
+    pub target_chain_id: u64, // The only chain whose mempool accepts this transaction
+    #[serde(default)]
+    pub inputs: Vec<TxInput>, // Outputs spent by this transaction; used by the UTXO ledger model
+    #[serde(default)]
+    pub outputs: Vec<TxOutput>, // Payments made; when empty, the whole amount goes to `to`
+    #[serde(default)]
+    pub multisig: Option<MultisigPolicy>, // Set when `from` is a multisig address
+    #[serde(default)]
+    pub signatures: Vec<String>, // Signatures over `serialize_for_signing` by the multisig keys
+    #[serde(default)]
+    pub data: Option<Vec<u8>>, // Memo or reference attached by the sender, at most `MAX_TRANSACTION_DATA_LEN` bytes
     pub signature: Option<String>,
 }
+
+const ED25519_PUBLIC_KEY_LEN: usize = 32;
+/// Locktimes below this are block heights; from it up they are Unix timestamps in seconds.
+pub const LOCKTIME_THRESHOLD: i64 = 500_000_000;
+/// Largest `data` payload the mempool accepts, in bytes.
+pub const MAX_TRANSACTION_DATA_LEN: usize = 256;
+
+// Amounts are hashed as their count of base units rather than their decimal formatting, which
+// is not guaranteed to be stable. Transactions signed before this encoding no longer verify and
+// must be re-signed.
+fn amount_bytes(amount: Amount) -> [u8; 8] {
+    amount.base_units().to_le_bytes()
+}
+
+// A presence flag and length prefix keep no data, empty data and payloads that happen to
+// resemble other fields from hashing alike
+fn data_bytes(data: &Option<Vec<u8>>) -> Vec<u8> {
+    match data {
+        None => vec![0],
+        Some(data) => {
+            let mut bytes = vec![1];
+            bytes.extend_from_slice(&(data.len() as u64).to_le_bytes());
+            bytes.extend_from_slice(data);
+            bytes
+        }
+    }
+}
+
+fn default_chain_id() -> u64 {
+    DEFAULT_CHAIN_ID
+}
+
 impl Transaction {
-    pub fn new(from: String, to: String, amount: f64, fee: f64) -> Self {
+    pub fn new(from: String, to: String, amount: Amount, fee: Amount) -> Self {
         Logger::transaction(&format!("Creating new transaction: {} -> {}, amount: {}, fee: {}", from, to, amount, fee));
         Transaction {
             id: Uuid::new_v4().to_string(),
@@ -27,17 +80,106 @@ impl Transaction {
             fee,
             timestamp: chrono::Utc::now().timestamp(),
             expiration: chrono::Utc::now().timestamp() + 3600, // Set expiration to 1 hour from now
+            nonce: 0,
+            locktime: 0,
+            target_chain_id: DEFAULT_CHAIN_ID,
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            multisig: None,
+            signatures: Vec::new(),
+            data: None,
             signature: None,
         }
     }
 
+    /// Creates a transaction paying every `(address, amount)` in `recipients` from one sender. It
+    /// is signed and applied as a unit, so either every recipient is paid or none is.
+    pub fn new_multi(from: String, recipients: Vec<(String, Amount)>, fee: Amount) -> Self {
+        let outputs = recipients.into_iter().map(|(address, amount)| TxOutput { address, amount }).collect();
+        Transaction::with_outputs(from, outputs, fee)
+    }
+
+    /// Creates a transaction spending from the address of `policy`. It becomes valid once at
+    /// least `policy.threshold` of its keys have signed it with `add_signature`.
+    pub fn new_multisig(policy: MultisigPolicy, to: String, amount: Amount, fee: Amount) -> Self {
+        let mut transaction = Transaction::new(policy.address(), to, amount, fee);
+        transaction.multisig = Some(policy);
+        transaction
+    }
+
+    /// Creates a transaction spending `inputs` into `outputs` under the UTXO ledger model. The
+    /// inputs must hold exactly the amount plus `fee`, so any change is paid back to the sender as an output.
+    pub fn new_utxo(from: String, inputs: Vec<TxInput>, outputs: Vec<TxOutput>, fee: Amount) -> Self {
+        let mut transaction = Transaction::with_outputs(from, outputs, fee);
+        transaction.inputs = inputs;
+        transaction
+    }
+
+    // The amount is the sum of the outputs and `to` is the first output's address
+    fn with_outputs(from: String, outputs: Vec<TxOutput>, fee: Amount) -> Self {
+        let to = outputs.first().map(|output| output.address.clone()).unwrap_or_default();
+        let amount = outputs.iter().map(|output| output.amount).sum();
+        let mut transaction = Transaction::new(from, to, amount, fee);
+        transaction.outputs = outputs;
+        transaction
+    }
+
+    /// The payments this transaction makes: its outputs, or the whole amount to `to` if it has none.
+    pub fn payments(&self) -> Vec<TxOutput> {
+        if self.outputs.is_empty() {
+            vec![TxOutput { address: self.to.clone(), amount: self.amount }]
+        } else {
+            self.outputs.clone()
+        }
+    }
+
+    /// True if a block at `height` with the given Unix `timestamp` may include this transaction.
+    /// A locktime of zero or less never locks.
+    pub fn is_final(&self, height: u64, timestamp: i64) -> bool {
+        if self.locktime <= 0 {
+            true
+        } else if self.locktime < LOCKTIME_THRESHOLD {
+            self.locktime as u64 <= height
+        } else {
+            self.locktime <= timestamp
+        }
+    }
+
+    /// True if any of this transaction's payments goes to `address`.
+    pub fn pays(&self, address: &str) -> bool {
+        if self.outputs.is_empty() {
+            self.to == address
+        } else {
+            self.outputs.iter().any(|output| output.address == address)
+        }
+    }
+
+    /// True if every payment goes back to the sender, so the transaction moves no value.
+    pub fn is_self_send(&self) -> bool {
+        self.payments().iter().all(|payment| payment.address == self.from)
+    }
+
     pub fn calculate_hash(&self) -> Vec<u8> {
         let mut hasher = Sha256::new();
         hasher.update(self.id.as_bytes());
         hasher.update(self.from.as_bytes());
         hasher.update(self.to.as_bytes());
-        hasher.update(self.amount.to_string().as_bytes());
-        hasher.update(self.timestamp.to_string().as_bytes());
+        hasher.update(amount_bytes(self.amount));
+        hasher.update(amount_bytes(self.fee));
+        hasher.update(self.timestamp.to_le_bytes());
+        hasher.update(self.expiration.to_le_bytes());
+        hasher.update(self.nonce.to_le_bytes());
+        hasher.update(self.locktime.to_le_bytes());
+        hasher.update(self.target_chain_id.to_le_bytes());
+        for input in &self.inputs {
+            hasher.update(input.prev_tx_id.as_bytes());
+            hasher.update(input.output_index.to_le_bytes());
+        }
+        for output in &self.outputs {
+            hasher.update(output.address.as_bytes());
+            hasher.update(amount_bytes(output.amount));
+        }
+        hasher.update(data_bytes(&self.data));
         hasher.finalize().to_vec()
     }
 
@@ -46,28 +188,69 @@ impl Transaction {
         data.extend_from_slice(self.id.as_bytes());
         data.extend_from_slice(self.from.as_bytes());
         data.extend_from_slice(self.to.as_bytes());
-        data.extend_from_slice(self.amount.to_string().as_bytes());
-        data.extend_from_slice(self.timestamp.to_string().as_bytes());
+        data.extend_from_slice(&amount_bytes(self.amount));
+        data.extend_from_slice(&amount_bytes(self.fee));
+        data.extend_from_slice(&self.timestamp.to_le_bytes());
+        data.extend_from_slice(&self.expiration.to_le_bytes());
+        data.extend_from_slice(&self.nonce.to_le_bytes());
+        data.extend_from_slice(&self.locktime.to_le_bytes());
+        data.extend_from_slice(&self.target_chain_id.to_le_bytes());
+        for input in &self.inputs {
+            data.extend_from_slice(input.prev_tx_id.as_bytes());
+            data.extend_from_slice(&input.output_index.to_le_bytes());
+        }
+        for output in &self.outputs {
+            data.extend_from_slice(output.address.as_bytes());
+            data.extend_from_slice(&amount_bytes(output.amount));
+        }
+        data.extend_from_slice(&data_bytes(&self.data));
         data
     }
 
     pub fn is_valid(&self) -> bool {
+        // Reject totals that overflow up front so the ledger's arithmetic below can't wrap
+        if self.amount.checked_add(self.fee).is_none() {
+            return false;
+        }
+
+        // Explicit outputs must be positive and account for the whole amount
+        if !self.outputs.is_empty() {
+            if self.outputs.iter().any(|output| output.amount == Amount::ZERO) {
+                return false;
+            }
+            if Amount::checked_sum(self.outputs.iter().map(|output| &output.amount)) != Some(self.amount) {
+                return false;
+            }
+        }
+
         if self.from == "Blockchain" {
             // This is a mining reward transaction, no signature needed
             return true;
         }
     
-        if self.amount <= 0.0 {
+        if self.amount == Amount::ZERO || self.is_self_send() {
             return false;
         }
+
+        if let Some(policy) = &self.multisig {
+            return self.from == policy.address()
+                && policy.signers(&self.serialize_for_signing(), &self.signatures) >= policy.threshold;
+        }
     
         if let Some(signature) = &self.signature {
             let message = self.calculate_hash();
-            let public_key = hex::decode(&self.from).unwrap();
-            let signature = hex::decode(signature).unwrap();
-            ring::signature::UnparsedPublicKey::new(&ring::signature::ED25519, &public_key)
-                .verify(&message, &signature)
-                .is_ok()
+            verify_cached(&self.id, signature, &message, || {
+                // Malformed input from the network is rejected rather than allowed to panic the node
+                let (Ok(public_key), Ok(signature)) = (hex::decode(&self.from), hex::decode(signature)) else {
+                    return false;
+                };
+                if public_key.len() != ED25519_PUBLIC_KEY_LEN {
+                    return false;
+                }
+                ring::signature::UnparsedPublicKey::new(&ring::signature::ED25519, &public_key)
+                    .verify(&message, &signature)
+                    .is_ok()
+            })
         } else {
             false
         }
@@ -79,4 +262,11 @@ impl Transaction {
         let signature = key_pair.sign(&message);
         self.signature = Some(hex::encode(signature.as_ref()));
     }
+
+    /// Adds one key holder's signature to a multisig transaction.
+    pub fn add_signature(&mut self, key_pair: &Ed25519KeyPair) {
+        Logger::transaction(&format!("Adding multisig signature to transaction: {}", self.id));
+        let signature = key_pair.sign(&self.serialize_for_signing());
+        self.signatures.push(hex::encode(signature.as_ref()));
+    }
 }
\ No newline at end of file
@@ -0,0 +1,68 @@
+use super::amount::Amount;
+use super::transaction::Transaction;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Points at an output of an earlier transaction, which the transaction carrying it spends.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct TxInput {
+    pub prev_tx_id: String,
+    pub output_index: u32,
+}
+
+/// An amount paid to an address by a transaction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxOutput {
+    pub address: String,
+    pub amount: Amount,
+}
+
+// Unspent outputs, keyed by the input that would spend them
+#[derive(Debug, Clone, Default)]
+pub(crate) struct UtxoSet {
+    outputs: HashMap<TxInput, TxOutput>,
+}
+
+impl UtxoSet {
+    pub(crate) fn get(&self, input: &TxInput) -> Option<&TxOutput> {
+        self.outputs.get(input)
+    }
+
+    pub(crate) fn remove(&mut self, input: &TxInput) -> Option<TxOutput> {
+        self.outputs.remove(input)
+    }
+
+    pub(crate) fn insert(&mut self, input: TxInput, output: TxOutput) {
+        self.outputs.insert(input, output);
+    }
+
+    // True if any output created by `tx_id` is still unspent
+    pub(crate) fn contains_transaction(&self, tx_id: &str) -> bool {
+        self.outputs.keys().any(|input| input.prev_tx_id == tx_id)
+    }
+
+    pub(crate) fn balance_of(&self, address: &str) -> Amount {
+        self.outputs.values().filter(|output| output.address == address).map(|output| output.amount).sum()
+    }
+
+    // Spends the transaction's inputs and adds its payments as new outputs, numbered in order
+    pub(crate) fn apply(&mut self, transaction: &Transaction) {
+        for input in &transaction.inputs {
+            self.outputs.remove(input);
+        }
+        for (index, output) in transaction.payments().into_iter().enumerate() {
+            let input = TxInput { prev_tx_id: transaction.id.clone(), output_index: index as u32 };
+            self.outputs.insert(input, output);
+        }
+    }
+
+    pub(crate) fn unspent_for(&self, address: &str) -> Vec<(TxInput, TxOutput)> {
+        let mut unspent: Vec<(TxInput, TxOutput)> = self.outputs
+            .iter()
+            .filter(|(_, output)| output.address == address)
+            .map(|(input, output)| (input.clone(), output.clone()))
+            .collect();
+        unspent.sort_by(|a, b| a.0.cmp(&b.0));
+        unspent
+    }
+}
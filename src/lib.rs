@@ -1,2 +1,6 @@
+#![allow(non_snake_case)]
+
 pub mod blockchain;
+pub mod net;
+pub mod rpc;
 pub mod utils;
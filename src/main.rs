@@ -1,4 +1,6 @@
-use KrakenChain::blockchain::{Blockchain, Transaction};
+#![allow(non_snake_case)]
+
+use KrakenChain::blockchain::{Amount, Blockchain, Transaction};
 use chrono::Duration;
 use ring::signature::KeyPair;
 
@@ -11,9 +13,13 @@ fn create_keypair() -> (ring::signature::Ed25519KeyPair, String) {
     (key_pair, address)
 }
 
+fn coins(coins: f64) -> Amount {
+    Amount::from_coins(coins).expect("demo amounts are valid")
+}
+
 fn main() {
     // Create a new blockchain
-    let mut blockchain = Blockchain::new(4, 10.0, Duration::seconds(10));
+    let mut blockchain = Blockchain::new(4, coins(10.0), Duration::seconds(10));
 
     // Create some keypairs for testing
     let (alice_key, alice_address) = create_keypair();
@@ -21,8 +27,8 @@ fn main() {
     let (charlie_key, charlie_address) = create_keypair();
 
     // Add some initial balance to Alice and Bob
-    blockchain.add_balance(&alice_address, 100.0);
-    blockchain.add_balance(&bob_address, 50.0);
+    blockchain.add_balance(&alice_address, coins(100.0));
+    blockchain.add_balance(&bob_address, coins(50.0));
 
     println!("Initial balances:");
     println!("Alice: {}", blockchain.get_balance(&alice_address));
@@ -30,15 +36,15 @@ fn main() {
     println!("Charlie: {}", blockchain.get_balance(&charlie_address));
 
     // Create and add transactions to mempool
-    let mut tx1 = Transaction::new(alice_address.clone(), bob_address.clone(), 30.0, 0.1);
+    let mut tx1 = Transaction::new(alice_address.clone(), bob_address.clone(), coins(30.0), coins(0.1));
     tx1.sign(&alice_key);
     blockchain.add_to_mempool(tx1).unwrap();
 
-    let mut tx2 = Transaction::new(bob_address.clone(), charlie_address.clone(), 15.0, 0.1);
+    let mut tx2 = Transaction::new(bob_address.clone(), charlie_address.clone(), coins(15.0), coins(0.1));
     tx2.sign(&bob_key);
     blockchain.add_to_mempool(tx2).unwrap();
 
-    let mut tx3 = Transaction::new(alice_address.clone(), charlie_address.clone(), 20.0, 0.1);
+    let mut tx3 = Transaction::new(alice_address.clone(), charlie_address.clone(), coins(20.0), coins(0.1));
     tx3.sign(&alice_key);
     blockchain.add_to_mempool(tx3).unwrap();
 
@@ -56,11 +62,11 @@ fn main() {
     println!("\nIs blockchain valid? {}", blockchain.validate_chain());
 
     // Add more transactions
-    let mut tx4 = Transaction::new(charlie_address.clone(), alice_address.clone(), 5.0, 0.1);
+    let mut tx4 = Transaction::new(charlie_address.clone(), alice_address.clone(), coins(5.0), coins(0.1));
     tx4.sign(&charlie_key);
     blockchain.add_to_mempool(tx4).unwrap();
 
-    let mut tx5 = Transaction::new(bob_address.clone(), alice_address.clone(), 10.0, 0.1);
+    let mut tx5 = Transaction::new(bob_address.clone(), alice_address.clone(), coins(10.0), coins(0.1));
     tx5.sign(&bob_key);
     blockchain.add_to_mempool(tx5).unwrap();
 
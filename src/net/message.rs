@@ -0,0 +1,27 @@
+use crate::blockchain::{Block, BlockHeader, Transaction};
+use serde::{Deserialize, Serialize};
+
+/// Protocol version this node speaks, announced in the `Version` handshake.
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Oldest peer protocol version this node still accepts.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Most headers (and blocks requested by index) sent in a single message.
+pub const MAX_HEADERS_PER_MESSAGE: usize = 2000;
+
+/// Messages exchanged between nodes, each sent as one JSON frame.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum NetMessage {
+    /// The first message each side sends on a new connection.
+    Version { protocol_version: u32, chain_id: u64, tip_index: u64 },
+    NewTransaction(Transaction),
+    NewBlock(Block),
+    /// Requests up to `MAX_HEADERS_PER_MESSAGE` blocks from the given index toward the sender's tip.
+    GetBlocks { from_index: u64 },
+    Blocks(Vec<Block>),
+    /// Requests up to `MAX_HEADERS_PER_MESSAGE` headers starting at the given index.
+    GetHeaders { from_index: u64 },
+    Headers(Vec<BlockHeader>),
+    /// Requests the full blocks at the given indices, answered with `Blocks`.
+    GetBlocksByIndex(Vec<u64>),
+}
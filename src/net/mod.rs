@@ -0,0 +1,32 @@
+//! Peer-to-peer networking. Every message is a `NetMessage` encoded as JSON inside a frame of a
+//! big-endian `u32` length followed by the payload.
+//!
+//! On connect, both sides first send `Version` with their protocol version, chain ID, and tip
+//! index. A peer on another chain, or with an unsupported protocol version, is disconnected
+//! before any other message is exchanged. If the peer's tip is ahead, a sync starts right away.
+//!
+//! Peers that send malformed messages, blocks failing their own hash or proof-of-work, or
+//! transactions failing `Transaction::is_valid` accumulate a `PeerScore`. Crossing
+//! `BAN_THRESHOLD` disconnects the peer and bans its IP address for a while.
+//!
+//! Transactions and blocks accepted by a node are relayed to all of its other peers with
+//! `NewTransaction` and `NewBlock`. A node that falls behind, either because `Node::sync` was
+//! called or because a `NewBlock` arrived ahead of its tip, catches up headers-first:
+//!
+//! 1. It sends `GetHeaders { from_index: tip + 1 }` to the peer.
+//! 2. The peer answers with `Headers`, at most `MAX_HEADERS_PER_MESSAGE` at a time.
+//! 3. The node checks linkage and proof-of-work of the headers with
+//!    `Blockchain::validate_headers`, which needs no transactions.
+//! 4. Only then does it request the full blocks with `GetBlocksByIndex`, answered with `Blocks`,
+//!    which are fully validated as they are added.
+//! 5. If the batch was full, it asks for the next headers from its new tip.
+
+mod frame;
+mod message;
+mod node;
+mod peer;
+
+pub use frame::{read_frame, write_frame, MAX_FRAME_LEN};
+pub use message::{NetMessage, MAX_HEADERS_PER_MESSAGE, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+pub use node::Node;
+pub use peer::{PeerScore, Violation, BAN_THRESHOLD};
@@ -0,0 +1,393 @@
+use super::frame::{read_frame, write_frame};
+use super::message::{NetMessage, MAX_HEADERS_PER_MESSAGE, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+use super::peer::{PeerScore, Violation};
+use crate::blockchain::{Block, Blockchain, ChainError, NodeStatus, Transaction};
+use crate::utils::Logger;
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const BAN_DURATION: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A peer-to-peer node sharing a mempool and chain with its peers. Each message is a
+/// length-prefixed JSON `NetMessage`; transactions and blocks accepted locally are relayed to every
+/// other peer, and a block ahead of the local tip triggers a sync from the peer that sent it.
+pub struct Node {
+    inner: Arc<NodeInner>,
+    local_addr: SocketAddr,
+}
+
+struct NodeInner {
+    blockchain: Arc<Mutex<Blockchain>>,
+    peers: Mutex<HashMap<u64, Peer>>, // Peers that completed the handshake, by peer id
+    bans: Mutex<HashMap<IpAddr, Instant>>, // Banned addresses and when their ban expires
+    next_peer_id: AtomicU64,
+}
+
+struct Peer {
+    stream: TcpStream, // Kept to shut the connection down even while a write is pending
+    writer: Arc<Mutex<TcpStream>>, // Write half, locked per frame so frames never interleave
+    addr: SocketAddr,
+    score: PeerScore,
+}
+
+impl Node {
+    /// Binds a listener on `addr` and starts accepting peer connections in the background.
+    pub fn bind<A: ToSocketAddrs>(addr: A, blockchain: Arc<Mutex<Blockchain>>) -> io::Result<Node> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let inner = Arc::new(NodeInner {
+            blockchain,
+            peers: Mutex::new(HashMap::new()),
+            bans: Mutex::new(HashMap::new()),
+            next_peer_id: AtomicU64::new(0),
+        });
+
+        let accepting = Arc::clone(&inner);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        if let Err(e) = NodeInner::add_peer(&accepting, stream) {
+                            Logger::error(&format!("Failed to register incoming peer: {}", e));
+                        }
+                    }
+                    Err(e) => Logger::error(&format!("Failed to accept peer connection: {}", e)),
+                }
+            }
+        });
+
+        Logger::info(&format!("Node listening on {}", local_addr));
+        Ok(Node { inner, local_addr })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub fn blockchain(&self) -> Arc<Mutex<Blockchain>> {
+        Arc::clone(&self.inner.blockchain)
+    }
+
+    pub fn peer_count(&self) -> usize {
+        self.inner.peers.lock().unwrap().len()
+    }
+
+    /// The blockchain's status, with the number of connected peers filled in.
+    pub fn status(&self) -> NodeStatus {
+        let mut status = self.inner.blockchain.lock().unwrap().status();
+        status.peer_count = Some(self.peer_count());
+        status
+    }
+
+    /// Addresses currently banned for misbehavior.
+    pub fn banned_peers(&self) -> Vec<IpAddr> {
+        let mut bans = self.inner.bans.lock().unwrap();
+        let now = Instant::now();
+        bans.retain(|_, expires| *expires > now);
+        bans.keys().copied().collect()
+    }
+
+    /// Lifts a ban early. Returns false if the address was not banned.
+    pub fn unban(&self, addr: IpAddr) -> bool {
+        let lifted = self.inner.bans.lock().unwrap().remove(&addr).is_some();
+        if lifted {
+            Logger::info(&format!("Unbanned {}", addr));
+        }
+        lifted
+    }
+
+    /// Opens an outbound connection to another node.
+    pub fn connect<A: ToSocketAddrs>(&self, addr: A) -> io::Result<()> {
+        let stream = TcpStream::connect(addr)?;
+        NodeInner::add_peer(&self.inner, stream)
+    }
+
+    /// Adds a locally created transaction to the mempool and relays it to all peers.
+    pub fn submit_transaction(&self, transaction: Transaction) -> Result<(), ChainError> {
+        self.inner.blockchain.lock().unwrap().add_to_mempool(transaction.clone())?;
+        self.inner.relay(&NetMessage::NewTransaction(transaction), None);
+        Ok(())
+    }
+
+    /// Asks every peer for the headers beyond the local tip, starting a headers-first sync.
+    pub fn sync(&self) {
+        let from_index = next_index(&self.inner.blockchain.lock().unwrap());
+        self.inner.relay(&NetMessage::GetHeaders { from_index }, None);
+    }
+
+    /// Mines the pending transactions into a new block and announces it to all peers.
+    pub fn mine_pending_transactions(&self, miner_address: &str) -> Result<Block, ChainError> {
+        let block = {
+            let mut blockchain = self.inner.blockchain.lock().unwrap();
+            blockchain.mine_pending_transactions(miner_address)?;
+            blockchain.try_get_latest_block().cloned()
+                .ok_or_else(|| ChainError::NotFound("Blockchain is empty".to_string()))?
+        };
+        self.inner.relay(&NetMessage::NewBlock(block.clone()), None);
+        Ok(block)
+    }
+}
+
+impl NodeInner {
+    fn add_peer(inner: &Arc<NodeInner>, mut stream: TcpStream) -> io::Result<()> {
+        let peer_addr = stream.peer_addr()?;
+        if inner.is_banned(peer_addr.ip()) {
+            let _ = stream.shutdown(Shutdown::Both);
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("{} is banned", peer_addr.ip())));
+        }
+        let mut reader = stream.try_clone()?;
+        let writer = Arc::new(Mutex::new(stream.try_clone()?));
+        let peer_id = inner.next_peer_id.fetch_add(1, Ordering::Relaxed);
+        let version = encode_message(&inner.version_message())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Failed to encode version message"))?;
+        write_frame(&mut stream, &version)?;
+
+        let inner = Arc::clone(inner);
+        thread::spawn(move || {
+            match inner.await_handshake(peer_id, &mut reader) {
+                Ok(peer_tip) => {
+                    inner.peers.lock().unwrap().insert(peer_id, Peer { stream, writer, addr: peer_addr, score: PeerScore::default() });
+                    Logger::info(&format!("Connected to peer {} ({})", peer_id, peer_addr));
+                    let from_index = next_index(&inner.blockchain.lock().unwrap());
+                    if peer_tip >= from_index {
+                        inner.send_to(peer_id, &NetMessage::GetHeaders { from_index });
+                    }
+                    inner.read_from_peer(peer_id, reader);
+                }
+                Err(e) => {
+                    Logger::error(&format!("Refused peer {} ({}): {}", peer_id, peer_addr, e));
+                    let _ = stream.shutdown(Shutdown::Both);
+                }
+            }
+        });
+        Ok(())
+    }
+
+    fn is_banned(&self, addr: IpAddr) -> bool {
+        self.bans.lock().unwrap().get(&addr).is_some_and(|expires| *expires > Instant::now())
+    }
+
+    // Adds the violation to the peer's score, disconnecting and banning it past the threshold
+    fn penalize(&self, peer_id: u64, violation: Violation) {
+        let mut peers = self.peers.lock().unwrap();
+        let Some(peer) = peers.get_mut(&peer_id) else {
+            return;
+        };
+        Logger::error(&format!("Peer {} ({}) misbehaved: {:?}", peer_id, peer.addr, violation));
+        if peer.score.penalize(violation) {
+            let peer = peers.remove(&peer_id).expect("Peer was just found");
+            let _ = peer.stream.shutdown(Shutdown::Both);
+            self.bans.lock().unwrap().insert(peer.addr.ip(), Instant::now() + BAN_DURATION);
+            Logger::error(&format!("Banned {} after a misbehavior score of {}", peer.addr.ip(), peer.score.misbehavior));
+        }
+    }
+
+    fn version_message(&self) -> NetMessage {
+        let blockchain = self.blockchain.lock().unwrap();
+        NetMessage::Version {
+            protocol_version: PROTOCOL_VERSION,
+            chain_id: blockchain.chain_id,
+            tip_index: blockchain.try_get_latest_block().map_or(0, |tip| tip.index),
+        }
+    }
+
+    // Reads the peer's `Version` and returns its tip index if the peer is compatible
+    fn await_handshake(&self, peer_id: u64, reader: &mut TcpStream) -> Result<u64, String> {
+        reader.set_read_timeout(Some(HANDSHAKE_TIMEOUT)).map_err(|e| e.to_string())?;
+        let frame = read_frame(reader).map_err(|e| format!("Handshake failed: {}", e))?;
+        reader.set_read_timeout(None).map_err(|e| e.to_string())?;
+
+        let Ok(NetMessage::Version { protocol_version, chain_id, tip_index }) = serde_json::from_slice(&frame) else {
+            return Err("Peer did not start with a version message".to_string());
+        };
+        if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&protocol_version) {
+            return Err(format!("Unsupported protocol version {}", protocol_version));
+        }
+        let local_chain_id = self.blockchain.lock().unwrap().chain_id;
+        if chain_id != local_chain_id {
+            return Err(format!("Peer is on chain {} but this node is on chain {}", chain_id, local_chain_id));
+        }
+        Logger::info(&format!("Peer {} speaks protocol {} at tip {}", peer_id, protocol_version, tip_index));
+        Ok(tip_index)
+    }
+
+    fn read_from_peer(&self, peer_id: u64, mut reader: TcpStream) {
+        loop {
+            let frame = match read_frame(&mut reader) {
+                Ok(frame) => frame,
+                Err(e) => {
+                    // An oversized frame leaves the stream unframed, so the peer is dropped either way
+                    if e.kind() == io::ErrorKind::InvalidData {
+                        self.penalize(peer_id, Violation::MalformedMessage);
+                    } else if e.kind() != io::ErrorKind::UnexpectedEof {
+                        Logger::error(&format!("Dropping peer {}: {}", peer_id, e));
+                    }
+                    break;
+                }
+            };
+
+            match serde_json::from_slice(&frame) {
+                Ok(message) => self.handle_message(peer_id, message),
+                Err(e) => {
+                    Logger::error(&format!("Malformed message from peer {}: {}", peer_id, e));
+                    self.penalize(peer_id, Violation::MalformedMessage);
+                }
+            }
+        }
+        self.peers.lock().unwrap().remove(&peer_id);
+        Logger::info(&format!("Disconnected from peer {}", peer_id));
+    }
+
+    // Rejected transactions and blocks, including ones already known, are not relayed further
+    fn handle_message(&self, peer_id: u64, message: NetMessage) {
+        match message {
+            NetMessage::Version { .. } => Logger::info(&format!("Ignored repeated version message from peer {}", peer_id)),
+            NetMessage::NewTransaction(transaction) => {
+                if !transaction.is_valid() {
+                    self.penalize(peer_id, Violation::InvalidTransaction);
+                    return;
+                }
+                let accepted = self.blockchain.lock().unwrap().add_to_mempool(transaction.clone());
+                match accepted {
+                    Ok(()) => self.relay(&NetMessage::NewTransaction(transaction), Some(peer_id)),
+                    Err(e) => Logger::info(&format!("Ignored transaction {} from peer {}: {}", transaction.id, peer_id, e)),
+                }
+            }
+            NetMessage::NewBlock(block) => {
+                let index = block.index;
+                let (result, from_index) = {
+                    let mut blockchain = self.blockchain.lock().unwrap();
+                    if !block.verify_self_hash() || !blockchain.meets_work_requirement(&block) {
+                        drop(blockchain);
+                        self.penalize(peer_id, Violation::InvalidBlock);
+                        return;
+                    }
+                    let result = blockchain.submit_block(block.clone());
+                    (result, next_index(&blockchain))
+                };
+                match result {
+                    Ok(0) => {
+                        Logger::info(&format!("Block {} from peer {} is ahead of our next height {}; syncing", index, peer_id, from_index));
+                        self.send_to(peer_id, &NetMessage::GetHeaders { from_index });
+                    }
+                    Ok(_) => self.relay(&NetMessage::NewBlock(block), Some(peer_id)),
+                    Err(e) => Logger::info(&format!("Ignored block {} from peer {}: {}", index, peer_id, e)),
+                }
+            }
+            NetMessage::GetBlocks { from_index } => {
+                let blocks: Vec<Block> = {
+                    let blockchain = self.blockchain.lock().unwrap();
+                    blockchain.chain.iter()
+                        .filter(|block| block.index >= from_index)
+                        .take(MAX_HEADERS_PER_MESSAGE)
+                        .cloned()
+                        .collect()
+                };
+                self.send_to(peer_id, &NetMessage::Blocks(blocks));
+            }
+            NetMessage::Blocks(blocks) => {
+                let full_batch = blocks.len() >= MAX_HEADERS_PER_MESSAGE;
+                let from_index = {
+                    let mut blockchain = self.blockchain.lock().unwrap();
+                    for block in blocks {
+                        // Blocks already connected from the orphan pool are expected to be rejected here
+                        if let Err(e) = blockchain.submit_block(block) {
+                            Logger::info(&format!("Skipped synced block from peer {}: {}", peer_id, e));
+                        }
+                    }
+                    next_index(&blockchain)
+                };
+                if full_batch {
+                    self.send_to(peer_id, &NetMessage::GetHeaders { from_index });
+                }
+            }
+            NetMessage::GetHeaders { from_index } => {
+                let headers: Vec<_> = {
+                    let blockchain = self.blockchain.lock().unwrap();
+                    blockchain.chain.iter()
+                        .filter(|block| block.index >= from_index)
+                        .take(MAX_HEADERS_PER_MESSAGE)
+                        .map(|block| block.header())
+                        .collect()
+                };
+                self.send_to(peer_id, &NetMessage::Headers(headers));
+            }
+            NetMessage::Headers(headers) => {
+                if headers.is_empty() {
+                    return;
+                }
+                if let Err(e) = self.blockchain.lock().unwrap().validate_headers(&headers) {
+                    Logger::info(&format!("Ignored headers from peer {}: {}", peer_id, e));
+                    return;
+                }
+                let indices = headers.iter().map(|header| header.index).collect();
+                self.send_to(peer_id, &NetMessage::GetBlocksByIndex(indices));
+            }
+            NetMessage::GetBlocksByIndex(indices) => {
+                let blocks: Vec<Block> = {
+                    let blockchain = self.blockchain.lock().unwrap();
+                    indices.iter()
+                        .take(MAX_HEADERS_PER_MESSAGE)
+                        .filter_map(|&index| blockchain.get_block_by_index(index).cloned())
+                        .collect()
+                };
+                self.send_to(peer_id, &NetMessage::Blocks(blocks));
+            }
+        }
+    }
+
+    fn send_to(&self, peer_id: u64, message: &NetMessage) {
+        let Some(payload) = encode_message(message) else {
+            return;
+        };
+        // The peer list stays unlocked during the write, so a slow peer holds up only itself
+        let Some(writer) = self.peers.lock().unwrap().get(&peer_id).map(|peer| Arc::clone(&peer.writer)) else {
+            return;
+        };
+        let sent = write_frame(&mut *writer.lock().unwrap(), &payload);
+        if let Err(e) = sent {
+            Logger::error(&format!("Dropping peer {} after failed send: {}", peer_id, e));
+            self.peers.lock().unwrap().remove(&peer_id);
+        }
+    }
+
+    fn relay(&self, message: &NetMessage, source: Option<u64>) {
+        let Some(payload) = encode_message(message) else {
+            return;
+        };
+
+        let writers: Vec<(u64, Arc<Mutex<TcpStream>>)> = self.peers.lock().unwrap()
+            .iter()
+            .filter(|(&peer_id, _)| Some(peer_id) != source)
+            .map(|(&peer_id, peer)| (peer_id, Arc::clone(&peer.writer)))
+            .collect();
+        for (peer_id, writer) in writers {
+            let sent = write_frame(&mut *writer.lock().unwrap(), &payload);
+            if let Err(e) = sent {
+                Logger::error(&format!("Dropping peer {} after failed relay: {}", peer_id, e));
+                self.peers.lock().unwrap().remove(&peer_id);
+            }
+        }
+    }
+}
+
+// Height of the block that would extend the local chain: one past the tip, or 0 if it is empty
+fn next_index(blockchain: &Blockchain) -> u64 {
+    blockchain.try_get_latest_block().map_or(0, |tip| tip.index + 1)
+}
+
+fn encode_message(message: &NetMessage) -> Option<Vec<u8>> {
+    match serde_json::to_vec(message) {
+        Ok(payload) => Some(payload),
+        Err(e) => {
+            Logger::error(&format!("Failed to encode message: {}", e));
+            None
+        }
+    }
+}
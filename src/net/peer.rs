@@ -0,0 +1,37 @@
+/// Misbehavior points at which a peer is disconnected and banned.
+pub const BAN_THRESHOLD: u32 = 100;
+
+/// Protocol violations a peer can be penalized for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Violation {
+    /// A frame that could not be read or a payload that is not a valid message.
+    MalformedMessage,
+    /// A block whose hash does not match its contents or that misses its proof-of-work target.
+    InvalidBlock,
+    /// A transaction that fails `Transaction::is_valid`, e.g. a bad signature.
+    InvalidTransaction,
+}
+
+impl Violation {
+    pub fn penalty(self) -> u32 {
+        match self {
+            Violation::MalformedMessage => 50,
+            Violation::InvalidBlock => 100,
+            Violation::InvalidTransaction => 20,
+        }
+    }
+}
+
+/// Accumulated misbehavior of a single connection.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PeerScore {
+    pub misbehavior: u32,
+}
+
+impl PeerScore {
+    /// Records a violation and returns true once the peer has crossed `BAN_THRESHOLD`.
+    pub fn penalize(&mut self, violation: Violation) -> bool {
+        self.misbehavior = self.misbehavior.saturating_add(violation.penalty());
+        self.misbehavior >= BAN_THRESHOLD
+    }
+}
@@ -0,0 +1,28 @@
+use serde_json::{json, Value};
+
+/// A JSON-RPC 2.0 error object.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub const PARSE_ERROR: i64 = -32700;
+    pub const INVALID_REQUEST: i64 = -32600;
+    pub const METHOD_NOT_FOUND: i64 = -32601;
+    pub const INVALID_PARAMS: i64 = -32602;
+    pub const ADDRESS_NOT_FOUND: i64 = -32001;
+    pub const BLOCK_NOT_FOUND: i64 = -32002;
+    pub const TRANSACTION_NOT_FOUND: i64 = -32003;
+    pub const INSUFFICIENT_BALANCE: i64 = -32004;
+    pub const TRANSACTION_REJECTED: i64 = -32005;
+
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        RpcError { code, message: message.into() }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({ "code": self.code, "message": self.message })
+    }
+}
@@ -0,0 +1,29 @@
+//! JSON-RPC 2.0 over HTTP for wallets and explorers. Every request is a `POST` whose body is a
+//! JSON-RPC request object; the response body is the matching JSON-RPC response.
+//!
+//! | Method            | Params                      | Result                                  |
+//! |-------------------|-----------------------------|-----------------------------------------|
+//! | `getbalance`      | `[address]`                 | balance in base units, as an integer    |
+//! | `getblock`        | `[index]`                   | the block                               |
+//! | `gettransaction`  | `[id]`                      | the transaction, from the chain or pool |
+//! | `sendtransaction` | `[signed_tx_json]`          | the transaction ID                      |
+//! | `getchaininfo`    | `[]`                        | height, tip, difficulty, chain ID, ...  |
+//!
+//! Transactions are exchanged as JSON with every field. Their `amount` and `fee` are integers
+//! counting base units (`BASE_UNITS_PER_COIN` per coin), and the optional `data` memo is an array
+//! of byte values (`null` when absent).
+//!
+//! Failures use the reserved JSON-RPC codes for malformed requests, and the `RpcError` codes in
+//! the `-32000` range for application errors such as an unknown address or an overdrawn sender.
+//!
+//! For push updates, `WebSocketServer` accepts clients that send `{"subscribe": "newBlock"}` or
+//! `{"subscribe": "newTransaction"}`. Each subscription is acknowledged, after which every block
+//! added and every transaction accepted arrives as `{"channel": ..., "data": ...}`.
+
+mod error;
+mod server;
+mod websocket;
+
+pub use error::RpcError;
+pub use server::{handle_request, RpcServer};
+pub use websocket::{WebSocketServer, NEW_BLOCK_CHANNEL, NEW_TRANSACTION_CHANNEL};
@@ -0,0 +1,144 @@
+use super::error::RpcError;
+use crate::blockchain::{Blockchain, ChainError, Transaction};
+use crate::utils::Logger;
+use serde_json::{json, Value};
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tiny_http::{Header, Method, Response, Server};
+
+/// An HTTP server answering JSON-RPC 2.0 requests against a shared blockchain.
+pub struct RpcServer {
+    local_addr: SocketAddr,
+}
+
+impl RpcServer {
+    /// Binds the server on `addr` and starts serving requests in the background.
+    pub fn bind<A: ToSocketAddrs>(addr: A, blockchain: Arc<Mutex<Blockchain>>) -> io::Result<RpcServer> {
+        let server = Server::http(addr).map_err(io::Error::other)?;
+        let local_addr = server.server_addr().to_ip()
+            .ok_or_else(|| io::Error::other("RPC server is not listening on an IP address"))?;
+
+        thread::spawn(move || {
+            for mut request in server.incoming_requests() {
+                let response = if *request.method() != Method::Post {
+                    Response::from_string("JSON-RPC requests must be sent with POST").with_status_code(405)
+                } else {
+                    let mut body = String::new();
+                    let reply = match request.as_reader().read_to_string(&mut body) {
+                        Ok(_) => handle_request(&blockchain, &body),
+                        Err(_) => error_response(Value::Null, RpcError::new(RpcError::PARSE_ERROR, "Request body is not valid UTF-8")),
+                    };
+                    Response::from_string(reply.to_string())
+                        .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
+                };
+                if let Err(e) = request.respond(response) {
+                    Logger::error(&format!("Failed to send RPC response: {}", e));
+                }
+            }
+        });
+
+        Logger::info(&format!("RPC server listening on {}", local_addr));
+        Ok(RpcServer { local_addr })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+/// Answers a single JSON-RPC request body, returning the response object. Exposed so the method
+/// handling can be used without going through HTTP.
+pub fn handle_request(blockchain: &Mutex<Blockchain>, body: &str) -> Value {
+    let request: Value = match serde_json::from_str(body) {
+        Ok(request) => request,
+        Err(e) => return error_response(Value::Null, RpcError::new(RpcError::PARSE_ERROR, format!("Parse error: {}", e))),
+    };
+
+    let id = request.get("id").cloned().unwrap_or(Value::Null);
+    let method = match (request.get("jsonrpc").and_then(Value::as_str), request.get("method").and_then(Value::as_str)) {
+        (Some("2.0"), Some(method)) => method,
+        _ => return error_response(id, RpcError::new(RpcError::INVALID_REQUEST, "Invalid request")),
+    };
+    let params = match request.get("params") {
+        None | Some(Value::Null) => Vec::new(),
+        Some(Value::Array(params)) => params.clone(),
+        Some(_) => return error_response(id, RpcError::new(RpcError::INVALID_PARAMS, "Params must be an array")),
+    };
+
+    let result = {
+        let mut blockchain = blockchain.lock().unwrap();
+        dispatch(&mut blockchain, method, &params)
+    };
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "result": result, "id": id }),
+        Err(error) => error_response(id, error),
+    }
+}
+
+fn dispatch(blockchain: &mut Blockchain, method: &str, params: &[Value]) -> Result<Value, RpcError> {
+    match method {
+        "getbalance" => {
+            let address = string_param(params, 0, "address")?;
+            if !blockchain.is_known_address(address) {
+                return Err(RpcError::new(RpcError::ADDRESS_NOT_FOUND, format!("Address not found: {}", address)));
+            }
+            Ok(json!(blockchain.get_balance(address).base_units()))
+        }
+        "getblock" => {
+            let index = params.first().and_then(Value::as_u64)
+                .ok_or_else(|| RpcError::new(RpcError::INVALID_PARAMS, "Expected a block index"))?;
+            let block = blockchain.get_block_by_index(index)
+                .ok_or_else(|| RpcError::new(RpcError::BLOCK_NOT_FOUND, format!("Block not found: {}", index)))?;
+            Ok(json!(block))
+        }
+        "gettransaction" => {
+            let id = string_param(params, 0, "transaction ID")?;
+            let transaction = blockchain.get_transaction_by_id(id).map(|(_, tx)| tx)
+                .or_else(|| blockchain.mempool.iter().find(|tx| tx.id == id))
+                .ok_or_else(|| RpcError::new(RpcError::TRANSACTION_NOT_FOUND, format!("Transaction not found: {}", id)))?;
+            Ok(json!(transaction))
+        }
+        "sendtransaction" => {
+            let transaction: Transaction = match params.first() {
+                Some(Value::String(raw)) => serde_json::from_str(raw),
+                Some(object @ Value::Object(_)) => serde_json::from_value(object.clone()),
+                _ => return Err(RpcError::new(RpcError::INVALID_PARAMS, "Expected a signed transaction")),
+            }.map_err(|e| RpcError::new(RpcError::INVALID_PARAMS, format!("Malformed transaction: {}", e)))?;
+
+            let id = transaction.id.clone();
+            blockchain.add_to_mempool(transaction).map_err(|e| {
+                let code = match e {
+                    ChainError::InsufficientBalance(_) => RpcError::INSUFFICIENT_BALANCE,
+                    _ => RpcError::TRANSACTION_REJECTED,
+                };
+                RpcError::new(code, e.to_string())
+            })?;
+            Ok(json!(id))
+        }
+        "getchaininfo" => {
+            let tip = blockchain.try_get_latest_block()
+                .ok_or_else(|| RpcError::new(RpcError::BLOCK_NOT_FOUND, "Chain has no blocks"))?;
+            Ok(json!({
+                "chain_id": blockchain.chain_id,
+                "genesis_hash": blockchain.genesis_hash,
+                "height": tip.index,
+                "tip_hash": tip.hash,
+                "difficulty": blockchain.difficulty,
+                "mempool_size": blockchain.mempool.len(),
+                "stats": blockchain.stats(),
+            }))
+        }
+        _ => Err(RpcError::new(RpcError::METHOD_NOT_FOUND, format!("Method not found: {}", method))),
+    }
+}
+
+fn string_param<'a>(params: &'a [Value], position: usize, name: &str) -> Result<&'a str, RpcError> {
+    params.get(position).and_then(Value::as_str)
+        .ok_or_else(|| RpcError::new(RpcError::INVALID_PARAMS, format!("Expected {} as a string", name)))
+}
+
+fn error_response(id: Value, error: RpcError) -> Value {
+    json!({ "jsonrpc": "2.0", "error": error.to_json(), "id": id })
+}
@@ -0,0 +1,117 @@
+use crate::blockchain::{Blockchain, ChainEvent};
+use crate::utils::Logger;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tungstenite::{Message, WebSocket};
+
+/// Channel carrying every block added to the chain.
+pub const NEW_BLOCK_CHANNEL: &str = "newBlock";
+/// Channel carrying every transaction accepted into the mempool.
+pub const NEW_TRANSACTION_CHANNEL: &str = "newTransaction";
+
+// How long a connection waits for client messages before forwarding pending events
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A WebSocket server pushing chain events to subscribed clients.
+pub struct WebSocketServer {
+    local_addr: SocketAddr,
+}
+
+impl WebSocketServer {
+    /// Binds the server on `addr` and starts accepting clients in the background.
+    pub fn bind<A: ToSocketAddrs>(addr: A, blockchain: Arc<Mutex<Blockchain>>) -> io::Result<WebSocketServer> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let events = blockchain.lock().unwrap().subscribe_events();
+                        thread::spawn(move || {
+                            if let Err(e) = serve_client(stream, events) {
+                                Logger::error(&format!("WebSocket client disconnected: {}", e));
+                            }
+                        });
+                    }
+                    Err(e) => Logger::error(&format!("Failed to accept WebSocket client: {}", e)),
+                }
+            }
+        });
+
+        Logger::info(&format!("WebSocket server listening on {}", local_addr));
+        Ok(WebSocketServer { local_addr })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+// Alternates between reading subscription requests, with a short timeout, and forwarding the
+// events the client subscribed to
+fn serve_client(stream: TcpStream, events: Receiver<ChainEvent>) -> Result<(), String> {
+    let mut socket = tungstenite::accept(stream).map_err(|e| e.to_string())?;
+    socket.get_ref().set_read_timeout(Some(POLL_INTERVAL)).map_err(|e| e.to_string())?;
+    let mut channels: HashSet<String> = HashSet::new();
+
+    loop {
+        match socket.read() {
+            Ok(Message::Text(text)) => {
+                let reply = handle_client_message(&mut channels, &text);
+                send_json(&mut socket, reply)?;
+            }
+            Ok(Message::Close(_)) => return Ok(()),
+            Ok(_) => {}
+            Err(tungstenite::Error::Io(e)) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {}
+            Err(tungstenite::Error::ConnectionClosed) => return Ok(()),
+            Err(e) => return Err(e.to_string()),
+        }
+
+        for event in events.try_iter() {
+            let (channel, data) = match &event {
+                ChainEvent::NewBlock(block) => (NEW_BLOCK_CHANNEL, json!(block)),
+                ChainEvent::NewTransaction(transaction) => (NEW_TRANSACTION_CHANNEL, json!(transaction)),
+            };
+            if channels.contains(channel) {
+                send_json(&mut socket, json!({ "channel": channel, "data": data }))?;
+            }
+        }
+    }
+}
+
+// Clients send `{"subscribe": "<channel>"}` or `{"unsubscribe": "<channel>"}` and get an
+// acknowledgement, or an error for unknown channels and malformed messages
+fn handle_client_message(channels: &mut HashSet<String>, text: &str) -> Value {
+    let request: Value = match serde_json::from_str(text) {
+        Ok(request) => request,
+        Err(e) => return json!({ "error": format!("Malformed message: {}", e) }),
+    };
+    let (subscribe, channel) = match (request.get("subscribe"), request.get("unsubscribe")) {
+        (Some(channel), None) => (true, channel),
+        (None, Some(channel)) => (false, channel),
+        _ => return json!({ "error": "Expected a subscribe or unsubscribe request" }),
+    };
+    match channel.as_str() {
+        Some(channel @ (NEW_BLOCK_CHANNEL | NEW_TRANSACTION_CHANNEL)) => {
+            if subscribe {
+                channels.insert(channel.to_string());
+                json!({ "subscribed": channel })
+            } else {
+                channels.remove(channel);
+                json!({ "unsubscribed": channel })
+            }
+        }
+        _ => json!({ "error": format!("Unknown channel: {}", channel) }),
+    }
+}
+
+fn send_json(socket: &mut WebSocket<TcpStream>, value: Value) -> Result<(), String> {
+    socket.send(Message::Text(value.to_string())).map_err(|e| e.to_string())
+}
@@ -1,3 +1,5 @@
+pub mod clock;
 pub mod logger;
 
+pub use clock::{Clock, MockClock, SystemClock};
 pub use logger::Logger;
\ No newline at end of file
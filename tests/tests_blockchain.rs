@@ -0,0 +1,2970 @@
+use KrakenChain::blockchain::{encode_binary, Amount, BalanceSnapshot, BINARY_MAGIC, Block, BlockHeader, Blockchain, BlockchainBuilder, ChainError, Checkpoint, ConfirmationPolicy, Consensus, LedgerModel, MemoryChainStore, MerkleTree, multisig_address, MultisigPolicy, ProofOfWork, RewardPolicy, SelectionPolicy, SignedCheckpoints, Transaction, TransactionStatus, TxOutput, U256, WaitError, LOCKTIME_THRESHOLD, MAX_TRANSACTION_DATA_LEN};
+use KrakenChain::net::{write_frame, NetMessage, Node, PROTOCOL_VERSION};
+use KrakenChain::rpc::{RpcError, RpcServer, WebSocketServer};
+use KrakenChain::utils::MockClock;
+use chrono::{Duration, Utc};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
+
+fn create_keypair() -> (Ed25519KeyPair, String) {
+    let rng = ring::rand::SystemRandom::new();
+    let pkcs8_bytes = Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+    let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8_bytes.as_ref()).unwrap();
+    let address = hex::encode(key_pair.public_key().as_ref());
+    (key_pair, address)
+}
+
+// Converts whole coins to an `Amount`, for writing test amounts as literals
+fn coins(coins: f64) -> Amount {
+    Amount::from_coins(coins).unwrap()
+}
+
+fn signed_transaction(key: &Ed25519KeyPair, from: &str, to: &str, amount: f64, fee: f64) -> Transaction {
+    let mut tx = Transaction::new(from.to_string(), to.to_string(), coins(amount), coins(fee));
+    tx.sign(key);
+    tx
+}
+
+// Signs a transaction carrying the next nonce `blockchain` expects from `from`
+fn next_signed_transaction(blockchain: &Blockchain, key: &Ed25519KeyPair, from: &str, to: &str, amount: f64, fee: f64) -> Transaction {
+    let mut tx = Transaction::new(from.to_string(), to.to_string(), coins(amount), coins(fee));
+    tx.nonce = blockchain.next_nonce(from);
+    tx.sign(key);
+    tx
+}
+
+// The coinbase the next block must carry on a block-subsidy chain: its reward plus `transactions`' fees
+fn next_coinbase(blockchain: &Blockchain, transactions: &[Transaction]) -> Transaction {
+    let height = blockchain.get_latest_block().index + 1;
+    let fees: Amount = transactions.iter().map(|tx| tx.fee).sum();
+    Transaction::new("Blockchain".to_string(), "miner".to_string(), blockchain.reward_at_height(height) + fees, Amount::ZERO)
+}
+
+// Appends a mined block without validating it, adding the coinbase unless the caller supplied one
+fn push_mined_block(blockchain: &mut Blockchain, mut transactions: Vec<Transaction>) {
+    if !transactions.iter().any(|tx| tx.from == "Blockchain") {
+        transactions.push(next_coinbase(blockchain, &transactions));
+    }
+    let previous = blockchain.get_latest_block();
+    let mut block = Block::new(previous.index + 1, transactions, previous.hash.clone(), blockchain.difficulty);
+    block.mine_block(blockchain.difficulty, &AtomicBool::new(false), u64::MAX);
+    blockchain.chain.push(block);
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("krakenchain-{}-{}", uuid::Uuid::new_v4(), name))
+}
+
+struct EvenNonce;
+
+impl ProofOfWork for EvenNonce {
+    fn target_for(&self, _difficulty: u32) -> U256 {
+        U256::MAX
+    }
+
+    fn meets_target(&self, block: &Block, _difficulty: u32) -> bool {
+        block.nonce.is_multiple_of(2)
+    }
+}
+
+#[test]
+fn custom_proof_of_work_is_used_for_mining_and_validation() {
+    let mut blockchain = Blockchain::new(4, coins(10.0), Duration::seconds(10));
+    blockchain.proof_of_work = Arc::new(EvenNonce);
+    let (_, miner) = create_keypair();
+
+    blockchain.mine_pending_transactions(&miner).unwrap();
+    assert!(blockchain.get_latest_block().nonce.is_multiple_of(2));
+    assert!(blockchain.validate_chain());
+
+    let tip = blockchain.chain.last_mut().unwrap();
+    tip.nonce += 1;
+    tip.hash = tip.calculate_hash();
+    assert!(!blockchain.validate_chain());
+}
+
+#[test]
+fn loaded_mempool_is_sorted_by_fee_rate() {
+    let mut blockchain = Blockchain::new(4, coins(10.0), Duration::seconds(10));
+    let (_, alice) = create_keypair();
+    let (_, bob) = create_keypair();
+    for fee in [0.1, 0.5, 0.3] {
+        blockchain.mempool.push(Transaction::new(alice.clone(), bob.clone(), coins(1.0), coins(fee)));
+    }
+    let path = temp_path("mempool.json");
+    blockchain.save_mempool(path.to_str().unwrap()).unwrap();
+
+    let mut restored = Blockchain::new(4, coins(10.0), Duration::seconds(10));
+    restored.load_mempool(path.to_str().unwrap()).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let fees: Vec<Amount> = restored.mempool.iter().map(|tx| tx.fee).collect();
+    assert_eq!(fees, vec![coins(0.5), coins(0.3), coins(0.1)]);
+}
+
+#[test]
+fn recent_fee_rates_averages_over_last_blocks() {
+    let mut blockchain = Blockchain::new(4, coins(10.0), Duration::seconds(10));
+    let (alice_key, alice) = create_keypair();
+    let (_, bob) = create_keypair();
+    blockchain.add_balance(&alice, coins(100.0));
+
+    let mut fee_rates = Vec::new();
+    for fees in [vec![0.1, 0.3], vec![0.2]] {
+        for fee in fees {
+            let tx = next_signed_transaction(&blockchain, &alice_key, &alice, &bob, 1.0, fee);
+            fee_rates.push(fee / blockchain.calculate_transaction_size(&tx) as f64);
+            blockchain.add_to_mempool(tx).unwrap();
+        }
+        blockchain.mine_pending_transactions(&bob).unwrap();
+    }
+
+    let last_block_average = fee_rates[2];
+    let two_block_average = fee_rates.iter().sum::<f64>() / 3.0;
+    assert!((blockchain.recent_fee_rates(1) - last_block_average).abs() < 1e-12);
+    assert!((blockchain.recent_fee_rates(2) - two_block_average).abs() < 1e-12);
+    assert!((blockchain.recent_fee_rates(10) - two_block_average).abs() < 1e-12);
+}
+
+#[test]
+fn zero_difficulty_chain_validates_without_panicking() {
+    let mut blockchain = Blockchain::new(0, coins(10.0), Duration::seconds(10));
+    blockchain.difficulty_adjustment_interval = u64::MAX;
+    let (_, miner) = create_keypair();
+
+    blockchain.mine_pending_transactions(&miner).unwrap();
+    blockchain.mine_pending_transactions(&miner).unwrap();
+
+    assert_eq!(blockchain.chain.len(), 3);
+    assert!(blockchain.validate_chain());
+    assert!(blockchain.is_chain_valid());
+}
+
+#[test]
+fn chain_stats_over_height_window() {
+    let mut blockchain = Blockchain::new(4, coins(10.0), Duration::seconds(10));
+    let (alice_key, alice) = create_keypair();
+    let (_, bob) = create_keypair();
+    blockchain.add_balance(&alice, coins(100.0));
+
+    let mut fee_rates = Vec::new();
+    for (amount, fee) in [(5.0, 0.1), (7.0, 0.2), (11.0, 0.4)] {
+        let tx = next_signed_transaction(&blockchain, &alice_key, &alice, &bob, amount, fee);
+        fee_rates.push(fee / blockchain.calculate_transaction_size(&tx) as f64);
+        blockchain.add_to_mempool(tx).unwrap();
+        blockchain.mine_pending_transactions(&bob).unwrap();
+    }
+
+    let stats = blockchain.chain_stats(2, 3);
+    assert_eq!(stats.transaction_count, 2);
+    assert_eq!(stats.total_volume, coins(18.0));
+    assert_eq!(stats.total_fees, coins(0.6));
+    assert!((stats.average_fee_rate - (fee_rates[1] + fee_rates[2]) / 2.0).abs() < 1e-12);
+    assert_eq!(stats.average_block_time, blockchain.chain[3].timestamp - blockchain.chain[2].timestamp);
+
+    let single = blockchain.chain_stats(1, 1);
+    assert_eq!(single.transaction_count, 1);
+    assert_eq!(single.average_block_time, Duration::zero());
+
+    let empty = blockchain.chain_stats(3, 1);
+    assert_eq!(empty.transaction_count, 0);
+}
+
+#[test]
+fn import_signed_checkpoints_verifies_authority_signature() {
+    let mut blockchain = Blockchain::new(4, coins(10.0), Duration::seconds(10));
+    let (authority_key, _) = create_keypair();
+    let (_, miner) = create_keypair();
+    blockchain.mine_pending_transactions(&miner).unwrap();
+    blockchain.mine_pending_transactions(&miner).unwrap();
+
+    let checkpoints = vec![
+        Checkpoint { height: 1, hash: blockchain.chain[1].hash.clone() },
+        Checkpoint { height: 2, hash: blockchain.chain[2].hash.clone() },
+    ];
+    let signed = SignedCheckpoints::sign(checkpoints, &authority_key);
+
+    let mut tampered = signed.clone();
+    tampered.checkpoints[1].hash = "00".repeat(32);
+    let authority_pubkey = authority_key.public_key().as_ref();
+    assert!(blockchain.import_signed_checkpoints(&tampered.to_bytes(), authority_pubkey).is_err());
+    assert!(blockchain.checkpoints.is_empty());
+
+    assert_eq!(blockchain.import_signed_checkpoints(&signed.to_bytes(), authority_pubkey), Ok(2));
+    assert!(blockchain.validate_chain());
+
+    blockchain.checkpoints.insert(2, "00".repeat(32));
+    assert!(!blockchain.validate_chain());
+}
+
+#[test]
+fn verify_self_hash_detects_altered_hash() {
+    let mut block = Block::new(1, Vec::new(), "0".repeat(64), 1);
+    assert!(block.verify_self_hash());
+
+    block.hash = "f".repeat(64);
+    assert!(!block.verify_self_hash());
+}
+
+#[test]
+fn incremental_merkle_root_matches_full_rebuild() {
+    let (_, alice) = create_keypair();
+    let (_, bob) = create_keypair();
+    let mut tree = MerkleTree::new(&[]);
+    let mut transactions = Vec::new();
+
+    for i in 1..=9 {
+        let tx = Transaction::new(alice.clone(), bob.clone(), coins(i as f64), coins(0.1));
+        tree.append(&tx);
+        transactions.push(tx);
+        assert_eq!(tree.root, MerkleTree::new(&transactions).root, "root mismatch after {} appends", i);
+    }
+}
+
+#[test]
+fn clear_mempool_resets_size_accounting() {
+    let mut blockchain = Blockchain::new(4, coins(10.0), Duration::seconds(10));
+    let (alice_key, alice) = create_keypair();
+    let (_, bob) = create_keypair();
+    blockchain.add_balance(&alice, coins(100.0));
+    blockchain.add_to_mempool(next_signed_transaction(&blockchain, &alice_key, &alice, &bob, 1.0, 0.1)).unwrap();
+    blockchain.add_to_mempool(next_signed_transaction(&blockchain, &alice_key, &alice, &bob, 2.0, 0.1)).unwrap();
+    assert!(blockchain.mempool_size_bytes > 0);
+
+    blockchain.clear_mempool();
+    assert!(blockchain.mempool.is_empty());
+    assert_eq!(blockchain.mempool_size_bytes, 0);
+}
+
+#[test]
+fn estimated_next_block_eta_uses_hashrate_and_threads() {
+    let mut blockchain = Blockchain::new(10, coins(10.0), Duration::seconds(10));
+    assert_eq!(blockchain.estimated_next_block_eta(), Duration::seconds(10));
+
+    blockchain.measured_hashrate = 256.0;
+    blockchain.mining_threads = 2;
+    assert_eq!(blockchain.estimated_next_block_eta(), Duration::seconds(2));
+
+    blockchain.difficulty = 12;
+    assert_eq!(blockchain.estimated_next_block_eta(), Duration::seconds(8));
+}
+
+#[test]
+fn block_total_overflowing_an_amount_is_rejected() {
+    let (alice_key, alice) = create_keypair();
+    let (_, bob) = create_keypair();
+    let mut blockchain = Blockchain::new(4, coins(10.0), Duration::seconds(10));
+
+    let huge = |fee: Amount| {
+        let mut tx = Transaction::new(alice.clone(), bob.clone(), Amount::from_base_units(u64::MAX / 2), fee);
+        tx.sign(&alice_key);
+        tx
+    };
+    assert!(!huge(Amount::from_base_units(u64::MAX / 2 + 2)).is_valid());
+    let first = huge(coins(0.1));
+    let second = huge(coins(0.1));
+    assert!(first.is_valid() && second.is_valid());
+
+    push_mined_block(&mut blockchain, vec![first, second]);
+    assert!(!blockchain.validate_chain());
+}
+
+#[test]
+fn block_with_overflowing_fees_is_rejected_without_panicking() {
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    let mut transactions: Vec<Transaction> = (0..2).map(|_| {
+        let (key, address) = create_keypair();
+        let mut tx = Transaction::new(address, "bob".to_string(), coins(1.0), Amount::from_base_units(1 << 63));
+        tx.sign(&key);
+        assert!(tx.is_valid());
+        tx
+    }).collect();
+    transactions.push(Transaction::new("Blockchain".to_string(), "miner".to_string(), coins(10.0), Amount::ZERO));
+    let previous = blockchain.get_latest_block().clone();
+    let mut block = Block::new(previous.index + 1, transactions, previous.hash.clone(), blockchain.difficulty);
+    block.mine_block(blockchain.difficulty, &AtomicBool::new(false), u64::MAX);
+
+    let mut chain = blockchain.chain.clone();
+    chain.push(block.clone());
+    assert!(matches!(blockchain.validate_external_chain(&chain), Err(ChainError::InvalidBlock(_))));
+    assert!(matches!(blockchain.submit_block(block), Err(ChainError::InvalidBlock(_))));
+    assert_eq!(blockchain.chain.len(), 1);
+}
+
+#[test]
+fn total_supply_is_conserved_exactly_across_many_transfers() {
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    let (alice_key, alice) = create_keypair();
+    let (bob_key, bob) = create_keypair();
+    let (_, miner) = create_keypair();
+    blockchain.add_balance(&alice, coins(100.0));
+    blockchain.add_balance(&bob, coins(100.0));
+    let addresses = vec![alice.clone(), bob.clone(), miner.clone()];
+    let total = |blockchain: &Blockchain| blockchain.get_balances(&addresses).into_values().sum::<Amount>();
+    let initial = total(&blockchain);
+
+    let rounds = 5;
+    for _ in 0..rounds {
+        for _ in 0..10 {
+            let tx = next_signed_transaction(&blockchain, &alice_key, &alice, &bob, 0.1, 0.03);
+            blockchain.add_to_mempool(tx).unwrap();
+            let tx = next_signed_transaction(&blockchain, &bob_key, &bob, &alice, 0.2, 0.01);
+            blockchain.add_to_mempool(tx).unwrap();
+        }
+        blockchain.mine_pending_transactions(&miner).unwrap();
+    }
+
+    assert_eq!(total(&blockchain), initial + Amount::from_base_units(coins(10.0).base_units() * rounds));
+    assert_eq!(blockchain.get_balance(&alice), coins(103.5));
+    assert_eq!(blockchain.get_balance(&bob), coins(94.5));
+}
+
+#[test]
+fn replace_chain_rejects_over_reward_coinbase() {
+    let mut blockchain = Blockchain::new(4, coins(10.0), Duration::seconds(10));
+    let (_, miner) = create_keypair();
+    blockchain.mine_pending_transactions(&miner).unwrap();
+
+    let mut greedy = Blockchain::new(4, coins(10.0), Duration::seconds(10));
+    greedy.chain = blockchain.chain.clone();
+    let coinbase = Transaction::new("Blockchain".to_string(), miner.clone(), coins(1000.0), coins(0.0));
+    push_mined_block(&mut greedy, vec![coinbase]);
+
+    let err = blockchain.validate_external_chain(&greedy.chain).unwrap_err();
+    assert!(err.message().contains("coinbase"), "unexpected error: {}", err);
+    assert!(blockchain.replace_chain(greedy.chain).is_err());
+    assert_eq!(blockchain.chain.len(), 2);
+
+    let mut honest = Blockchain::new(4, coins(10.0), Duration::seconds(10));
+    honest.chain = blockchain.chain.clone();
+    let coinbase = Transaction::new("Blockchain".to_string(), miner.clone(), coins(10.0), coins(0.0));
+    push_mined_block(&mut honest, vec![coinbase]);
+
+    blockchain.replace_chain(honest.chain).unwrap();
+    assert_eq!(blockchain.chain.len(), 3);
+    assert_eq!(blockchain.get_balance(&miner), coins(20.0));
+}
+
+#[test]
+fn fee_only_chain_mines_with_zero_reward() {
+    let mut blockchain = Blockchain::new(4, coins(0.0), Duration::seconds(10));
+    blockchain.reward_policy = RewardPolicy::FeesOnly;
+    let (alice_key, alice) = create_keypair();
+    let (_, bob) = create_keypair();
+    let (_, miner) = create_keypair();
+    blockchain.add_balance(&alice, coins(100.0));
+
+    blockchain.add_to_mempool(signed_transaction(&alice_key, &alice, &bob, 5.0, 0.5)).unwrap();
+    blockchain.mine_pending_transactions(&miner).unwrap();
+
+    let coinbase = blockchain.get_latest_block().transactions.last().unwrap();
+    assert_eq!(coinbase.from, "Blockchain");
+    assert_eq!(coinbase.amount, coins(0.5));
+    assert_eq!(blockchain.get_balance(&alice), coins(94.5));
+    assert_eq!(blockchain.get_balance(&miner), coins(0.5));
+
+    blockchain.mine_pending_transactions(&miner).unwrap();
+    assert_eq!(blockchain.get_latest_block().transactions.last().unwrap().amount, Amount::ZERO);
+    assert!(blockchain.validate_chain());
+}
+
+#[test]
+fn zero_value_coinbase_requires_fee_only_policy() {
+    let mut blockchain = Blockchain::new(4, coins(0.0), Duration::seconds(10));
+    let (_, miner) = create_keypair();
+    assert!(blockchain.mine_pending_transactions(&miner).is_err());
+}
+
+#[test]
+fn all_addresses_collects_every_participant() {
+    let mut blockchain = Blockchain::new(4, coins(10.0), Duration::seconds(10));
+    let (alice_key, alice) = create_keypair();
+    let (_, bob) = create_keypair();
+    let (_, carol) = create_keypair();
+    let (_, miner) = create_keypair();
+    let (_, dormant) = create_keypair();
+    blockchain.add_balance(&alice, coins(100.0));
+    blockchain.add_balance(&dormant, coins(1.0));
+
+    blockchain.add_to_mempool(next_signed_transaction(&blockchain, &alice_key, &alice, &bob, 5.0, 0.1)).unwrap();
+    blockchain.add_to_mempool(next_signed_transaction(&blockchain, &alice_key, &alice, &carol, 5.0, 0.1)).unwrap();
+    blockchain.mine_pending_transactions(&miner).unwrap();
+
+    let expected: HashSet<String> = [alice, bob, carol, miner, dormant].into_iter().collect();
+    assert_eq!(blockchain.all_addresses(), expected);
+}
+
+#[test]
+fn fee_weighted_selection_is_reproducible_for_a_seed() {
+    let (_, alice) = create_keypair();
+    let (_, bob) = create_keypair();
+    let mempool: Vec<Transaction> = (1..=8)
+        .map(|i| Transaction::new(alice.clone(), bob.clone(), coins(1.0), coins(i as f64 * 0.1)))
+        .collect();
+
+    let select = |seed: u64| {
+        let mut blockchain = Blockchain::new(4, coins(10.0), Duration::seconds(10));
+        blockchain.selection_policy = SelectionPolicy::FeeWeighted { seed };
+        blockchain.add_balance(&alice, coins(100.0));
+        blockchain.mempool = mempool.clone();
+        let selected: Vec<String> = blockchain.get_transactions_from_mempool(4).into_iter().map(|tx| tx.id).collect();
+        assert_eq!(blockchain.mempool.len(), 4);
+        selected
+    };
+
+    let first = select(42);
+    assert_eq!(first.len(), 4);
+    assert_eq!(first.iter().collect::<HashSet<_>>().len(), 4);
+    assert_eq!(first, select(42));
+}
+
+#[test]
+fn repair_mempool_accounting_restores_size() {
+    let mut blockchain = Blockchain::new(4, coins(10.0), Duration::seconds(10));
+    let (alice_key, alice) = create_keypair();
+    let (_, bob) = create_keypair();
+    blockchain.add_balance(&alice, coins(100.0));
+    blockchain.add_to_mempool(next_signed_transaction(&blockchain, &alice_key, &alice, &bob, 1.0, 0.1)).unwrap();
+    blockchain.add_to_mempool(next_signed_transaction(&blockchain, &alice_key, &alice, &bob, 2.0, 0.1)).unwrap();
+    let expected: usize = blockchain.mempool.iter().map(|tx| blockchain.calculate_transaction_size(tx)).sum();
+
+    blockchain.mempool_size_bytes = 7;
+    blockchain.repair_mempool_accounting();
+    assert_eq!(blockchain.mempool_size_bytes, expected);
+
+    blockchain.get_transactions_from_mempool(1);
+    let remaining: usize = blockchain.mempool.iter().map(|tx| blockchain.calculate_transaction_size(tx)).sum();
+    assert_eq!(blockchain.mempool_size_bytes, remaining);
+}
+
+#[test]
+fn mempool_ttl_evicts_long_lived_transactions() {
+    let mut blockchain = Blockchain::new(4, coins(10.0), Duration::seconds(10));
+    let clock = Arc::new(MockClock::new(Utc::now()));
+    blockchain.clock = clock.clone();
+    blockchain.mempool_tx_ttl = Some(Duration::minutes(10));
+    let (alice_key, alice) = create_keypair();
+    let (_, bob) = create_keypair();
+    blockchain.add_balance(&alice, coins(100.0));
+
+    let mut tx = Transaction::new(alice.clone(), bob.clone(), coins(1.0), coins(0.1));
+    tx.expiration += 24 * 3600;
+    tx.sign(&alice_key);
+    blockchain.add_to_mempool(tx).unwrap();
+
+    clock.advance(Duration::minutes(9));
+    blockchain.clean_expired_transactions();
+    assert_eq!(blockchain.mempool.len(), 1);
+
+    clock.advance(Duration::minutes(2));
+    blockchain.clean_expired_transactions();
+    assert!(blockchain.mempool.is_empty());
+    assert_eq!(blockchain.mempool_size_bytes, 0);
+}
+
+#[test]
+fn permissioned_mode_requires_authorized_producer() {
+    let mut blockchain = Blockchain::new(4, coins(10.0), Duration::seconds(10));
+    let (authorized_key, authorized) = create_keypair();
+    let (outsider_key, _) = create_keypair();
+    blockchain.authorized_producers = Some([authorized.clone()].into_iter().collect());
+
+    blockchain.mine_pending_transactions_as(&authorized, &authorized_key).unwrap();
+    assert_eq!(blockchain.get_latest_block().producer.as_deref(), Some(authorized.as_str()));
+    assert!(blockchain.validate_chain());
+
+    assert!(blockchain.mine_pending_transactions_as(&authorized, &outsider_key).is_err());
+    assert!(blockchain.mine_pending_transactions(&authorized).is_err());
+    assert_eq!(blockchain.chain.len(), 2);
+}
+
+#[test]
+fn mempool_admission_totals_a_senders_pending_spends() {
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    let (alice_key, alice) = create_keypair();
+    let (_, miner) = create_keypair();
+    blockchain.add_balance(&alice, coins(100.0));
+    for _ in 0..2 {
+        let tx = next_signed_transaction(&blockchain, &alice_key, &alice, "bob", 40.0, 0.1);
+        blockchain.add_to_mempool(tx).unwrap();
+    }
+
+    // Each payment fits the balance on its own, but not on top of the two already pending
+    let third = next_signed_transaction(&blockchain, &alice_key, &alice, "bob", 40.0, 0.1);
+    assert!(matches!(blockchain.add_to_mempool(third), Err(ChainError::DoubleSpend(_))));
+
+    blockchain.mine_pending_transactions(&miner).unwrap();
+    assert_eq!(blockchain.get_balance(&alice), coins(19.8));
+    assert!(blockchain.validate_chain());
+}
+
+#[test]
+fn transactions_of_a_rejected_mined_block_return_to_the_mempool() {
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    let (alice_key, alice) = create_keypair();
+    let (producer_key, producer) = create_keypair();
+    let (outsider_key, _) = create_keypair();
+    blockchain.authorized_producers = Some([producer.clone()].into_iter().collect());
+    blockchain.add_balance(&alice, coins(100.0));
+    let tx = next_signed_transaction(&blockchain, &alice_key, &alice, "bob", 5.0, 0.1);
+    blockchain.add_to_mempool(tx.clone()).unwrap();
+    let first_seen = blockchain.first_seen(&tx.id);
+
+    // Signed by the wrong key, the block fails validation and nothing is lost
+    assert!(blockchain.mine_pending_transactions_as(&producer, &outsider_key).is_err());
+    assert_eq!(blockchain.mempool.iter().map(|tx| &tx.id).collect::<Vec<_>>(), vec![&tx.id]);
+    assert_eq!(blockchain.first_seen(&tx.id), first_seen);
+
+    blockchain.mine_pending_transactions_as(&producer, &producer_key).unwrap();
+    assert!(blockchain.mempool.is_empty());
+    assert_eq!(blockchain.get_balance("bob"), coins(5.0));
+}
+
+#[test]
+fn merkle_tree_from_leaves_matches_new() {
+    let (_, alice) = create_keypair();
+    let (_, bob) = create_keypair();
+    let transactions: Vec<Transaction> = (1..=5)
+        .map(|i| Transaction::new(alice.clone(), bob.clone(), coins(i as f64), coins(0.1)))
+        .collect();
+    let leaves = transactions.iter().map(|tx| tx.calculate_hash()).collect();
+
+    assert_eq!(MerkleTree::from_leaves(leaves).root, MerkleTree::new(&transactions).root);
+    assert_eq!(MerkleTree::from_leaves(Vec::new()).root, MerkleTree::new(&[]).root);
+}
+
+#[test]
+fn malformed_previous_hash_is_rejected_gracefully() {
+    let mut blockchain = Blockchain::new(4, coins(10.0), Duration::seconds(10));
+    let (_, miner) = create_keypair();
+    let coinbase = Transaction::new("Blockchain".to_string(), miner, coins(10.0), coins(0.0));
+    let mut block = Block::new(1, vec![coinbase], "not-a-hash".to_string(), blockchain.difficulty);
+    block.mine_block(blockchain.difficulty, &AtomicBool::new(false), u64::MAX);
+
+    let err = blockchain.add_block(block.clone()).unwrap_err();
+    assert!(err.message().contains("Previous hash"), "unexpected error: {}", err);
+    assert_eq!(blockchain.chain.len(), 1);
+
+    blockchain.chain.push(block);
+    assert!(!blockchain.validate_chain());
+}
+
+#[test]
+fn save_and_load_chain_round_trip() {
+    let mut blockchain = Blockchain::new(4, coins(10.0), Duration::seconds(10));
+    let (_, miner) = create_keypair();
+    blockchain.mine_pending_transactions(&miner).unwrap();
+    blockchain.mine_pending_transactions(&miner).unwrap();
+    let path = temp_path("chain.json");
+    blockchain.save_chain(path.to_str().unwrap()).unwrap();
+
+    let mut restored = Blockchain::new(4, coins(10.0), Duration::seconds(10));
+    restored.load_chain(path.to_str().unwrap()).unwrap();
+    let hashes = |chain: &Blockchain| chain.chain.iter().map(|block| block.hash.clone()).collect::<Vec<_>>();
+    assert_eq!(hashes(&restored), hashes(&blockchain));
+    assert_eq!(restored.get_balance(&miner), coins(20.0));
+
+    let mut tampered = blockchain.chain.clone();
+    tampered[1].transactions[0].amount = coins(1000.0);
+    std::fs::write(&path, serde_json::to_string(&tampered).unwrap()).unwrap();
+    let mut untouched = Blockchain::new(4, coins(10.0), Duration::seconds(10));
+    assert!(untouched.load_chain(path.to_str().unwrap()).is_err());
+    assert_eq!(untouched.chain.len(), 1);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn binary_chain_round_trip_and_header_check() {
+    let mut blockchain = Blockchain::new(4, coins(10.0), Duration::seconds(10));
+    let (_, miner) = create_keypair();
+    blockchain.mine_pending_transactions(&miner).unwrap();
+    let json_path = temp_path("chain.json");
+    let binary_path = temp_path("chain.bin");
+    blockchain.save_chain(json_path.to_str().unwrap()).unwrap();
+    blockchain.save_chain_binary(binary_path.to_str().unwrap()).unwrap();
+
+    let binary = std::fs::read(&binary_path).unwrap();
+    assert_eq!(&binary[..4], b"KRKN");
+    assert!(binary.len() < std::fs::metadata(&json_path).unwrap().len() as usize);
+
+    let mut restored = Blockchain::new(4, coins(10.0), Duration::seconds(10));
+    restored.load_chain_binary(binary_path.to_str().unwrap()).unwrap();
+    assert_eq!(restored.get_latest_block().hash, blockchain.get_latest_block().hash);
+
+    let mut future_version = encode_binary(&blockchain.chain).unwrap();
+    future_version[5] += 1;
+    std::fs::write(&binary_path, future_version).unwrap();
+    assert!(restored.load_chain_binary(binary_path.to_str().unwrap()).is_err());
+
+    std::fs::remove_file(&json_path).unwrap();
+    std::fs::remove_file(&binary_path).unwrap();
+}
+
+#[test]
+fn recommended_confirmations_grow_with_amount() {
+    let mut blockchain = Blockchain::new(4, coins(10.0), Duration::seconds(10));
+    let small = blockchain.recommended_confirmations(coins(10.0));
+    let medium = blockchain.recommended_confirmations(coins(1_000.0));
+    let large = blockchain.recommended_confirmations(coins(100_000.0));
+    assert_eq!(small, 1);
+    assert!(small < medium && medium < large);
+
+    blockchain.confirmation_policy = ConfirmationPolicy { base_confirmations: 6, value_step: coins(1.0), max_confirmations: 10 };
+    assert_eq!(blockchain.recommended_confirmations(coins(0.5)), 6);
+    assert_eq!(blockchain.recommended_confirmations(coins(4.0)), 9);
+    assert_eq!(blockchain.recommended_confirmations(Amount::from_base_units(u64::MAX)), 10);
+}
+
+#[test]
+fn loaded_chain_continues_difficulty_adjustment() {
+    let new_chain = || {
+        let mut blockchain = Blockchain::new(4, coins(10.0), Duration::seconds(10));
+        blockchain.difficulty_adjustment_interval = 3;
+        blockchain
+    };
+    let (_, miner) = create_keypair();
+    let path = temp_path("chain.json");
+    let path = path.to_str().unwrap();
+
+    let mut original = new_chain();
+    for _ in 0..6 {
+        original.mine_pending_transactions(&miner).unwrap();
+    }
+    assert!(original.difficulty > 4);
+    original.save_chain(path).unwrap();
+
+    let mut resumed = new_chain();
+    resumed.load_chain(path).unwrap();
+    assert_eq!(resumed.difficulty, original.difficulty);
+    assert_eq!(resumed.block_time_window, original.block_time_window);
+
+    resumed.mine_pending_transactions(&miner).unwrap();
+    assert_eq!(resumed.get_latest_block().difficulty, original.difficulty);
+    assert!(resumed.validate_chain());
+
+    // Replaying the extended chain must land on the same state the live node reached
+    resumed.save_chain(path).unwrap();
+    let mut replayed = new_chain();
+    replayed.load_chain(path).unwrap();
+    assert_eq!(replayed.difficulty, resumed.difficulty);
+    assert_eq!(replayed.block_time_window, resumed.block_time_window);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn interrupted_save_leaves_previous_mempool_file_intact() {
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    let (key, sender) = create_keypair();
+    blockchain.add_balance(&sender, coins(100.0));
+    blockchain.add_to_mempool(signed_transaction(&key, &sender, "Bob", 5.0, 0.5)).unwrap();
+    let path = temp_path("mempool.json");
+    let path = path.to_str().unwrap();
+    blockchain.save_mempool(path).unwrap();
+
+    // A crash mid-save leaves only a partial temporary file next to the destination
+    let temp_file = format!("{}.tmp", path);
+    std::fs::write(&temp_file, b"[{\"id\": \"trunc").unwrap();
+
+    let mut restored = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    restored.load_mempool(path).unwrap();
+    assert_eq!(restored.mempool.len(), 1);
+    assert_eq!(restored.mempool[0].id, blockchain.mempool[0].id);
+
+    // The next save replaces the leftover temporary file and the destination atomically
+    blockchain.save_mempool(path).unwrap();
+    assert!(!std::path::Path::new(&temp_file).exists());
+    restored.load_mempool(path).unwrap();
+    assert_eq!(restored.mempool.len(), 1);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn first_seen_records_admission_time_not_sender_timestamp() {
+    let mut blockchain = Blockchain::new(4, coins(10.0), Duration::seconds(10));
+    let admitted_at = Utc::now();
+    let clock = Arc::new(MockClock::new(admitted_at));
+    blockchain.clock = clock.clone();
+    let (alice_key, alice) = create_keypair();
+    blockchain.add_balance(&alice, coins(100.0));
+
+    let mut tx = Transaction::new(alice.clone(), "Bob".to_string(), coins(1.0), coins(0.1));
+    tx.timestamp = (admitted_at - Duration::hours(2)).timestamp();
+    tx.sign(&alice_key);
+    blockchain.add_to_mempool(tx.clone()).unwrap();
+
+    clock.advance(Duration::minutes(5));
+    assert_eq!(blockchain.first_seen(&tx.id), Some(admitted_at));
+    assert_ne!(blockchain.first_seen(&tx.id).unwrap().timestamp(), tx.timestamp);
+    assert_eq!(blockchain.first_seen("unknown"), None);
+}
+
+#[test]
+fn gzip_chain_round_trip_is_smaller_and_identical() {
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    let (_, miner) = create_keypair();
+    for _ in 0..5 {
+        blockchain.mine_pending_transactions(&miner).unwrap();
+    }
+    let plain_path = temp_path("chain.json");
+    let gzip_path = temp_path("chain.json.gz");
+    blockchain.save_chain(plain_path.to_str().unwrap()).unwrap();
+    blockchain.save_chain_gzip(gzip_path.to_str().unwrap()).unwrap();
+
+    let plain_size = std::fs::metadata(&plain_path).unwrap().len();
+    let gzip_size = std::fs::metadata(&gzip_path).unwrap().len();
+    assert!(gzip_size < plain_size);
+
+    let mut from_gzip = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    from_gzip.load_chain_gzip(gzip_path.to_str().unwrap()).unwrap();
+    let mut detected = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    detected.load_chain(gzip_path.to_str().unwrap()).unwrap();
+    let mut from_plain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    from_plain.load_chain(plain_path.to_str().unwrap()).unwrap();
+
+    let original = serde_json::to_string(&blockchain.chain).unwrap();
+    assert_eq!(serde_json::to_string(&from_gzip.chain).unwrap(), original);
+    assert_eq!(serde_json::to_string(&detected.chain).unwrap(), original);
+    assert_eq!(serde_json::to_string(&from_plain.chain).unwrap(), original);
+    std::fs::remove_file(plain_path).unwrap();
+    std::fs::remove_file(gzip_path).unwrap();
+}
+
+#[test]
+fn fee_efficiency_report_flags_skipped_high_fee_transaction() {
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    let (alice_key, alice) = create_keypair();
+    let (bob_key, bob) = create_keypair();
+    blockchain.add_balance(&alice, coins(100.0));
+    let skipped = next_signed_transaction(&blockchain, &alice_key, &alice, &bob, 1.0, 5.0);
+    blockchain.add_to_mempool(skipped.clone()).unwrap();
+
+    push_mined_block(&mut blockchain, vec![signed_transaction(&bob_key, &bob, &alice, 1.0, 0.1)]);
+
+    // Admitted after the block was assembled, so the miner could not have included it
+    let late = next_signed_transaction(&blockchain, &alice_key, &alice, &bob, 1.0, 6.0);
+    blockchain.add_to_mempool(late.clone()).unwrap();
+
+    let report = blockchain.analyze_block_fee_efficiency(1).unwrap();
+    assert!(report.has_inversions());
+    assert_eq!(report.skipped_transactions, vec![skipped.id]);
+    assert!(report.lowest_included_fee_rate.unwrap() > 0.0);
+
+    assert!(!blockchain.analyze_block_fee_efficiency(0).unwrap().has_inversions());
+    assert!(blockchain.analyze_block_fee_efficiency(5).is_none());
+}
+
+#[test]
+fn block_log_appends_records_and_replays_chain() {
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    let (_, miner) = create_keypair();
+    let path = temp_path("blocks.log");
+    let path = path.to_str().unwrap();
+    blockchain.open_block_log(path).unwrap();
+
+    let mut sizes = vec![std::fs::metadata(path).unwrap().len()];
+    for _ in 0..3 {
+        blockchain.mine_pending_transactions(&miner).unwrap();
+        sizes.push(std::fs::metadata(path).unwrap().len());
+    }
+    // Each block adds one record of roughly constant size, not a rewrite of the whole chain
+    let growth: Vec<u64> = sizes.windows(2).map(|pair| pair[1] - pair[0]).collect();
+    let (smallest, largest) = (*growth.iter().min().unwrap(), *growth.iter().max().unwrap());
+    assert!(smallest > 0 && largest < smallest * 2);
+
+    let replayed = Blockchain::replay_log(path, coins(10.0), Duration::seconds(10)).unwrap();
+    assert_eq!(serde_json::to_string(&replayed.chain).unwrap(), serde_json::to_string(&blockchain.chain).unwrap());
+    assert_eq!(replayed.get_balance(&miner), coins(30.0));
+    assert_eq!(replayed.difficulty, blockchain.difficulty);
+
+    // A record cut short by a crash is reported rather than silently dropped
+    let bytes = std::fs::read(path).unwrap();
+    std::fs::write(path, &bytes[..bytes.len() - 10]).unwrap();
+    assert!(Blockchain::replay_log(path, coins(10.0), Duration::seconds(10)).is_err());
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn block_log_follows_reorgs_reloads_and_pruning() {
+    let (_, miner) = create_keypair();
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    let path = temp_path("reorg-blocks.log");
+    let path = path.to_str().unwrap();
+    blockchain.open_block_log(path).unwrap();
+    blockchain.mine_pending_transactions(&miner).unwrap();
+    blockchain.mine_pending_transactions(&miner).unwrap();
+    let replayed_hashes = || {
+        let replayed = Blockchain::replay_log(path, coins(10.0), Duration::seconds(10)).unwrap();
+        replayed.chain.iter().map(|block| block.hash.clone()).collect::<Vec<_>>()
+    };
+
+    // The abandoned branch is replaced by the adopted one, and later blocks append after it
+    let mut rival = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    for _ in 0..3 {
+        rival.mine_pending_transactions(&miner).unwrap();
+    }
+    blockchain.replace_chain(rival.chain.clone()).unwrap();
+    blockchain.mine_pending_transactions(&miner).unwrap();
+    let hashes: Vec<String> = blockchain.chain.iter().map(|block| block.hash.clone()).collect();
+    assert_eq!(replayed_hashes(), hashes);
+
+    // Reloading a shorter chain file cuts the log back to it
+    let saved = temp_path("reorg-blocks.json");
+    let mut shorter = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    shorter.mine_pending_transactions(&miner).unwrap();
+    shorter.save_chain(saved.to_str().unwrap()).unwrap();
+    blockchain.load_chain(saved.to_str().unwrap()).unwrap();
+    let hashes: Vec<String> = blockchain.chain.iter().map(|block| block.hash.clone()).collect();
+    assert_eq!(replayed_hashes(), hashes);
+
+    // Pruning keeps the dropped blocks' records, so the log still replays from genesis
+    blockchain.mine_pending_transactions(&miner).unwrap();
+    blockchain.prune_to(1);
+    blockchain.mine_pending_transactions(&miner).unwrap();
+    assert_eq!(replayed_hashes().len(), 4);
+    assert_eq!(replayed_hashes().last(), Some(&blockchain.get_latest_block().hash));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn block_log_record_with_an_oversized_length_is_rejected() {
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    let path = temp_path("oversized-record.log");
+    let path = path.to_str().unwrap();
+    blockchain.open_block_log(path).unwrap();
+
+    // A corrupt length prefix is refused before a buffer of that size is allocated
+    let mut bytes = std::fs::read(path).unwrap();
+    bytes.extend_from_slice(&u32::MAX.to_be_bytes());
+    std::fs::write(path, &bytes).unwrap();
+    match Blockchain::replay_log(path, coins(10.0), Duration::seconds(10)) {
+        Err(e) => assert!(e.message().contains("exceeds the maximum")),
+        Ok(_) => panic!("replayed a log with an oversized record"),
+    }
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn blocks_within_the_same_second_are_accepted() {
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::milliseconds(100));
+    let (_, miner) = create_keypair();
+    blockchain.mine_pending_transactions(&miner).unwrap();
+    blockchain.mine_pending_transactions(&miner).unwrap();
+    assert_eq!(blockchain.chain.len(), 3);
+    assert!(blockchain.chain[2].timestamp > blockchain.chain[1].timestamp);
+
+    // A block one nanosecond after its parent shares the parent's second but is still later
+    let previous = blockchain.get_latest_block().clone();
+    let mut block = Block::new(previous.index + 1, vec![next_coinbase(&blockchain, &[])], previous.hash.clone(), blockchain.difficulty);
+    block.timestamp = previous.timestamp + Duration::nanoseconds(1);
+    block.hash = block.calculate_hash();
+    block.mine_block(blockchain.difficulty, &AtomicBool::new(false), u64::MAX);
+    blockchain.add_block(block).unwrap();
+
+    // Sub-second precision survives persistence, so the block hashes still verify after a reload
+    let path = temp_path("chain.json");
+    blockchain.save_chain(path.to_str().unwrap()).unwrap();
+    let mut restored = Blockchain::new(1, coins(10.0), Duration::milliseconds(100));
+    restored.load_chain(path.to_str().unwrap()).unwrap();
+    assert_eq!(restored.chain.len(), 4);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn csv_exports_list_balances_and_transactions() {
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    blockchain.add_balance("alice", coins(50.0));
+    blockchain.add_balance("bob", coins(75.0));
+    blockchain.add_balance("carol,jr", coins(5.0));
+    let (alice_key, alice) = create_keypair();
+    let tx = signed_transaction(&alice_key, &alice, "bob", 2.0, 0.5);
+    push_mined_block(&mut blockchain, vec![tx.clone()]);
+
+    let balances_path = temp_path("balances.csv");
+    blockchain.export_balances_csv(balances_path.to_str().unwrap()).unwrap();
+    let balances = std::fs::read_to_string(&balances_path).unwrap();
+    let lines: Vec<&str> = balances.lines().collect();
+    assert_eq!(lines, vec!["address,balance", "bob,75.00000000", "alice,50.00000000", "\"carol,jr\",5.00000000"]);
+
+    let transactions_path = temp_path("transactions.csv");
+    blockchain.export_transactions_csv(transactions_path.to_str().unwrap()).unwrap();
+    let transactions = std::fs::read_to_string(&transactions_path).unwrap();
+    let lines: Vec<&str> = transactions.lines().collect();
+    assert_eq!(lines[0], "block_index,id,from,to,amount,fee,timestamp");
+    assert_eq!(lines[1], format!("1,{},{},bob,2.00000000,0.50000000,{}", tx.id, alice, tx.timestamp));
+    assert!(lines[2].contains(",Blockchain,miner,10.50000000,0.00000000,"), "{}", lines[2]);
+    assert_eq!(lines.len(), 3);
+    std::fs::remove_file(balances_path).unwrap();
+    std::fs::remove_file(transactions_path).unwrap();
+}
+
+#[test]
+fn replacing_a_parent_evicts_descendants_it_no_longer_funds() {
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    let (alice_key, alice) = create_keypair();
+    let (bob_key, bob) = create_keypair();
+    blockchain.add_balance(&alice, coins(100.0));
+
+    let parent = signed_transaction(&alice_key, &alice, &bob, 50.0, 1.0);
+    blockchain.add_to_mempool(parent.clone()).unwrap();
+    // Bob has no confirmed funds, so the child only works if the parent confirms with it
+    let child = signed_transaction(&bob_key, &bob, "carol", 40.0, 1.0);
+    blockchain.mempool_size_bytes += blockchain.calculate_transaction_size(&child);
+    blockchain.mempool.push(child.clone());
+
+    // Bumping the fee keeps the parent's output, so the child stays
+    let mut bumped = parent.clone();
+    bumped.fee = coins(2.0);
+    bumped.sign(&alice_key);
+    assert!(blockchain.replace_transaction(bumped).unwrap().is_empty());
+    assert!(blockchain.mempool.iter().any(|tx| tx.id == child.id));
+
+    // Redirecting the parent leaves the child unfunded
+    let mut redirected = parent.clone();
+    redirected.to = "dave".to_string();
+    redirected.fee = coins(3.0);
+    redirected.sign(&alice_key);
+    assert_eq!(blockchain.replace_transaction(redirected).unwrap(), vec![child.id.clone()]);
+    assert_eq!(blockchain.mempool.len(), 1);
+    assert_eq!(blockchain.mempool[0].to, "dave");
+    let expected_size = blockchain.calculate_transaction_size(&blockchain.mempool[0]);
+    assert_eq!(blockchain.mempool_size_bytes, expected_size);
+}
+
+#[test]
+fn from_blocks_rebuilds_state_and_names_first_bad_block() {
+    let mut source = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    let (_, miner) = create_keypair();
+    for _ in 0..3 {
+        source.mine_pending_transactions(&miner).unwrap();
+    }
+
+    let rebuilt = Blockchain::from_blocks(source.chain.clone(), 1, coins(10.0), Duration::seconds(10)).unwrap();
+    assert_eq!(rebuilt.chain.len(), 4);
+    assert_eq!(rebuilt.get_balance(&miner), coins(30.0));
+    assert_eq!(rebuilt.difficulty, source.difficulty);
+
+    let mut tampered = source.chain.clone();
+    tampered[2].previous_hash = tampered[0].hash.clone();
+    let err = Blockchain::from_blocks(tampered, 1, coins(10.0), Duration::seconds(10)).err().unwrap();
+    assert!(err.message().contains("index 2"), "{}", err);
+
+    assert!(Blockchain::from_blocks(Vec::new(), 1, coins(10.0), Duration::seconds(10)).is_err());
+    assert!(Blockchain::from_blocks(source.chain.clone(), 2, coins(10.0), Duration::seconds(10)).is_err());
+}
+
+#[test]
+fn get_balances_returns_funded_and_unfunded_addresses() {
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    blockchain.add_balance("alice", coins(12.5));
+    blockchain.add_balance("bob", coins(3.0));
+
+    let addresses = vec!["alice".to_string(), "bob".to_string(), "carol".to_string()];
+    let balances = blockchain.get_balances(&addresses);
+    assert_eq!(balances.len(), 3);
+    assert_eq!(balances["alice"], coins(12.5));
+    assert_eq!(balances["bob"], coins(3.0));
+    assert_eq!(balances["carol"], coins(0.0));
+}
+
+#[test]
+fn chain_store_receives_blocks_and_rebuilds_chain() {
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    let (_, miner) = create_keypair();
+    blockchain.mine_pending_transactions(&miner).unwrap();
+    blockchain.attach_store(Box::new(MemoryChainStore::new())).unwrap();
+    assert_eq!(blockchain.store().unwrap().len(), 2);
+
+    blockchain.mine_pending_transactions(&miner).unwrap();
+    let store = blockchain.store().unwrap();
+    assert_eq!(store.len(), 3);
+    assert_eq!(store.get_block(2).unwrap().hash, blockchain.chain[2].hash);
+    assert!(store.get_block(3).is_none());
+
+    let store = blockchain.detach_store().unwrap();
+    let mut rebuilt = Blockchain::from_store(store, 1, coins(10.0), Duration::seconds(10)).unwrap();
+    assert_eq!(rebuilt.chain.len(), 3);
+    assert_eq!(rebuilt.get_balance(&miner), coins(20.0));
+
+    rebuilt.mine_pending_transactions(&miner).unwrap();
+    assert_eq!(rebuilt.store().unwrap().len(), 4);
+}
+
+#[test]
+fn chain_store_follows_reorgs_reloads_and_pruning() {
+    let (_, miner) = create_keypair();
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    blockchain.attach_store(Box::new(MemoryChainStore::new())).unwrap();
+    blockchain.mine_pending_transactions(&miner).unwrap();
+    blockchain.mine_pending_transactions(&miner).unwrap();
+    let matches_chain = |blockchain: &Blockchain| {
+        let store = blockchain.store().unwrap();
+        store.len() == blockchain.get_latest_block().index + 1
+            && blockchain.chain.iter().all(|block| store.get_block(block.index).is_some_and(|stored| stored.hash == block.hash))
+    };
+
+    // A longer rival chain replaces every block after genesis
+    let mut rival = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    for _ in 0..4 {
+        rival.mine_pending_transactions(&miner).unwrap();
+    }
+    blockchain.replace_chain(rival.chain.clone()).unwrap();
+    assert!(matches_chain(&blockchain));
+
+    // Reloading a shorter chain file drops the stored blocks past its tip
+    let path = temp_path("store-reload.json");
+    let mut shorter = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    shorter.mine_pending_transactions(&miner).unwrap();
+    shorter.save_chain(path.to_str().unwrap()).unwrap();
+    blockchain.load_chain(path.to_str().unwrap()).unwrap();
+    assert_eq!(blockchain.chain.len(), 2);
+    assert!(matches_chain(&blockchain));
+
+    // Pruned blocks stay stored, so the store can still rebuild the whole chain
+    blockchain.mine_pending_transactions(&miner).unwrap();
+    blockchain.prune_to(1);
+    assert!(matches_chain(&blockchain));
+    let rebuilt = Blockchain::from_store(blockchain.detach_store().unwrap(), 1, coins(10.0), Duration::seconds(10)).unwrap();
+    assert_eq!(rebuilt.chain.len(), 3);
+    assert_eq!(rebuilt.get_balance(&miner), coins(20.0));
+}
+
+#[test]
+fn tampered_genesis_fails_validation() {
+    let blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    assert_eq!(blockchain.chain[0].hash, Blockchain::new(1, coins(10.0), Duration::seconds(10)).chain[0].hash);
+    assert!(blockchain.validate_chain());
+
+    // A self-consistent genesis that credits coins out of thin air
+    let mut tampered = blockchain.chain.clone();
+    tampered[0].transactions.push(Transaction::new("Blockchain".to_string(), "mallory".to_string(), coins(1_000_000.0), coins(0.0)));
+    tampered[0].hash = tampered[0].calculate_hash();
+    assert!(tampered[0].verify_self_hash());
+
+    let mut loaded = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    loaded.chain = tampered.clone();
+    assert!(!loaded.validate_chain());
+    assert!(!loaded.is_chain_valid());
+
+    let path = temp_path("chain.json");
+    std::fs::write(&path, serde_json::to_string(&tampered).unwrap()).unwrap();
+    let mut restored = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    assert!(restored.load_chain(path.to_str().unwrap()).is_err());
+    assert_eq!(restored.get_balance("mallory"), coins(0.0));
+    std::fs::remove_file(path).unwrap();
+
+    // Operators can pin a different genesis explicitly
+    let mut custom = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    custom.genesis_hash = "00".repeat(32);
+    assert!(!custom.validate_chain());
+}
+
+#[test]
+fn pruned_chain_keeps_balances_and_validates() {
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    let (miner_key, miner) = create_keypair();
+    let (_, bob) = create_keypair();
+    for _ in 0..10 {
+        blockchain.mine_pending_transactions(&miner).unwrap();
+    }
+    push_mined_block(&mut blockchain, vec![signed_transaction(&miner_key, &miner, &bob, 30.0, 1.0)]);
+    for _ in 0..9 {
+        blockchain.mine_pending_transactions(&miner).unwrap();
+    }
+    blockchain.recalculate_balances();
+    let (miner_balance, bob_balance) = (blockchain.get_balance(&miner), blockchain.get_balance(&bob));
+    assert_eq!(bob_balance, coins(30.0));
+
+    assert_eq!(blockchain.prune_to(2), 19);
+    assert_eq!(blockchain.chain.len(), 2);
+    assert_eq!(blockchain.chain[0].index, 19);
+    assert_eq!(blockchain.get_balance(&miner), miner_balance);
+    assert_eq!(blockchain.get_balance(&bob), bob_balance);
+    assert!(blockchain.validate_chain());
+    assert!(blockchain.is_chain_valid());
+
+    // The pruned chain keeps growing from the retained tip
+    blockchain.mine_pending_transactions(&miner).unwrap();
+    assert_eq!(blockchain.get_latest_block().index, 21);
+    assert!(blockchain.validate_chain());
+    blockchain.recalculate_balances();
+    assert_eq!(blockchain.get_balance(&miner), miner_balance + coins(10.0));
+    assert_eq!(blockchain.chain_stats(0, 21).transaction_count, 0);
+
+    // Tampering with the checkpointed first block is detected
+    blockchain.chain[0].nonce += 1;
+    assert!(!blockchain.validate_chain());
+}
+
+#[test]
+fn replace_chain_rebuilds_difficulty_state() {
+    let mut source = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    source.difficulty_adjustment_interval = 2;
+    let (_, miner) = create_keypair();
+    for _ in 0..5 {
+        source.mine_pending_transactions(&miner).unwrap();
+    }
+    assert!(source.difficulty > 1);
+
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    blockchain.difficulty_adjustment_interval = 2;
+    blockchain.replace_chain(source.chain.clone()).unwrap();
+    assert_eq!(blockchain.difficulty, source.difficulty);
+    assert_eq!(blockchain.block_time_window, source.block_time_window);
+
+    // The next block is mined at the difficulty the adopted chain calls for
+    blockchain.mine_pending_transactions(&miner).unwrap();
+    assert!(blockchain.validate_chain());
+}
+
+#[test]
+fn full_chain_replacing_a_pruned_one_does_not_double_count_balances() {
+    let mut source = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    let (_, miner) = create_keypair();
+    for _ in 0..4 {
+        source.mine_pending_transactions(&miner).unwrap();
+    }
+    let mut blockchain = Blockchain::from_blocks(source.chain.clone(), 1, coins(10.0), Duration::seconds(10)).unwrap();
+    blockchain.prune_to(2);
+    let mut reloaded = Blockchain::from_blocks(source.chain.clone(), 1, coins(10.0), Duration::seconds(10)).unwrap();
+    reloaded.prune_to(2);
+
+    source.mine_pending_transactions(&miner).unwrap();
+    source.mine_pending_transactions(&miner).unwrap();
+    blockchain.replace_chain(source.chain.clone()).unwrap();
+    assert_eq!(blockchain.get_balance(&miner), source.get_balance(&miner));
+    assert_eq!(blockchain.get_balance(&miner), coins(60.0));
+    assert!(blockchain.validate_chain());
+
+    // Loading a full chain file over a pruned chain reconciles the same way
+    let path = temp_path("full-chain.json");
+    source.save_chain(path.to_str().unwrap()).unwrap();
+    reloaded.load_chain(path.to_str().unwrap()).unwrap();
+    assert_eq!(reloaded.chain.len(), source.chain.len());
+    assert_eq!(reloaded.get_balance(&miner), coins(60.0));
+    assert_eq!(reloaded.difficulty, source.difficulty);
+    assert!(reloaded.validate_chain());
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn block_serialized_size_matches_encoded_length() {
+    let (alice_key, alice) = create_keypair();
+    let transactions = vec![
+        signed_transaction(&alice_key, &alice, "bob", 1.0, 0.1),
+        signed_transaction(&alice_key, &alice, "carol", 2.0, 0.2),
+    ];
+    let block = Block::new(1, transactions, "0".repeat(64), 1);
+
+    let header_len = BINARY_MAGIC.len() + 2;
+    assert_eq!(block.serialized_size(), encode_binary(&block).unwrap().len() - header_len);
+
+    let empty = Block::new(1, Vec::new(), "0".repeat(64), 1);
+    assert!(block.serialized_size() > empty.serialized_size());
+}
+
+#[test]
+fn balance_snapshot_restores_and_replays_later_blocks() {
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    let (miner_key, miner) = create_keypair();
+    let (_, bob) = create_keypair();
+    for _ in 0..3 {
+        blockchain.mine_pending_transactions(&miner).unwrap();
+    }
+    blockchain.recalculate_balances();
+    let snapshot = blockchain.snapshot_balances();
+    assert_eq!(snapshot.height, 3);
+    assert_eq!(snapshot.balances[&miner], coins(30.0));
+
+    // Round-trips through JSON so it can be persisted alongside the chain
+    let snapshot: BalanceSnapshot = serde_json::from_str(&serde_json::to_string(&snapshot).unwrap()).unwrap();
+
+    push_mined_block(&mut blockchain, vec![signed_transaction(&miner_key, &miner, &bob, 5.0, 0.0)]);
+    blockchain.recalculate_balances();
+    let expected = blockchain.snapshot_balances().balances;
+
+    blockchain.add_balance("ghost", coins(99.0));
+    blockchain.restore_from_snapshot(snapshot.clone()).unwrap();
+    assert_eq!(blockchain.snapshot_balances().balances, expected);
+    assert_eq!(blockchain.get_balance("ghost"), coins(0.0));
+    assert_eq!(blockchain.get_balance(&bob), coins(5.0));
+
+    let mut ahead = snapshot;
+    ahead.height = 10;
+    assert!(blockchain.restore_from_snapshot(ahead).is_err());
+}
+
+#[test]
+fn parallel_merkle_root_matches_serial_root() {
+    let transactions: Vec<Transaction> = (0..10_000)
+        .map(|i| Transaction::new(format!("sender{}", i), "receiver".to_string(), coins(i as f64), coins(0.1)))
+        .collect();
+    let leaves: Vec<Vec<u8>> = transactions.iter().map(|tx| tx.calculate_hash()).collect();
+
+    let serial = MerkleTree::from_leaves_with_threads(leaves.clone(), 1);
+    for threads in [2, 3, 8] {
+        assert_eq!(MerkleTree::from_leaves_with_threads(leaves.clone(), threads).root, serial.root);
+    }
+    assert_eq!(MerkleTree::new(&transactions).root, serial.root);
+
+    // Odd level sizes must duplicate the same trailing node as the serial build
+    let odd: Vec<Vec<u8>> = leaves[..4_099].to_vec();
+    assert_eq!(
+        MerkleTree::from_leaves_with_threads(odd.clone(), 4).root,
+        MerkleTree::from_leaves_with_threads(odd, 1).root
+    );
+}
+
+fn wait_until(condition: impl Fn() -> bool) -> bool {
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    while std::time::Instant::now() < deadline {
+        if condition() {
+            return true;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    false
+}
+
+#[test]
+fn nodes_relay_accepted_transactions_to_other_peers() {
+    let (alice_key, alice) = create_keypair();
+    let spawn_node = || {
+        let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+        blockchain.add_balance(&alice, coins(100.0));
+        Node::bind("127.0.0.1:0", Arc::new(Mutex::new(blockchain))).unwrap()
+    };
+    let hub = spawn_node();
+    let sender = spawn_node();
+    let listener = spawn_node();
+    sender.connect(hub.local_addr()).unwrap();
+    listener.connect(hub.local_addr()).unwrap();
+    assert!(wait_until(|| hub.peer_count() == 2));
+
+    let tx = signed_transaction(&alice_key, &alice, "bob", 5.0, 0.5);
+    sender.submit_transaction(tx.clone()).unwrap();
+
+    let has_tx = |node: &Node| node.blockchain().lock().unwrap().mempool.iter().any(|t| t.id == tx.id);
+    assert!(wait_until(|| has_tx(&hub) && has_tx(&listener)));
+    assert_eq!(listener.blockchain().lock().unwrap().mempool.len(), 1);
+
+    // Resubmitting is rejected locally, so nothing loops between peers
+    assert!(sender.submit_transaction(tx).is_err());
+}
+
+#[test]
+fn orphan_blocks_wait_for_their_parent() {
+    let mut source = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    let (_, miner) = create_keypair();
+    source.mine_pending_transactions(&miner).unwrap();
+    source.mine_pending_transactions(&miner).unwrap();
+
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    assert_eq!(blockchain.submit_block(source.chain[2].clone()).unwrap(), 0);
+    let orphans = blockchain.orphan_blocks();
+    assert_eq!(orphans.len(), 1);
+    assert_eq!(orphans[0].hash, source.chain[2].hash);
+
+    assert_eq!(blockchain.submit_block(source.chain[1].clone()).unwrap(), 2);
+    assert!(blockchain.orphan_blocks().is_empty());
+    assert_eq!(blockchain.get_latest_block().hash, source.chain[2].hash);
+
+    // Blocks that neither extend the tip nor lie ahead of it are rejected
+    assert!(blockchain.submit_block(source.chain[1].clone()).is_err());
+}
+
+#[test]
+fn orphan_below_the_chain_difficulty_is_refused() {
+    let mut blockchain = Blockchain::new(2, coins(10.0), Duration::seconds(10));
+    let mut free = Block::new(5, Vec::new(), format!("{:064x}", 1), 0);
+    free.mine_block(0, &AtomicBool::new(false), u64::MAX);
+
+    let err = blockchain.submit_block(free).unwrap_err();
+    assert!(err.message().contains("below the chain difficulty 2"), "{}", err);
+    assert!(blockchain.orphan_blocks().is_empty());
+}
+
+#[test]
+fn full_orphan_pool_evicts_oldest_and_stale_orphans() {
+    let mut source = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    let (_, miner) = create_keypair();
+    source.mine_pending_transactions(&miner).unwrap();
+    source.mine_pending_transactions(&miner).unwrap();
+
+    // Fill the pool with junk orphans at height 3 whose parents will never arrive
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    let junk: Vec<Block> = (0..100)
+        .map(|i| {
+            let mut block = Block::new(3, Vec::new(), format!("{:064x}", i), 1);
+            block.mine_block(1, &AtomicBool::new(false), u64::MAX);
+            block
+        })
+        .collect();
+    for block in &junk {
+        assert_eq!(blockchain.submit_block(block.clone()).unwrap(), 0);
+    }
+    assert_eq!(blockchain.orphan_blocks().len(), 100);
+
+    // A real orphan still gets in, displacing the oldest junk
+    assert_eq!(blockchain.submit_block(source.chain[2].clone()).unwrap(), 0);
+    let orphans = blockchain.orphan_blocks();
+    assert_eq!(orphans.len(), 100);
+    assert!(orphans.iter().all(|orphan| orphan.hash != junk[0].hash));
+
+    // Once the chain reaches height 2, orphans that can't extend it are dropped
+    assert_eq!(blockchain.submit_block(source.chain[1].clone()).unwrap(), 2);
+    assert_eq!(blockchain.orphan_blocks().len(), 99);
+    source.mine_pending_transactions(&miner).unwrap();
+    assert_eq!(blockchain.submit_block(source.chain[3].clone()).unwrap(), 1);
+    assert!(blockchain.orphan_blocks().is_empty());
+}
+
+#[test]
+fn gossiped_blocks_converge_two_nodes() {
+    let spawn_node = || {
+        let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+        blockchain.mining_threads = 1;
+        Node::bind("127.0.0.1:0", Arc::new(Mutex::new(blockchain))).unwrap()
+    };
+    let (_, miner) = create_keypair();
+    let first = spawn_node();
+    let second = spawn_node();
+
+    // The first node gets ahead before the nodes are connected
+    first.mine_pending_transactions(&miner).unwrap();
+    first.mine_pending_transactions(&miner).unwrap();
+    second.connect(first.local_addr()).unwrap();
+    assert!(wait_until(|| first.peer_count() == 1));
+
+    let tip = |node: &Node| node.blockchain().lock().unwrap().get_latest_block().hash.clone();
+    let mined = first.mine_pending_transactions(&miner).unwrap();
+    assert!(wait_until(|| tip(&second) == mined.hash));
+    assert_eq!(second.blockchain().lock().unwrap().chain.len(), 4);
+
+    let mined = second.mine_pending_transactions(&miner).unwrap();
+    assert!(wait_until(|| tip(&first) == mined.hash));
+    assert!(first.blockchain().lock().unwrap().validate_chain());
+}
+
+#[test]
+fn amount_converts_between_coins_and_base_units() {
+    let amount = Amount::from_coins(1.5).unwrap();
+    assert_eq!(amount.base_units(), 150_000_000);
+    assert_eq!(amount.to_coins(), 1.5);
+    assert_eq!(amount.to_string(), "1.50000000");
+    assert_eq!(Amount::from_base_units(1).to_string(), "0.00000001");
+
+    // Values are rounded to the nearest base unit, with halves rounding up
+    assert_eq!(Amount::from_coins(0.000000005).unwrap().base_units(), 1);
+    assert_eq!(Amount::from_coins(0.0000000049).unwrap().base_units(), 0);
+    assert_eq!(Amount::from_coins(0.1).unwrap().base_units(), 10_000_000);
+    for units in [0, 1, 99_999_999, 100_000_001, 2_100_000_000_000_000] {
+        assert_eq!(Amount::from_coins(Amount::from_base_units(units).to_coins()).unwrap().base_units(), units);
+    }
+
+    assert!(Amount::from_coins(-1.0).is_err());
+    assert!(Amount::from_coins(f64::NAN).is_err());
+    assert!(Amount::from_coins(f64::INFINITY).is_err());
+    assert_eq!(Amount::from_base_units(5).checked_sub(Amount::from_base_units(6)), None);
+}
+
+#[test]
+fn headers_are_validated_without_transactions() {
+    let mut source = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    let (_, miner) = create_keypair();
+    for _ in 0..3 {
+        source.mine_pending_transactions(&miner).unwrap();
+    }
+    let headers: Vec<BlockHeader> = source.chain[1..].iter().map(|block| block.header()).collect();
+    assert_eq!(headers[0].calculate_hash(), source.chain[1].hash);
+
+    let fresh = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    fresh.validate_headers(&headers).unwrap();
+    assert!(fresh.validate_headers(&headers[1..]).is_err());
+
+    let mut tampered = headers.clone();
+    tampered[1].nonce += 1;
+    assert!(fresh.validate_headers(&tampered).is_err());
+}
+
+#[test]
+fn headers_below_the_expected_difficulty_are_rejected() {
+    let blockchain = Blockchain::new(2, coins(10.0), Duration::seconds(10));
+    let genesis = blockchain.get_latest_block();
+    let mut free = Block::new(1, Vec::new(), genesis.hash.clone(), 0);
+    free.mine_block(0, &AtomicBool::new(false), u64::MAX);
+
+    let err = blockchain.validate_headers(&[free.header()]).unwrap_err();
+    assert_eq!(err.message(), "Header 1 has difficulty 0 but 2 was expected");
+}
+
+#[test]
+fn fresh_node_syncs_headers_first() {
+    let spawn_node = || {
+        let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+        blockchain.mining_threads = 1;
+        Node::bind("127.0.0.1:0", Arc::new(Mutex::new(blockchain))).unwrap()
+    };
+    let (_, miner) = create_keypair();
+    let seed = spawn_node();
+    for _ in 0..5 {
+        seed.mine_pending_transactions(&miner).unwrap();
+    }
+
+    let fresh = spawn_node();
+    fresh.connect(seed.local_addr()).unwrap();
+    assert!(wait_until(|| seed.peer_count() == 1));
+    fresh.sync();
+
+    let seed_tip = seed.blockchain().lock().unwrap().get_latest_block().hash.clone();
+    assert!(wait_until(|| fresh.blockchain().lock().unwrap().get_latest_block().hash == seed_tip));
+    assert!(fresh.blockchain().lock().unwrap().validate_chain());
+}
+
+#[test]
+fn far_future_expiration_is_rejected_when_capped() {
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    let (alice_key, alice) = create_keypair();
+    blockchain.add_balance(&alice, coins(100.0));
+    let far_future = |amount: f64, nonce: u64| {
+        let mut tx = Transaction::new(alice.clone(), "bob".to_string(), coins(amount), coins(0.1));
+        tx.expiration = (Utc::now() + Duration::days(365)).timestamp();
+        tx.nonce = nonce;
+        tx.sign(&alice_key);
+        tx
+    };
+
+    // Without a cap the transaction is admitted as before
+    blockchain.add_to_mempool(far_future(1.0, 0)).unwrap();
+
+    blockchain.max_expiration_horizon = Some(Duration::hours(72));
+    let err = blockchain.add_to_mempool(far_future(2.0, 1)).unwrap_err();
+    assert!(err.message().contains("too far in the future"));
+    blockchain.add_to_mempool(next_signed_transaction(&blockchain, &alice_key, &alice, "bob", 3.0, 0.1)).unwrap();
+}
+
+#[test]
+fn handshake_refuses_peers_on_another_chain() {
+    let spawn_node = |chain_id: u64| {
+        let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+        blockchain.chain_id = chain_id;
+        Node::bind("127.0.0.1:0", Arc::new(Mutex::new(blockchain))).unwrap()
+    };
+    let mainnet = spawn_node(1);
+    let testnet = spawn_node(2);
+    let other_mainnet = spawn_node(1);
+
+    testnet.connect(mainnet.local_addr()).unwrap();
+    other_mainnet.connect(mainnet.local_addr()).unwrap();
+    assert!(wait_until(|| mainnet.peer_count() == 1 && other_mainnet.peer_count() == 1));
+
+    // The testnet connection was opened first; give its handshake time to finish before checking
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    assert_eq!(mainnet.peer_count(), 1);
+    assert_eq!(testnet.peer_count(), 0);
+}
+
+#[test]
+fn misbehaving_peer_is_banned_until_unbanned() {
+    let node = Node::bind("127.0.0.1:0", Arc::new(Mutex::new(Blockchain::new(1, coins(10.0), Duration::seconds(10))))).unwrap();
+
+    // A raw client completes the handshake, then sends junk
+    let mut client = std::net::TcpStream::connect(node.local_addr()).unwrap();
+    let version = NetMessage::Version { protocol_version: PROTOCOL_VERSION, chain_id: 1, tip_index: 0 };
+    write_frame(&mut client, &serde_json::to_vec(&version).unwrap()).unwrap();
+    assert!(wait_until(|| node.peer_count() == 1));
+    write_frame(&mut client, b"not json").unwrap();
+    write_frame(&mut client, b"still not json").unwrap();
+
+    let localhost: std::net::IpAddr = "127.0.0.1".parse().unwrap();
+    assert!(wait_until(|| node.banned_peers() == vec![localhost]));
+    assert_eq!(node.peer_count(), 0);
+
+    // Banned addresses cannot reconnect
+    let peer = Node::bind("127.0.0.1:0", Arc::new(Mutex::new(Blockchain::new(1, coins(10.0), Duration::seconds(10))))).unwrap();
+    // The node may hang up before the connect completes, so either outcome is fine here
+    let _ = peer.connect(node.local_addr());
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    assert_eq!(node.peer_count(), 0);
+
+    assert!(node.unban(localhost));
+    assert!(!node.unban(localhost));
+    peer.connect(node.local_addr()).unwrap();
+    assert!(wait_until(|| node.peer_count() == 1));
+}
+
+#[test]
+fn paused_node_stops_mining_until_resumed() {
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    let clock = Arc::new(MockClock::new(Utc::now()));
+    blockchain.clock = clock.clone();
+    blockchain.mempool_tx_ttl = Some(Duration::minutes(10));
+    let (alice_key, alice) = create_keypair();
+    let (_, miner) = create_keypair();
+    blockchain.add_balance(&alice, coins(100.0));
+    blockchain.add_to_mempool(signed_transaction(&alice_key, &alice, "bob", 1.0, 0.1)).unwrap();
+
+    blockchain.pause();
+    assert!(blockchain.is_paused());
+    assert!(blockchain.mine_pending_transactions(&miner).is_err());
+    assert_eq!(blockchain.chain.len(), 1);
+
+    // The janitor leaves the mempool alone while paused
+    clock.advance(Duration::minutes(11));
+    blockchain.clean_expired_transactions();
+    assert_eq!(blockchain.mempool.len(), 1);
+
+    blockchain.resume();
+    blockchain.clean_expired_transactions();
+    assert!(blockchain.mempool.is_empty());
+    blockchain.mine_pending_transactions(&miner).unwrap();
+    assert_eq!(blockchain.chain.len(), 2);
+}
+
+fn rpc_call(addr: std::net::SocketAddr, method: &str, params: serde_json::Value) -> serde_json::Value {
+    use std::io::{Read, Write};
+    let body = serde_json::json!({ "jsonrpc": "2.0", "method": method, "params": params, "id": 1 }).to_string();
+    let mut stream = std::net::TcpStream::connect(addr).unwrap();
+    write!(stream, "POST / HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", addr, body.len(), body).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (_, body) = response.split_once("\r\n\r\n").unwrap();
+    serde_json::from_str(body).unwrap()
+}
+
+#[test]
+fn rpc_server_answers_queries_and_reports_errors() {
+    let blockchain = Arc::new(Mutex::new(Blockchain::new(1, coins(10.0), Duration::seconds(10))));
+    let (alice_key, alice) = create_keypair();
+    blockchain.lock().unwrap().add_balance(&alice, coins(10.0));
+    let server = RpcServer::bind("127.0.0.1:0", Arc::clone(&blockchain)).unwrap();
+    let addr = server.local_addr();
+
+    assert_eq!(rpc_call(addr, "getbalance", serde_json::json!([alice]))["result"], coins(10.0).base_units());
+    assert_eq!(rpc_call(addr, "getbalance", serde_json::json!(["nobody"]))["error"]["code"], RpcError::ADDRESS_NOT_FOUND);
+
+    let transaction = signed_transaction(&alice_key, &alice, "bob", 2.0, 0.1);
+    let raw = serde_json::to_string(&transaction).unwrap();
+    assert_eq!(rpc_call(addr, "sendtransaction", serde_json::json!([raw]))["result"], transaction.id.as_str());
+    assert_eq!(rpc_call(addr, "gettransaction", serde_json::json!([transaction.id]))["result"]["to"], "bob");
+
+    let overdraft = serde_json::to_string(&signed_transaction(&alice_key, &alice, "bob", 50.0, 0.1)).unwrap();
+    assert_eq!(rpc_call(addr, "sendtransaction", serde_json::json!([overdraft]))["error"]["code"], RpcError::INSUFFICIENT_BALANCE);
+
+    let genesis_hash = blockchain.lock().unwrap().genesis_hash.clone();
+    assert_eq!(rpc_call(addr, "getblock", serde_json::json!([0]))["result"]["hash"], genesis_hash.as_str());
+    assert_eq!(rpc_call(addr, "getblock", serde_json::json!([7]))["error"]["code"], RpcError::BLOCK_NOT_FOUND);
+
+    let info = rpc_call(addr, "getchaininfo", serde_json::json!([]));
+    assert_eq!(info["result"]["height"], 0);
+    assert_eq!(info["result"]["mempool_size"], 1);
+    assert_eq!(rpc_call(addr, "nosuchmethod", serde_json::json!([]))["error"]["code"], RpcError::METHOD_NOT_FOUND);
+}
+
+#[test]
+fn confirmation_waiter_returns_once_the_depth_is_reached() {
+    let blockchain = Arc::new(Mutex::new(Blockchain::new(1, coins(10.0), Duration::seconds(10))));
+    let (alice_key, alice) = create_keypair();
+    let (_, miner) = create_keypair();
+    let transaction = {
+        let mut chain = blockchain.lock().unwrap();
+        chain.add_balance(&alice, coins(100.0));
+        let transaction = signed_transaction(&alice_key, &alice, "bob", 5.0, 0.1);
+        chain.add_to_mempool(transaction.clone()).unwrap();
+        transaction
+    };
+
+    let mining = {
+        let blockchain = Arc::clone(&blockchain);
+        std::thread::spawn(move || {
+            for _ in 0..3 {
+                std::thread::sleep(std::time::Duration::from_millis(50));
+                blockchain.lock().unwrap().mine_pending_transactions(&miner).unwrap();
+            }
+        })
+    };
+    let depth = Blockchain::wait_for_confirmation(&blockchain, &transaction.id, 3, std::time::Duration::from_secs(10));
+    assert_eq!(depth, Ok(3));
+    mining.join().unwrap();
+
+    // Nothing mines a fourth block, so waiting for one times out at the depth reached
+    let depth = Blockchain::wait_for_confirmation(&blockchain, &transaction.id, 4, std::time::Duration::from_millis(100));
+    assert_eq!(depth, Err(WaitError::Timeout(3)));
+}
+
+#[test]
+fn subscriber_is_alerted_when_a_reorg_drops_their_transaction() {
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    let (alice_key, alice) = create_keypair();
+    let (_, miner) = create_keypair();
+    blockchain.add_balance(&alice, coins(100.0));
+    let updates = blockchain.subscribe_address(&alice);
+
+    let transaction = signed_transaction(&alice_key, &alice, "bob", 5.0, 0.1);
+    blockchain.add_to_mempool(transaction.clone()).unwrap();
+    blockchain.mine_pending_transactions(&miner).unwrap();
+
+    // A competing chain that never included the transaction overtakes ours
+    let mut rival = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    rival.mine_pending_transactions(&miner).unwrap();
+    rival.mine_pending_transactions(&miner).unwrap();
+    blockchain.replace_chain(rival.chain.clone()).unwrap();
+
+    let statuses: Vec<TransactionStatus> = updates.try_iter()
+        .filter(|update| update.transaction_id == transaction.id)
+        .map(|update| update.status)
+        .collect();
+    // Alice's granted funds survive the reorg, so the dropped transaction is pending again
+    assert_eq!(statuses, vec![TransactionStatus::Pending, TransactionStatus::Confirmed(1), TransactionStatus::Reorged, TransactionStatus::Pending]);
+}
+
+#[test]
+fn reorged_transaction_returns_to_the_mempool_and_pays_its_fee_once() {
+    let (alice_key, alice) = create_keypair();
+    let (_, old_miner) = create_keypair();
+    let (_, new_miner) = create_keypair();
+    let mut source = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    source.mine_pending_transactions(&alice).unwrap();
+    let mut blockchain = Blockchain::from_blocks(source.chain.clone(), 1, coins(10.0), Duration::seconds(10)).unwrap();
+    let mut rival = Blockchain::from_blocks(source.chain.clone(), 1, coins(10.0), Duration::seconds(10)).unwrap();
+
+    let transaction = next_signed_transaction(&blockchain, &alice_key, &alice, "bob", 4.0, 0.5);
+    blockchain.add_to_mempool(transaction.clone()).unwrap();
+    blockchain.mine_pending_transactions(&old_miner).unwrap();
+    assert_eq!(blockchain.get_balance(&old_miner), coins(10.5));
+
+    // A competing chain that never included the transaction overtakes ours
+    rival.mine_pending_transactions(&new_miner).unwrap();
+    rival.mine_pending_transactions(&new_miner).unwrap();
+    blockchain.replace_chain(rival.chain.clone()).unwrap();
+    assert_eq!(blockchain.mempool.iter().map(|tx| &tx.id).collect::<Vec<_>>(), vec![&transaction.id]);
+    assert_eq!(blockchain.mempool[0].fee, coins(0.5));
+    assert_eq!(blockchain.get_balance(&old_miner), Amount::ZERO);
+    assert_eq!(blockchain.get_balance(&alice), coins(10.0));
+
+    // Mined again on the new chain, the fee moves from alice to the new miner exactly once
+    blockchain.mine_pending_transactions(&new_miner).unwrap();
+    assert!(blockchain.mempool.is_empty());
+    assert_eq!(blockchain.get_balance(&alice), coins(5.5));
+    assert_eq!(blockchain.get_balance("bob"), coins(4.0));
+    assert_eq!(blockchain.get_balance(&new_miner), coins(30.5));
+    assert!(blockchain.validate_chain());
+}
+
+#[test]
+fn websocket_subscriber_receives_mined_block() {
+    let blockchain = Arc::new(Mutex::new(Blockchain::new(1, coins(10.0), Duration::seconds(10))));
+    let server = WebSocketServer::bind("127.0.0.1:0", Arc::clone(&blockchain)).unwrap();
+    let stream = std::net::TcpStream::connect(server.local_addr()).unwrap();
+    stream.set_read_timeout(Some(std::time::Duration::from_secs(5))).unwrap();
+    let (mut socket, _) = tungstenite::client(format!("ws://{}", server.local_addr()), stream).unwrap();
+    let receive = |socket: &mut tungstenite::WebSocket<std::net::TcpStream>| -> serde_json::Value {
+        serde_json::from_str(socket.read().unwrap().to_text().unwrap()).unwrap()
+    };
+
+    socket.send(tungstenite::Message::Text(r#"{"subscribe": "newBlock"}"#.to_string())).unwrap();
+    assert_eq!(receive(&mut socket)["subscribed"], "newBlock");
+
+    let (_, miner) = create_keypair();
+    blockchain.lock().unwrap().mine_pending_transactions(&miner).unwrap();
+    let mined_hash = blockchain.lock().unwrap().get_latest_block().hash.clone();
+
+    let event = receive(&mut socket);
+    assert_eq!(event["channel"], "newBlock");
+    assert_eq!(event["data"]["hash"], mined_hash.as_str());
+}
+
+#[test]
+fn block_exceeding_per_address_outflow_cap_is_rejected() {
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    blockchain.max_address_outflow_per_block = Some(coins(5.0));
+    let (alice_key, alice) = create_keypair();
+    blockchain.add_balance(&alice, coins(10.0));
+    let mut transactions = vec![
+        signed_transaction(&alice_key, &alice, "bob", 3.0, 0.1),
+        signed_transaction(&alice_key, &alice, "carol", 3.0, 0.1),
+    ];
+    transactions.push(next_coinbase(&blockchain, &transactions));
+
+    let previous = blockchain.get_latest_block().clone();
+    let mut block = Block::new(previous.index + 1, transactions.clone(), previous.hash.clone(), blockchain.difficulty);
+    block.mine_block(blockchain.difficulty, &AtomicBool::new(false), u64::MAX);
+    let err = blockchain.add_block(block.clone()).unwrap_err();
+    assert!(err.message().contains("sends more than"), "{}", err);
+    assert_eq!(blockchain.chain.len(), 1);
+
+    // The same block is fine without the cap
+    blockchain.max_address_outflow_per_block = None;
+    blockchain.add_block(block).unwrap();
+}
+
+#[test]
+fn transaction_lookup_by_id_follows_new_blocks() {
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    let (alice_key, alice) = create_keypair();
+    let first = signed_transaction(&alice_key, &alice, "bob", 1.0, 0.1);
+    push_mined_block(&mut blockchain, vec![first.clone()]);
+    assert_eq!(blockchain.get_transaction_by_id(&first.id).map(|(index, tx)| (index, tx.id.clone())), Some((1, first.id.clone())));
+
+    // The index is rebuilt once another block is appended
+    let second = signed_transaction(&alice_key, &alice, "carol", 2.0, 0.1);
+    assert!(blockchain.get_transaction_by_id(&second.id).is_none());
+    push_mined_block(&mut blockchain, vec![signed_transaction(&alice_key, &alice, "dave", 1.0, 0.1), second.clone()]);
+    let (index, found) = blockchain.get_transaction_by_id(&second.id).unwrap();
+    assert_eq!(index, 2);
+    assert_eq!(found.to, "carol");
+}
+
+#[test]
+fn ndjson_export_streams_one_block_per_line() {
+    use std::io::BufRead;
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    let (alice_key, alice) = create_keypair();
+    push_mined_block(&mut blockchain, vec![signed_transaction(&alice_key, &alice, "bob", 1.0, 0.1)]);
+    push_mined_block(&mut blockchain, Vec::new());
+
+    let mut exported = Vec::new();
+    blockchain.export_ndjson(&mut exported).unwrap();
+
+    let blocks: Vec<Block> = exported.lines()
+        .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+        .collect();
+    assert_eq!(blocks.len(), 3);
+    for (read, original) in blocks.iter().zip(&blockchain.chain) {
+        assert_eq!(read.hash, original.hash);
+        assert_eq!(read.transactions.len(), original.transactions.len());
+    }
+}
+
+#[test]
+fn blocks_can_be_fetched_by_index_and_hash() {
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    let (_, miner) = create_keypair();
+    blockchain.mine_pending_transactions(&miner).unwrap();
+    blockchain.mine_pending_transactions(&miner).unwrap();
+
+    let second = blockchain.chain[1].clone();
+    assert_eq!(blockchain.get_block_by_index(1).map(|block| &block.hash), Some(&second.hash));
+    assert_eq!(blockchain.get_block_by_hash(&second.hash).map(|block| block.index), Some(1));
+    assert_eq!(blockchain.get_block_by_hash(&blockchain.genesis_hash.clone()).map(|block| block.index), Some(0));
+    assert!(blockchain.get_block_by_index(3).is_none());
+    assert!(blockchain.get_block_by_hash(&"0".repeat(64)).is_none());
+
+    // Lookups keep working by height once the start of the chain is pruned
+    blockchain.prune_to(2);
+    assert!(blockchain.get_block_by_index(0).is_none());
+    assert_eq!(blockchain.get_block_by_hash(&second.hash).map(|block| block.index), Some(1));
+}
+
+#[test]
+fn status_reports_tip_mempool_and_peers() {
+    let blockchain = Arc::new(Mutex::new(Blockchain::new(1, coins(10.0), Duration::seconds(10))));
+    let (alice_key, alice) = create_keypair();
+    let (_, miner) = create_keypair();
+    {
+        let mut blockchain = blockchain.lock().unwrap();
+        blockchain.mine_pending_transactions(&miner).unwrap();
+        blockchain.add_balance(&alice, coins(10.0));
+        blockchain.add_to_mempool(signed_transaction(&alice_key, &alice, "bob", 1.0, 0.1)).unwrap();
+    }
+
+    let status = blockchain.lock().unwrap().status();
+    let tip = blockchain.lock().unwrap().get_latest_block().clone();
+    assert_eq!(status.height, 1);
+    assert_eq!(status.tip_hash, tip.hash);
+    assert_eq!(status.last_block_time, tip.timestamp);
+    assert_eq!(status.difficulty, 1);
+    assert_eq!(status.mempool_transactions, 1);
+    assert!(status.mempool_bytes > 0);
+    assert!(status.chain_valid);
+    assert_eq!(status.peer_count, None);
+
+    let node = Node::bind("127.0.0.1:0", Arc::clone(&blockchain)).unwrap();
+    assert_eq!(node.status().peer_count, Some(0));
+}
+
+#[test]
+fn whole_chain_stats_cover_issuance_fees_and_mempool() {
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    let (alice_key, alice) = create_keypair();
+    let (_, miner) = create_keypair();
+    blockchain.add_balance(&alice, coins(100.0));
+    blockchain.add_to_mempool(next_signed_transaction(&blockchain, &alice_key, &alice, "bob", 5.0, 0.5)).unwrap();
+    blockchain.mine_pending_transactions(&miner).unwrap();
+    blockchain.mine_pending_transactions(&miner).unwrap();
+    blockchain.add_to_mempool(next_signed_transaction(&blockchain, &alice_key, &alice, "carol", 1.0, 0.2)).unwrap();
+
+    let stats = blockchain.stats();
+    assert_eq!(stats.block_count, 3);
+    assert_eq!(stats.transaction_count, 1);
+    assert_eq!(stats.total_issued, coins(20.0));
+    assert_eq!(stats.total_fees, coins(0.5));
+    assert_eq!(stats.difficulty, blockchain.difficulty);
+    assert_eq!(stats.mempool_transactions, 1);
+    assert!(stats.mempool_bytes > 0);
+
+    let json = serde_json::to_value(&stats).unwrap();
+    assert_eq!(json["total_issued"], coins(20.0).base_units());
+    assert!(json["average_block_time_ms"].is_i64());
+}
+
+#[test]
+fn only_one_side_of_a_double_spend_is_admitted() {
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    let (alice_key, alice) = create_keypair();
+    blockchain.add_balance(&alice, coins(10.0));
+
+    let (first, second) = blockchain.attempt_double_spend(&alice_key, &alice, "bob", "carol", coins(8.0));
+    assert!(first);
+    assert!(!second);
+    assert_eq!(blockchain.mempool.len(), 1);
+    assert_eq!(blockchain.mempool[0].to, "bob");
+}
+
+#[test]
+fn strided_mining_only_visits_its_own_nonces() {
+    let mut block = Block::new(1, Vec::new(), "0".repeat(64), 8);
+    block.nonce = 1;
+    block.hash = block.calculate_hash();
+    assert!(block.mine_block_strided(&KrakenChain::blockchain::Sha256ProofOfWork, 8, 3, &AtomicBool::new(false), u64::MAX));
+    assert_eq!(block.nonce % 3, 1);
+    assert!(block.verify_self_hash());
+
+    // An exhausted budget leaves the block unsolved
+    let mut block = Block::new(1, Vec::new(), "0".repeat(64), 64);
+    assert!(!block.mine_block_strided(&KrakenChain::blockchain::Sha256ProofOfWork, 64, 2, &AtomicBool::new(false), 100));
+    assert_eq!(block.nonce, 200);
+}
+
+#[test]
+fn reward_halves_at_each_boundary() {
+    let mut blockchain = Blockchain::new(1, coins(50.0), Duration::seconds(10));
+    assert_eq!(blockchain.reward_at_height(1_000_000), coins(50.0));
+
+    blockchain.halving_interval = Some(100);
+    assert_eq!(blockchain.reward_at_height(0), coins(50.0));
+    assert_eq!(blockchain.reward_at_height(99), coins(50.0));
+    assert_eq!(blockchain.reward_at_height(100), coins(25.0));
+    assert_eq!(blockchain.reward_at_height(199), coins(25.0));
+    assert_eq!(blockchain.reward_at_height(200), coins(12.5));
+    assert_eq!(blockchain.reward_at_height(350), coins(6.25));
+    assert_eq!(blockchain.reward_at_height(100 * 64), Amount::ZERO);
+    assert_eq!(blockchain.reward_at_height(u64::MAX), Amount::ZERO);
+}
+
+#[test]
+fn mining_stops_when_signalled() {
+    let mut block = Block::new(1, Vec::new(), "0".repeat(64), 64);
+    let stop = AtomicBool::new(true);
+    assert!(!block.mine_block(64, &stop, u64::MAX));
+    assert_eq!(block.nonce, 0);
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let miner = {
+        let stop = Arc::clone(&stop);
+        std::thread::spawn(move || block.mine_block(64, &stop, u64::MAX))
+    };
+    std::thread::sleep(std::time::Duration::from_millis(50));
+    stop.store(true, std::sync::atomic::Ordering::Relaxed);
+    assert!(!miner.join().unwrap());
+}
+
+#[test]
+fn compact_targets_round_trip_and_are_enforced() {
+    use KrakenChain::blockchain::{compact_for_difficulty, compact_to_target, target_to_compact};
+
+    // Targets with at most three significant bytes survive the round trip exactly
+    for target in [U256::from(0x12u64), U256::from(0x1234u64), U256::from(0x7f_ffffu64), U256::from(0x12_3456u64) << 96, U256::from(0xffffu64) << 240] {
+        assert_eq!(compact_to_target(target_to_compact(target)), Some(target));
+    }
+    // Longer targets are rounded down, never up
+    let exact = U256::MAX >> 20;
+    let rounded = compact_to_target(target_to_compact(exact)).unwrap();
+    assert!(rounded <= exact && rounded > exact - (exact >> 15));
+    assert_eq!(target_to_compact(U256::from(0x80u64)), 0x0200_8000);
+    assert_eq!(compact_to_target(0x0380_0001), None); // Sign bit set
+    assert_eq!(compact_to_target(0x2201_0000), None); // Overflows 256 bits
+
+    // Mined blocks carry the bits for their difficulty and meet the target they encode
+    let mut blockchain = Blockchain::new(8, coins(10.0), Duration::seconds(10));
+    let (_, miner) = create_keypair();
+    blockchain.mine_pending_transactions(&miner).unwrap();
+    let block = blockchain.get_latest_block().clone();
+    assert_eq!(block.bits, compact_for_difficulty(8));
+    let hash = block.hash_to_u256(&block.hash).unwrap();
+    assert!(hash <= compact_to_target(block.bits).unwrap());
+    // Hashes that are not 64 hex characters, e.g. from a corrupt chain file, are refused rather than panicking
+    for malformed in ["not hex", "abcd", &"ff".repeat(33)] {
+        assert_eq!(block.hash_to_u256(malformed), None);
+    }
+
+    // A block claiming an easier target than its difficulty is rejected
+    let previous = blockchain.get_latest_block().clone();
+    let mut easier = Block::new(previous.index + 1, vec![next_coinbase(&blockchain, &[])], previous.hash.clone(), blockchain.difficulty);
+    easier.bits = compact_for_difficulty(1);
+    easier.hash = easier.calculate_hash();
+    easier.mine_block(1, &AtomicBool::new(false), u64::MAX);
+    let err = blockchain.add_block(easier).unwrap_err();
+    assert!(err.message().contains("bits"), "{}", err);
+}
+
+#[test]
+fn cancelled_mining_adds_no_block_and_keeps_transactions() {
+    let mut blockchain = Blockchain::new(64, coins(10.0), Duration::seconds(10));
+    let (alice_key, alice) = create_keypair();
+    let (_, miner) = create_keypair();
+    blockchain.add_balance(&alice, coins(10.0));
+    let transaction = signed_transaction(&alice_key, &alice, "bob", 1.0, 0.1);
+    blockchain.add_to_mempool(transaction.clone()).unwrap();
+    let first_seen = blockchain.first_seen(&transaction.id);
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let canceller = {
+        let stop = Arc::clone(&stop);
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            stop.store(true, std::sync::atomic::Ordering::Relaxed);
+        })
+    };
+    let err = blockchain.mine_pending_transactions_cancellable(&miner, stop).unwrap_err();
+    canceller.join().unwrap();
+
+    assert_eq!(err.message(), "Mining was cancelled");
+    assert_eq!(blockchain.chain.len(), 1);
+    assert_eq!(blockchain.mempool.len(), 1);
+    assert_eq!(blockchain.first_seen(&transaction.id), first_seen);
+}
+
+#[test]
+fn revalidating_a_chain_reuses_cached_signature_checks() {
+    use KrakenChain::blockchain::signature_verifications;
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    let (alice_key, alice) = create_keypair();
+    for _ in 0..3 {
+        let transactions = (0..4).map(|_| signed_transaction(&alice_key, &alice, "bob", 1.0, 0.1)).collect();
+        push_mined_block(&mut blockchain, transactions);
+    }
+
+    let before = signature_verifications();
+    assert!(blockchain.is_chain_valid());
+    let first_pass = signature_verifications() - before;
+    assert!(first_pass >= 12);
+
+    let before = signature_verifications();
+    assert!(blockchain.is_chain_valid());
+    assert_eq!(signature_verifications() - before, 0);
+
+    // Tampering after signing changes the signed hash, so the cached verdict does not apply
+    let mut tampered = blockchain.chain[1].transactions[0].clone();
+    tampered.amount = coins(500.0);
+    assert!(!tampered.is_valid());
+}
+
+#[test]
+fn proof_of_work_compares_the_full_hash() {
+    use KrakenChain::blockchain::Sha256ProofOfWork;
+    let pow = Sha256ProofOfWork;
+    assert_eq!(pow.target_for(0), U256::MAX);
+    assert_eq!(pow.target_for(200), U256::MAX >> 200);
+    assert_eq!(pow.target_for(256), U256::zero());
+
+    // Past 128 bits of difficulty the low half of the hash decides
+    let mut block = Block::new(1, Vec::new(), "0".repeat(64), 0);
+    block.hash = format!("{}{}", "0".repeat(33), "f".repeat(31));
+    assert!(pow.meets_target(&block, 130));
+    assert!(!pow.meets_target(&block, 133));
+
+    // A malformed hash never meets the target, even at difficulty 0
+    block.hash = "zz".repeat(32);
+    assert!(!pow.meets_target(&block, 0));
+}
+
+#[test]
+fn transaction_for_another_chain_is_rejected() {
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    let (alice_key, alice) = create_keypair();
+    blockchain.add_balance(&alice, coins(10.0));
+
+    let mut transaction = Transaction::new(alice.clone(), "bob".to_string(), coins(1.0), coins(0.1));
+    transaction.target_chain_id = 2;
+    transaction.sign(&alice_key);
+    let err = blockchain.add_to_mempool(transaction).unwrap_err();
+    assert_eq!(err.message(), "Transaction is for chain 2 but this is chain 1");
+    assert!(blockchain.mempool.is_empty());
+
+    // The chain id is signed, so it cannot be retargeted without the sender's key
+    let mut retagged = signed_transaction(&alice_key, &alice, "bob", 1.0, 0.1);
+    retagged.target_chain_id = 2;
+    assert!(!retagged.is_valid());
+}
+
+fn blocks_spaced_by(count: u64, spacing: Duration, difficulty: u32) -> Vec<Block> {
+    let start = Utc::now();
+    (0..count)
+        .map(|index| {
+            let mut block = Block::new(index, Vec::new(), "0".repeat(64), difficulty);
+            block.timestamp = start + spacing * index as i32;
+            block
+        })
+        .collect()
+}
+
+#[test]
+fn slow_blocks_lower_difficulty_and_fast_blocks_raise_it() {
+    let blockchain = Blockchain::new(8, coins(10.0), Duration::seconds(10));
+    // Genesis, the skipped first window, and a full window measured after it
+    let window = blockchain.difficulty_adjustment_interval * 2 + 1;
+
+    let slow = blocks_spaced_by(window, Duration::seconds(20), 8);
+    assert_eq!(blockchain.next_difficulty(8, &slow), 4);
+    let fast = blocks_spaced_by(window, Duration::seconds(5), 8);
+    assert_eq!(blockchain.next_difficulty(8, &fast), 16);
+    let on_target = blocks_spaced_by(window, Duration::seconds(10), 8);
+    assert_eq!(blockchain.next_difficulty(8, &on_target), 8);
+
+    // Extreme block times move the difficulty by at most a factor of four
+    assert_eq!(blockchain.next_difficulty(8, &blocks_spaced_by(window, Duration::hours(1), 8)), 2);
+    assert_eq!(blockchain.next_difficulty(8, &blocks_spaced_by(window, Duration::milliseconds(1), 8)), 32);
+
+    // Nothing changes between the ends of adjustment windows
+    assert_eq!(blockchain.next_difficulty(8, &fast[..window as usize - 1]), 8);
+    assert_eq!(blockchain.next_difficulty(8, &fast[..3]), 8);
+}
+
+// Builds a chain block by block from `difficulty`, taking each block's time from `block_time` given
+// the difficulty it was mined at, and returns the difficulty of every block after genesis
+fn simulate_retargeting(blockchain: &Blockchain, difficulty: u32, blocks: u64, block_time: impl Fn(u32) -> Duration) -> Vec<u32> {
+    let mut chain = blocks_spaced_by(1, Duration::zero(), difficulty);
+    let mut difficulties = Vec::new();
+    let mut difficulty = difficulty;
+    for index in 1..=blocks {
+        let mut block = Block::new(index, Vec::new(), "0".repeat(64), difficulty);
+        block.timestamp = chain.last().unwrap().timestamp + block_time(difficulty);
+        chain.push(block);
+        difficulties.push(difficulty);
+        difficulty = blockchain.next_difficulty(difficulty, &chain);
+    }
+    difficulties
+}
+
+#[test]
+fn retargeting_converges_on_target_block_time() {
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    blockchain.difficulty_adjustment_interval = 5;
+
+    // Block times proportional to difficulty settle at the difficulty that takes 10s per block
+    let difficulties = simulate_retargeting(&blockchain, 1, 50, |difficulty| Duration::seconds(difficulty as i64));
+    assert_eq!(&difficulties[..10], &[1; 10]);
+    assert_eq!(&difficulties[10..15], &[4; 5]);
+    assert!(difficulties[15..].iter().all(|&difficulty| difficulty == 10));
+
+    // Constantly fast blocks raise the difficulty at every window without ever overshooting back down
+    let difficulties = simulate_retargeting(&blockchain, 8, 30, |_| Duration::seconds(5));
+    assert!(difficulties.windows(2).all(|pair| pair[0] <= pair[1]));
+    assert_eq!(difficulties.last(), Some(&128));
+}
+
+#[test]
+fn mempool_clearance_estimate_counts_blocks_at_average_block_time() {
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    assert_eq!(blockchain.mempool_clearance_estimate(), Duration::zero());
+
+    let (key, sender) = create_keypair();
+    let (_, recipient) = create_keypair();
+    blockchain.add_balance(&sender, coins(100.0));
+    for i in 0..5 {
+        let tx = next_signed_transaction(&blockchain, &key, &sender, &recipient, 1.0 + i as f64, 0.1);
+        blockchain.add_to_mempool(tx).unwrap();
+    }
+
+    // Just over half the mempool fits in a block, so clearing it takes two
+    blockchain.max_block_size_bytes = blockchain.mempool_size_bytes / 2 + 1;
+    assert_eq!(blockchain.mempool_clearance_estimate(), Duration::seconds(20));
+
+    blockchain.block_time_window = vec![Duration::seconds(4), Duration::seconds(6)];
+    assert_eq!(blockchain.mempool_clearance_estimate(), Duration::seconds(10));
+
+    let first_block = blockchain.get_transactions_from_mempool(1000);
+    assert_eq!(first_block.len(), 2);
+    assert_eq!(blockchain.mempool.len(), 3);
+}
+
+#[test]
+fn proof_of_stake_assigns_blocks_to_staked_validators_without_hashing() {
+    // Far too hard to mine in a test, so blocks can only be added if hashing is skipped
+    let mut blockchain = Blockchain::with_consensus(64, coins(10.0), Duration::seconds(10), Consensus::ProofOfStake);
+    let (_, miner) = create_keypair();
+    assert!(blockchain.mine_pending_transactions(&miner).is_err());
+
+    let (_, alice) = create_keypair();
+    let (_, bob) = create_keypair();
+    blockchain.add_balance(&alice, coins(10.0));
+    assert_eq!(blockchain.next_validator(), Some(alice.clone()));
+    blockchain.mine_pending_transactions(&miner).unwrap();
+    let coinbase = blockchain.get_latest_block().transactions.last().unwrap();
+    assert_eq!(coinbase.to, alice);
+    assert_eq!(blockchain.get_latest_block().difficulty, 64);
+
+    blockchain.add_balance(&bob, coins(30.0));
+    for _ in 0..3 {
+        let validator = blockchain.next_validator().unwrap();
+        assert_eq!(blockchain.next_validator().as_ref(), Some(&validator));
+        blockchain.mine_pending_transactions(&miner).unwrap();
+        assert_eq!(blockchain.get_latest_block().transactions.last().unwrap().to, validator);
+    }
+    assert!(blockchain.validate_chain());
+
+    // A block paying anyone but the drawn validator is refused
+    let previous = blockchain.get_latest_block().clone();
+    let validator = blockchain.next_validator().unwrap();
+    let impostor = if validator == alice { &bob } else { &alice };
+    let coinbase = Transaction::new("Blockchain".to_string(), impostor.clone(), coins(10.0), coins(0.0));
+    let block = Block::new(previous.index + 1, vec![coinbase], previous.hash, 64);
+    assert!(blockchain.add_block(block).is_err());
+}
+
+#[test]
+fn proof_of_stake_chain_with_forged_producers_is_not_adopted() {
+    let mut blockchain = Blockchain::with_consensus(64, coins(10.0), Duration::seconds(10), Consensus::ProofOfStake);
+    let (_, alice) = create_keypair();
+    blockchain.add_balance(&alice, coins(10.0));
+    blockchain.mine_pending_transactions(&alice).unwrap();
+
+    // The attacker's own node grants them the stake, so every coinbase pays them
+    let mut forged = Blockchain::with_consensus(64, coins(10.0), Duration::seconds(10), Consensus::ProofOfStake);
+    let (_, mallory) = create_keypair();
+    forged.add_balance(&mallory, coins(10.0));
+    for _ in 0..5 {
+        forged.mine_pending_transactions(&mallory).unwrap();
+    }
+    assert!(forged.validate_chain());
+
+    let error = blockchain.replace_chain(forged.chain.clone()).unwrap_err();
+    assert!(error.message().contains("not produced by the selected validator"), "{}", error);
+    assert_eq!(blockchain.chain.len(), 2);
+    assert_eq!(blockchain.get_balance(&mallory), Amount::ZERO);
+    assert!(blockchain.validate_external_chain(&forged.chain).is_err());
+}
+
+#[test]
+fn verify_against_reports_a_divergent_chain_file() {
+    let (_, miner) = create_keypair();
+    let mut node = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    node.mine_pending_transactions(&miner).unwrap();
+    let path = temp_path("verify.json");
+    let path = path.to_str().unwrap();
+
+    node.save_chain(path).unwrap();
+    assert_eq!(node.verify_against(path), Ok(()));
+
+    // The same height, mined by someone else
+    let (_, other_miner) = create_keypair();
+    let mut other = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    other.mine_pending_transactions(&other_miner).unwrap();
+    other.save_chain(path).unwrap();
+
+    let divergences = node.verify_against(path).unwrap_err();
+    assert!(divergences.iter().any(|d| d.starts_with("Tip hash differs")));
+    assert!(!divergences.iter().any(|d| d.starts_with("Height differs")));
+    assert!(divergences.iter().any(|d| d.contains(&miner)));
+    assert!(divergences.iter().any(|d| d.contains(&other_miner)));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn mined_coinbase_follows_the_halving_schedule() {
+    let mut blockchain = Blockchain::with_halving_interval(1, coins(50.0), Duration::seconds(10), 3);
+    let (_, miner) = create_keypair();
+    for _ in 0..4 {
+        blockchain.mine_pending_transactions(&miner).unwrap();
+    }
+
+    let coinbase_amounts: Vec<Amount> = blockchain.chain[1..]
+        .iter()
+        .map(|block| block.transactions.iter().find(|tx| tx.from == "Blockchain").unwrap().amount)
+        .collect();
+    assert_eq!(coinbase_amounts, [50.0, 50.0, 25.0, 25.0].map(coins));
+    assert!(blockchain.validate_chain());
+
+    // A coinbase still claiming the pre-halving reward is refused
+    let previous = blockchain.get_latest_block().clone();
+    let coinbase = Transaction::new("Blockchain".to_string(), miner.clone(), coins(50.0), coins(0.0));
+    let mut block = Block::new(previous.index + 1, vec![coinbase], previous.hash, blockchain.difficulty);
+    block.mine_block(blockchain.difficulty, &AtomicBool::new(false), u64::MAX);
+    let error = blockchain.add_block(block).unwrap_err();
+    assert!(error.message().contains("expected reward is 25"), "{}", error);
+}
+
+#[test]
+fn transaction_proof_verifies_against_its_block_header() {
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    let (key, sender) = create_keypair();
+    let (_, recipient) = create_keypair();
+    let (_, miner) = create_keypair();
+    blockchain.add_balance(&sender, coins(100.0));
+    let mut transactions = Vec::new();
+    for i in 0..5 {
+        let tx = next_signed_transaction(&blockchain, &key, &sender, &recipient, 1.0 + i as f64, 0.1);
+        blockchain.add_to_mempool(tx.clone()).unwrap();
+        transactions.push(tx);
+    }
+    blockchain.mine_pending_transactions(&miner).unwrap();
+
+    for tx in &transactions {
+        let (header, proof) = blockchain.transaction_proof(&tx.id).unwrap();
+        assert_eq!(header, blockchain.get_latest_block().header());
+        assert!(MerkleTree::verify_proof(&header.merkle_root, tx, &proof));
+        let other = transactions.iter().find(|other| other.id != tx.id).unwrap();
+        assert!(!MerkleTree::verify_proof(&header.merkle_root, other, &proof));
+    }
+    assert!(blockchain.transaction_proof("no-such-transaction").is_none());
+}
+
+#[test]
+fn miner_collects_transaction_fees_in_the_coinbase() {
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    let (key, sender) = create_keypair();
+    let (_, recipient) = create_keypair();
+    let (_, miner) = create_keypair();
+    blockchain.add_balance(&sender, coins(100.0));
+    for fee in [0.25, 0.5] {
+        blockchain.add_to_mempool(next_signed_transaction(&blockchain, &key, &sender, &recipient, 5.0, fee)).unwrap();
+    }
+
+    blockchain.mine_pending_transactions(&miner).unwrap();
+
+    assert_eq!(blockchain.get_latest_block().transactions.last().unwrap().amount, coins(10.75));
+    assert_eq!(blockchain.get_balance(&miner), coins(10.0 + 0.25 + 0.5));
+    assert_eq!(blockchain.get_balance(&sender), coins(100.0 - 10.0 - 0.75));
+    assert!(blockchain.validate_chain());
+}
+
+#[test]
+fn coinbase_rewards_are_spendable_only_after_maturity() {
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    blockchain.coinbase_maturity = 2;
+    let (miner_key, miner) = create_keypair();
+    let (_, other_miner) = create_keypair();
+    let (_, recipient) = create_keypair();
+
+    blockchain.mine_pending_transactions(&miner).unwrap();
+    assert_eq!(blockchain.spendable_balance(&miner), Amount::ZERO);
+    let spend = || signed_transaction(&miner_key, &miner, &recipient, 5.0, 0.1);
+    assert!(blockchain.add_to_mempool(spend()).is_err());
+    assert!(blockchain.add_transaction(spend()).is_err());
+
+    blockchain.mine_pending_transactions(&other_miner).unwrap();
+    assert!(blockchain.add_to_mempool(spend()).is_err());
+
+    blockchain.mine_pending_transactions(&other_miner).unwrap();
+    assert_eq!(blockchain.spendable_balance(&miner), coins(10.0));
+    blockchain.add_to_mempool(spend()).unwrap();
+}
+
+#[test]
+fn mining_applies_each_block_to_balances_once() {
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    let (alice_key, alice) = create_keypair();
+    let (_, bob) = create_keypair();
+    let (_, miner) = create_keypair();
+    blockchain.add_balance(&alice, coins(100.0));
+
+    let mut expected_alice = coins(100.0);
+    let mut expected_bob = Amount::ZERO;
+    let mut expected_miner = Amount::ZERO;
+    for (amount, fee) in [(5.0, 0.5), (7.0, 0.25), (1.0, 0.125), (2.0, 0.0625)] {
+        blockchain.add_to_mempool(next_signed_transaction(&blockchain, &alice_key, &alice, &bob, amount, fee)).unwrap();
+        blockchain.mine_pending_transactions(&miner).unwrap();
+        expected_alice -= coins(amount) + coins(fee);
+        expected_bob += coins(amount);
+        expected_miner += coins(10.0) + coins(fee);
+
+        assert_eq!(blockchain.get_balance(&alice), expected_alice);
+        assert_eq!(blockchain.get_balance(&bob), expected_bob);
+        assert_eq!(blockchain.get_balance(&miner), expected_miner);
+    }
+
+    // Without the off-chain seed, a full re-walk agrees on everything the chain itself moved
+    blockchain.recalculate_balances();
+    assert_eq!(blockchain.get_balance(&bob), expected_bob);
+    assert_eq!(blockchain.get_balance(&miner), expected_miner);
+}
+
+#[test]
+fn block_overdrawing_a_sender_is_rejected() {
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    let (alice_key, alice) = create_keypair();
+    let (_, bob) = create_keypair();
+    let (_, miner) = create_keypair();
+    blockchain.add_balance(&alice, coins(5.0));
+
+    let block_with = |blockchain: &Blockchain, amount: f64| {
+        let previous = blockchain.get_latest_block();
+        let transfer = signed_transaction(&alice_key, &alice, &bob, amount, 0.5);
+        let coinbase = Transaction::new("Blockchain".to_string(), miner.clone(), coins(10.5), coins(0.0));
+        let mut block = Block::new(previous.index + 1, vec![transfer, coinbase], previous.hash.clone(), blockchain.difficulty);
+        block.mine_block(blockchain.difficulty, &AtomicBool::new(false), u64::MAX);
+        block
+    };
+
+    let error = blockchain.add_block(block_with(&blockchain, 50.0)).unwrap_err();
+    assert!(error.message().contains("overdraws"), "{}", error);
+    assert_eq!(blockchain.chain.len(), 1);
+    assert_eq!(blockchain.get_balance(&alice), coins(5.0));
+
+    blockchain.add_block(block_with(&blockchain, 4.5)).unwrap();
+    assert_eq!(blockchain.get_balance(&alice), coins(0.0));
+    assert_eq!(blockchain.get_balance(&bob), coins(4.5));
+}
+
+#[test]
+fn mining_an_overspending_mempool_builds_a_valid_block_and_keeps_the_rest() {
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    let (alice_key, alice) = create_keypair();
+    let (_, miner) = create_keypair();
+    blockchain.add_balance(&alice, coins(100.0));
+    // Set directly, as a mempool loaded from disk may hold more than the sender can pay
+    blockchain.mempool = (0..3)
+        .map(|nonce| {
+            let mut tx = Transaction::new(alice.clone(), "bob".to_string(), coins(40.0), coins(0.1));
+            tx.nonce = nonce;
+            tx.sign(&alice_key);
+            tx
+        })
+        .collect();
+
+    blockchain.mine_pending_transactions(&miner).unwrap();
+    assert_eq!(blockchain.get_latest_block().transactions.len(), 3);
+    assert_eq!(blockchain.mempool.len(), 1);
+    assert_eq!(blockchain.get_balance(&alice), coins(19.8));
+    assert!(blockchain.validate_chain());
+
+    // The deferred payment confirms once the sender can cover it
+    blockchain.add_balance(&alice, coins(30.0));
+    blockchain.mine_pending_transactions(&miner).unwrap();
+    assert!(blockchain.mempool.is_empty());
+    assert_eq!(blockchain.get_balance("bob"), coins(120.0));
+}
+
+#[test]
+fn conflicting_transactions_within_a_block_are_rejected() {
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    let (alice_key, alice) = create_keypair();
+    let (_, bob) = create_keypair();
+    let (_, carol) = create_keypair();
+    let (_, miner) = create_keypair();
+    blockchain.add_balance(&alice, coins(10.0));
+
+    let block_with = |blockchain: &Blockchain, transfers: Vec<Transaction>| {
+        let previous = blockchain.get_latest_block();
+        let fees: Amount = transfers.iter().map(|tx| tx.fee).sum();
+        let mut transactions = transfers;
+        transactions.push(Transaction::new("Blockchain".to_string(), miner.clone(), coins(10.0) + fees, Amount::ZERO));
+        let mut block = Block::new(previous.index + 1, transactions, previous.hash.clone(), blockchain.difficulty);
+        block.mine_block(blockchain.difficulty, &AtomicBool::new(false), u64::MAX);
+        block
+    };
+
+    // Two spends that each fit the balance but not together
+    let to_bob = signed_transaction(&alice_key, &alice, &bob, 6.0, 0.0);
+    let to_carol = signed_transaction(&alice_key, &alice, &carol, 6.0, 0.0);
+    let error = blockchain.add_block(block_with(&blockchain, vec![to_bob.clone(), to_carol])).unwrap_err();
+    assert!(error.message().contains("held only 10"), "{}", error);
+
+    // The same transaction included twice
+    let error = blockchain.add_block(block_with(&blockchain, vec![to_bob.clone(), to_bob.clone()])).unwrap_err();
+    assert!(error.message().contains("more than once"), "{}", error);
+    assert_eq!(blockchain.chain.len(), 1);
+
+    blockchain.add_block(block_with(&blockchain, vec![to_bob])).unwrap();
+    assert_eq!(blockchain.get_balance(&alice), coins(4.0));
+}
+
+#[test]
+fn blocks_must_carry_exactly_one_correct_coinbase() {
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    let (_, miner) = create_keypair();
+    let add = |blockchain: &mut Blockchain, transactions: Vec<Transaction>| {
+        let previous = blockchain.get_latest_block();
+        let mut block = Block::new(previous.index + 1, transactions, previous.hash.clone(), blockchain.difficulty);
+        block.mine_block(blockchain.difficulty, &AtomicBool::new(false), u64::MAX);
+        blockchain.add_block(block)
+    };
+    let coinbase = |amount: f64| Transaction::new("Blockchain".to_string(), miner.clone(), coins(amount), Amount::ZERO);
+
+    let error = add(&mut blockchain, Vec::new()).unwrap_err();
+    assert!(error.message().contains("exactly one coinbase transaction but has 0"), "{}", error);
+    let error = add(&mut blockchain, vec![coinbase(10.0), coinbase(10.0)]).unwrap_err();
+    assert!(error.message().contains("exactly one coinbase transaction but has 2"), "{}", error);
+    let error = add(&mut blockchain, vec![coinbase(10.5)]).unwrap_err();
+    assert!(error.message().contains("expected reward is 10"), "{}", error);
+    assert_eq!(blockchain.chain.len(), 1);
+
+    add(&mut blockchain, vec![coinbase(10.0)]).unwrap();
+    assert_eq!(blockchain.get_balance(&miner), coins(10.0));
+}
+
+#[test]
+fn tampering_with_fee_or_expiration_invalidates_the_signature() {
+    let (key, sender) = create_keypair();
+    let transaction = signed_transaction(&key, &sender, "bob", 5.0, 0.5);
+    assert!(transaction.is_valid());
+
+    let mut lower_fee = transaction.clone();
+    lower_fee.fee = coins(0.01);
+    assert!(!lower_fee.is_valid());
+
+    let mut later_expiration = transaction.clone();
+    later_expiration.expiration += 86_400;
+    assert!(!later_expiration.is_valid());
+}
+
+#[test]
+fn shifting_digits_between_timestamp_and_expiration_invalidates_the_signature() {
+    let (key, sender) = create_keypair();
+    let mut transaction = Transaction::new(sender.clone(), "bob".to_string(), coins(5.0), coins(0.5));
+    transaction.timestamp = 1_712_345_678;
+    transaction.expiration = 1_712_349_278;
+    transaction.sign(&key);
+    assert!(transaction.is_valid());
+
+    // As decimal strings the two fields would concatenate to the same bytes
+    let mut shifted = transaction.clone();
+    shifted.timestamp = 171_234_567;
+    shifted.expiration = 81_712_349_278;
+    assert_eq!(
+        format!("{}{}", shifted.timestamp, shifted.expiration),
+        format!("{}{}", transaction.timestamp, transaction.expiration)
+    );
+    assert!(!shifted.is_valid());
+}
+
+#[test]
+fn malformed_hex_in_a_transaction_is_rejected_without_panicking() {
+    let (key, sender) = create_keypair();
+    let valid = signed_transaction(&key, &sender, "bob", 5.0, 0.5);
+
+    let mut bad_sender = valid.clone();
+    bad_sender.from = "not hex at all".to_string();
+    assert!(!bad_sender.is_valid());
+
+    let mut short_key = valid.clone();
+    short_key.from = sender[..32].to_string();
+    assert!(!short_key.is_valid());
+
+    let mut bad_signature = valid.clone();
+    bad_signature.signature = Some("zz".repeat(64));
+    assert!(!bad_signature.is_valid());
+
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    assert!(blockchain.add_to_mempool(bad_signature).is_err());
+}
+
+#[test]
+fn transaction_hash_depends_only_on_the_amount_value() {
+    let (key, sender) = create_keypair();
+    let base = signed_transaction(&key, &sender, "bob", 0.3, 0.0);
+    let hash_with = |amount: Amount, fee: Amount| {
+        let mut tx = base.clone();
+        tx.amount = amount;
+        tx.fee = fee;
+        tx.calculate_hash()
+    };
+
+    // Coins summed as base units land exactly on the value written directly
+    let sum = coins(0.1) + coins(0.2);
+    assert_eq!(sum, coins(0.3));
+    assert_eq!(hash_with(sum, Amount::ZERO), base.calculate_hash());
+    assert_ne!(hash_with(sum + Amount::from_base_units(1), Amount::ZERO), base.calculate_hash());
+    assert_eq!(hash_with(coins(1.0), Amount::ZERO), hash_with(Amount::from_base_units(100_000_000), Amount::ZERO));
+    assert!(base.is_valid());
+}
+
+#[test]
+fn mempool_rejects_replayed_and_out_of_order_nonces() {
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    let (alice_key, alice) = create_keypair();
+    let (_, bob) = create_keypair();
+    let (_, miner) = create_keypair();
+    blockchain.add_balance(&alice, coins(100.0));
+    let with_nonce = |nonce: u64| {
+        let mut tx = Transaction::new(alice.clone(), bob.clone(), coins(1.0), coins(0.1));
+        tx.nonce = nonce;
+        tx.sign(&alice_key);
+        tx
+    };
+    assert_eq!(blockchain.next_nonce(&alice), 0);
+
+    let error = blockchain.add_to_mempool(with_nonce(1)).unwrap_err();
+    assert!(error.message().contains("gap"), "{}", error);
+
+    blockchain.add_to_mempool(with_nonce(0)).unwrap();
+    assert_eq!(blockchain.next_nonce(&alice), 1);
+    let error = blockchain.add_to_mempool(with_nonce(0)).unwrap_err();
+    assert!(error.message().contains("already used"), "{}", error);
+
+    // Confirmation keeps the nonce used, so the same signed payload can't be replayed later
+    blockchain.mine_pending_transactions(&miner).unwrap();
+    assert!(blockchain.mempool.is_empty());
+    assert_eq!(blockchain.next_nonce(&alice), 1);
+    let error = blockchain.add_to_mempool(with_nonce(0)).unwrap_err();
+    assert!(error.message().contains("already used"), "{}", error);
+    let error = blockchain.add_to_mempool(with_nonce(3)).unwrap_err();
+    assert!(error.message().contains("gap"), "{}", error);
+    blockchain.add_to_mempool(with_nonce(1)).unwrap();
+}
+
+#[test]
+fn utxo_ledger_spends_outputs_once_and_returns_change() {
+    let mut blockchain = Blockchain::with_ledger_model(1, coins(50.0), Duration::seconds(10), LedgerModel::Utxo);
+    let (alice_key, alice) = create_keypair();
+    let (_, bob) = create_keypair();
+    let (_, carol) = create_keypair();
+    blockchain.mine_pending_transactions(&alice).unwrap();
+    let unspent = blockchain.unspent_outputs(&alice);
+    assert_eq!(unspent.len(), 1);
+    let (coinbase_output, _) = unspent[0].clone();
+
+    let spend = |outputs: Vec<TxOutput>| {
+        let mut tx = Transaction::new_utxo(alice.clone(), vec![coinbase_output.clone()], outputs, coins(0.1));
+        tx.sign(&alice_key);
+        tx
+    };
+    let payment = spend(vec![
+        TxOutput { address: bob.clone(), amount: coins(30.0) },
+        TxOutput { address: alice.clone(), amount: coins(19.9) },
+    ]);
+    assert_eq!(payment.amount, coins(49.9));
+    let error = blockchain.add_to_mempool(spend(vec![TxOutput { address: bob.clone(), amount: coins(10.0) }])).unwrap_err();
+    assert!(error.message().contains("spends outputs worth 50"), "{}", error);
+    blockchain.add_to_mempool(payment).unwrap();
+
+    let error = blockchain.add_to_mempool(spend(vec![TxOutput { address: carol.clone(), amount: coins(49.9) }])).unwrap_err();
+    assert!(error.message().contains("already spent by a mempool transaction"), "{}", error);
+    assert!(matches!(error, ChainError::DoubleSpend(_)));
+
+    let (_, miner) = create_keypair();
+    blockchain.mine_pending_transactions(&miner).unwrap();
+    assert_eq!(blockchain.get_balance(&bob), coins(30.0));
+    assert_eq!(blockchain.get_balance(&alice), coins(19.9));
+    let change: Vec<Amount> = blockchain.unspent_outputs(&alice).iter().map(|(_, output)| output.amount).collect();
+    assert_eq!(change, vec![coins(19.9)]);
+
+    let replay = spend(vec![TxOutput { address: carol.clone(), amount: coins(49.9) }]);
+    let error = blockchain.add_to_mempool(replay.clone()).unwrap_err();
+    assert!(error.message().contains("missing or already spent"), "{}", error);
+    assert!(matches!(error, ChainError::DoubleSpend(_)));
+
+    // A block smuggling the spent output past the mempool is refused as well
+    let coinbase = Transaction::new("Blockchain".to_string(), miner.clone(), coins(50.1), coins(0.0));
+    let previous = blockchain.get_latest_block();
+    let mut block = Block::new(previous.index + 1, vec![replay, coinbase], previous.hash.clone(), blockchain.difficulty);
+    block.mine_block(blockchain.difficulty, &AtomicBool::new(false), u64::MAX);
+    let error = blockchain.add_block(block).unwrap_err();
+    assert!(error.message().contains("missing or already spent"), "{}", error);
+    assert!(matches!(error, ChainError::InvalidBlock(_)));
+    assert_eq!(blockchain.get_balance(&carol), coins(0.0));
+}
+
+#[test]
+fn utxo_transaction_reusing_a_confirmed_id_is_rejected() {
+    let mut blockchain = Blockchain::with_ledger_model(1, coins(50.0), Duration::seconds(10), LedgerModel::Utxo);
+    let (alice_key, alice) = create_keypair();
+    let (_, bob) = create_keypair();
+    let (_, carol) = create_keypair();
+    let (_, miner) = create_keypair();
+    blockchain.add_balance(&alice, coins(50.0));
+    assert_eq!(blockchain.get_balance(&alice), coins(50.0));
+
+    let (credit, _) = blockchain.unspent_outputs(&alice)[0].clone();
+    let outputs = vec![
+        TxOutput { address: bob.clone(), amount: coins(10.0) },
+        TxOutput { address: alice.clone(), amount: coins(39.9) },
+    ];
+    let mut payment = Transaction::new_utxo(alice.clone(), vec![credit], outputs, coins(0.1));
+    payment.sign(&alice_key);
+    blockchain.add_to_mempool(payment.clone()).unwrap();
+    blockchain.mine_pending_transactions(&miner).unwrap();
+    assert_eq!(blockchain.get_balance(&bob), coins(10.0));
+
+    // Spending the change under the payment's id would overwrite bob's output with carol's
+    let (change, _) = blockchain.unspent_outputs(&alice)[0].clone();
+    let mut reused = Transaction::new_utxo(alice.clone(), vec![change], vec![TxOutput { address: carol.clone(), amount: coins(39.8) }], coins(0.1));
+    reused.id = payment.id.clone();
+    reused.sign(&alice_key);
+    let error = blockchain.add_to_mempool(reused.clone()).unwrap_err();
+    assert!(error.message().contains("already used on chain"), "{}", error);
+
+    let coinbase = Transaction::new("Blockchain".to_string(), miner.clone(), coins(50.1), coins(0.0));
+    let previous = blockchain.get_latest_block();
+    let mut block = Block::new(previous.index + 1, vec![reused, coinbase], previous.hash.clone(), blockchain.difficulty);
+    block.mine_block(blockchain.difficulty, &AtomicBool::new(false), u64::MAX);
+    let error = blockchain.add_block(block).unwrap_err();
+    assert!(error.message().contains("already used on chain"), "{}", error);
+    assert_eq!(blockchain.get_balance(&bob), coins(10.0));
+}
+
+#[test]
+fn multi_recipient_transaction_pays_everyone_atomically() {
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    let (alice_key, alice) = create_keypair();
+    let (_, miner) = create_keypair();
+    blockchain.add_balance(&alice, coins(100.0));
+    let recipients: Vec<String> = (0..50).map(|_| create_keypair().1).collect();
+    let payroll = |amount: f64| {
+        let mut tx = Transaction::new_multi(alice.clone(), recipients.iter().map(|r| (r.clone(), coins(amount))).collect(), coins(0.1));
+        tx.sign(&alice_key);
+        tx
+    };
+
+    // Short by 0.1 in total, so no recipient is paid at all
+    let error = blockchain.add_to_mempool(payroll(2.0)).unwrap_err();
+    assert!(matches!(error, ChainError::InsufficientBalance(_)), "{}", error);
+
+    let mut tampered = payroll(1.0);
+    tampered.outputs[7].address = alice.clone();
+    assert!(!tampered.is_valid());
+
+    let payment = payroll(1.0);
+    assert_eq!(payment.amount, coins(50.0));
+    blockchain.add_to_mempool(payment.clone()).unwrap();
+    blockchain.mine_pending_transactions(&miner).unwrap();
+
+    let block = blockchain.get_latest_block();
+    assert_eq!(block.transactions.iter().filter(|tx| tx.from == alice).count(), 1);
+    assert_eq!(blockchain.get_balance(&alice), coins(49.9));
+    for recipient in &recipients {
+        assert_eq!(blockchain.get_balance(recipient), coins(1.0));
+        assert_eq!(blockchain.get_transactions_for_address(recipient).len(), 1);
+    }
+    assert!(blockchain.is_chain_valid());
+}
+
+#[test]
+fn multisig_transaction_needs_threshold_of_distinct_signers() {
+    let keys: Vec<(Ed25519KeyPair, String)> = (0..3).map(|_| create_keypair()).collect();
+    let public_keys: Vec<String> = keys.iter().map(|(_, address)| address.clone()).collect();
+    let policy = MultisigPolicy::new(2, vec![public_keys[2].clone(), public_keys[0].clone(), public_keys[1].clone()]).unwrap();
+    assert_eq!(policy.address(), multisig_address(2, &public_keys));
+    assert_ne!(policy.address(), multisig_address(1, &public_keys));
+    assert!(MultisigPolicy::new(4, public_keys.clone()).is_err());
+
+    let (_, bob) = create_keypair();
+    let (outsider_key, _) = create_keypair();
+    let mut tx = Transaction::new_multisig(policy.clone(), bob.clone(), coins(5.0), coins(0.1));
+    tx.add_signature(&keys[0].0);
+    tx.add_signature(&keys[0].0);
+    tx.add_signature(&outsider_key);
+    assert!(!tx.is_valid(), "one distinct signer is below the 2-of-3 threshold");
+
+    tx.add_signature(&keys[2].0);
+    assert!(tx.is_valid());
+
+    // Claiming a looser policy changes the address it would spend from
+    let mut loosened = tx.clone();
+    loosened.multisig = Some(MultisigPolicy::new(1, public_keys.clone()).unwrap());
+    assert!(!loosened.is_valid());
+
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    blockchain.add_balance(&policy.address(), coins(20.0));
+    blockchain.add_to_mempool(tx).unwrap();
+    blockchain.mine_pending_transactions(&create_keypair().1).unwrap();
+    assert_eq!(blockchain.get_balance(&bob), coins(5.0));
+    assert_eq!(blockchain.get_balance(&policy.address()), coins(14.9));
+}
+
+#[test]
+fn transaction_data_is_signed_capped_and_served_over_rpc() {
+    let blockchain = Arc::new(Mutex::new(Blockchain::new(1, coins(10.0), Duration::seconds(10))));
+    let (alice_key, alice) = create_keypair();
+    blockchain.lock().unwrap().add_balance(&alice, coins(10.0));
+    let with_data = |data: Vec<u8>| {
+        let mut tx = Transaction::new(alice.clone(), "bob".to_string(), coins(1.0), coins(0.1));
+        tx.data = Some(data);
+        tx.sign(&alice_key);
+        tx
+    };
+
+    let mut tampered = with_data(b"invoice 42".to_vec());
+    tampered.data = Some(b"invoice 43".to_vec());
+    assert!(!tampered.is_valid());
+    tampered.data = None;
+    assert!(!tampered.is_valid());
+
+    let error = blockchain.lock().unwrap().add_to_mempool(with_data(vec![7; MAX_TRANSACTION_DATA_LEN + 1])).unwrap_err();
+    assert!(error.message().contains("at most 256"), "{}", error);
+
+    let memo = with_data(b"invoice 42".to_vec());
+    blockchain.lock().unwrap().add_to_mempool(memo.clone()).unwrap();
+    let server = RpcServer::bind("127.0.0.1:0", Arc::clone(&blockchain)).unwrap();
+    let served = rpc_call(server.local_addr(), "gettransaction", serde_json::json!([memo.id]))["result"].clone();
+    assert_eq!(served["data"], serde_json::json!(b"invoice 42".to_vec()));
+}
+
+#[test]
+fn block_data_cap_trims_selection_and_rejects_oversized_blocks() {
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    blockchain.max_block_data_bytes = 500;
+    let (alice_key, alice) = create_keypair();
+    let (_, miner) = create_keypair();
+    blockchain.add_balance(&alice, coins(100.0));
+    let with_data = |nonce: u64| {
+        let mut tx = Transaction::new(alice.clone(), "bob".to_string(), coins(1.0), coins(0.1));
+        tx.nonce = nonce;
+        tx.data = Some(vec![7; 200]);
+        tx.sign(&alice_key);
+        tx
+    };
+    let transactions: Vec<Transaction> = (0..3).map(with_data).collect();
+    for tx in &transactions {
+        blockchain.add_to_mempool(tx.clone()).unwrap();
+    }
+
+    // Only two 200-byte payloads fit under the 500-byte cap; the third waits
+    blockchain.mine_pending_transactions(&miner).unwrap();
+    assert_eq!(blockchain.get_latest_block().transactions.len(), 3);
+    assert_eq!(blockchain.mempool.len(), 1);
+    blockchain.mine_pending_transactions(&miner).unwrap();
+    assert!(blockchain.mempool.is_empty());
+
+    // A block packing all three is refused
+    let mut packed = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    packed.max_block_data_bytes = 500;
+    packed.add_balance(&alice, coins(100.0));
+    push_mined_block(&mut packed, transactions);
+    let block = packed.chain.pop().unwrap();
+    let error = packed.add_block(block).unwrap_err();
+    assert!(error.message().contains("600 bytes of transaction data"), "{}", error);
+}
+
+#[test]
+fn locked_transactions_wait_for_their_time_or_height() {
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    let start = Utc::now();
+    let clock = Arc::new(MockClock::new(start));
+    blockchain.clock = clock.clone();
+    let (alice_key, alice) = create_keypair();
+    let (_, bob) = create_keypair();
+    let (_, miner) = create_keypair();
+    blockchain.add_balance(&alice, coins(100.0));
+    let locked = |locktime: i64, nonce: u64| {
+        let mut tx = Transaction::new(alice.clone(), bob.clone(), coins(1.0), coins(0.1));
+        tx.locktime = locktime;
+        tx.nonce = nonce;
+        tx.sign(&alice_key);
+        tx
+    };
+
+    let unlock_at = start.timestamp() + 600;
+    assert!(unlock_at >= LOCKTIME_THRESHOLD);
+    let vesting = locked(unlock_at, 0);
+    let mut stripped = vesting.clone();
+    stripped.locktime = 0;
+    assert!(!stripped.is_valid());
+    let error = blockchain.add_to_mempool(vesting.clone()).unwrap_err();
+    assert!(error.message().contains("locked until"), "{}", error);
+    clock.advance(Duration::minutes(11));
+    blockchain.add_to_mempool(vesting).unwrap();
+
+    // Height 2 is only reachable by the block after next
+    let error = blockchain.add_to_mempool(locked(2, 1)).unwrap_err();
+    assert!(error.message().contains("locked until 2"), "{}", error);
+    let previous = blockchain.get_latest_block();
+    let early = locked(2, 1);
+    let coinbase = next_coinbase(&blockchain, std::slice::from_ref(&early));
+    let mut block = Block::new(previous.index + 1, vec![early, coinbase], previous.hash.clone(), blockchain.difficulty);
+    block.mine_block(blockchain.difficulty, &AtomicBool::new(false), u64::MAX);
+    let error = blockchain.add_block(block).unwrap_err();
+    assert!(error.message().contains("is locked until 2"), "{}", error);
+
+    blockchain.mine_pending_transactions(&miner).unwrap();
+    let scheduled = locked(2, 1);
+    blockchain.add_to_mempool(scheduled.clone()).unwrap();
+    blockchain.mine_pending_transactions(&miner).unwrap();
+    assert!(blockchain.get_latest_block().transactions.iter().any(|tx| tx.id == scheduled.id));
+    assert_eq!(blockchain.get_balance(&bob), coins(1.0));
+    // Blocks are stamped with the system time, which hasn't reached the vesting date yet
+    assert_eq!(blockchain.mempool.len(), 1);
+}
+
+#[test]
+fn self_send_is_rejected() {
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    let (alice_key, alice) = create_keypair();
+    blockchain.add_balance(&alice, coins(10.0));
+
+    let transaction = next_signed_transaction(&blockchain, &alice_key, &alice, &alice, 1.0, 0.1);
+    assert!(!transaction.is_valid());
+    let err = blockchain.add_to_mempool(transaction).unwrap_err();
+    assert_eq!(err.message(), "Transaction sends to its own address");
+    assert!(blockchain.mempool.is_empty());
+}
+
+#[test]
+fn zero_amount_transfer_is_rejected() {
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    let (alice_key, alice) = create_keypair();
+    let (_, bob) = create_keypair();
+
+    // Rejected for the amount even though the sender has no funds
+    let transaction = next_signed_transaction(&blockchain, &alice_key, &alice, &bob, 0.0, 0.1);
+    assert!(!transaction.is_valid());
+    let err = blockchain.add_to_mempool(transaction).unwrap_err();
+    assert_eq!(err.message(), "Transaction amount must be greater than zero");
+    assert!(blockchain.mempool.is_empty());
+}
+
+#[test]
+fn mempool_rejections_are_typed_by_cause() {
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    let (alice_key, alice) = create_keypair();
+    blockchain.add_balance(&alice, coins(10.0));
+
+    let cheap = next_signed_transaction(&blockchain, &alice_key, &alice, "bob", 1.0, 0.00000001);
+    assert!(matches!(blockchain.add_to_mempool(cheap), Err(ChainError::FeeTooLow(_))));
+
+    let mut expired = Transaction::new(alice.clone(), "bob".to_string(), coins(1.0), coins(0.1));
+    expired.expiration = (Utc::now() - Duration::hours(1)).timestamp();
+    expired.sign(&alice_key);
+    assert!(matches!(blockchain.add_to_mempool(expired), Err(ChainError::Expired(_))));
+
+    let overdraft = next_signed_transaction(&blockchain, &alice_key, &alice, "bob", 20.0, 0.1);
+    assert!(matches!(blockchain.add_to_mempool(overdraft), Err(ChainError::InsufficientBalance(_))));
+
+    blockchain.max_mempool_size_bytes = 10;
+    let oversized = next_signed_transaction(&blockchain, &alice_key, &alice, "bob", 1.0, 0.1);
+    let err = blockchain.add_to_mempool(oversized).unwrap_err();
+    assert!(matches!(err, ChainError::MempoolFull(_)), "{}", err);
+    assert!(blockchain.mempool.is_empty());
+}
+
+#[test]
+fn empty_chain_reports_errors_instead_of_panicking() {
+    let mut blockchain = Blockchain::new(1, coins(10.0), Duration::seconds(10));
+    let genesis = blockchain.get_latest_block().clone();
+    assert_eq!(blockchain.try_get_latest_block().map(|tip| &tip.hash), Some(&genesis.hash));
+
+    blockchain.chain.clear();
+    assert!(blockchain.try_get_latest_block().is_none());
+    let err = blockchain.mine_pending_transactions("miner").unwrap_err();
+    assert_eq!(err, ChainError::NotFound("Blockchain is empty".to_string()));
+    assert!(matches!(blockchain.submit_block(genesis.clone()), Err(ChainError::NotFound(_))));
+    assert!(matches!(blockchain.validate_headers(&[]), Err(ChainError::NotFound(_))));
+    assert_eq!(blockchain.snapshot_balances().height, 0);
+
+    let status = blockchain.status();
+    assert_eq!((status.height, status.tip_hash.as_str()), (0, ""));
+    assert!(status.chain_valid);
+    let stats = blockchain.chain_stats(0, 10);
+    assert_eq!(stats.block_count, 0);
+    assert_eq!(stats.transaction_count, 0);
+}
+
+#[test]
+fn full_mempool_admits_only_better_paying_transactions() {
+    let mut blockchain = Blockchain::builder(1, coins(10.0), Duration::seconds(10)).max_mempool_size(2).build();
+    let senders: Vec<(Ed25519KeyPair, String)> = (0..4).map(|_| create_keypair()).collect();
+    for (_, sender) in &senders {
+        blockchain.add_balance(sender, coins(10.0));
+    }
+    let payment = |sender: usize, fee: f64| {
+        let (key, address) = &senders[sender];
+        signed_transaction(key, address, "bob", 1.0, fee)
+    };
+    let cheapest = payment(0, 0.1);
+    blockchain.add_to_mempool(cheapest.clone()).unwrap();
+    blockchain.add_to_mempool(payment(1, 0.2)).unwrap();
+
+    let error = blockchain.add_to_mempool(payment(2, 0.05)).unwrap_err();
+    assert!(matches!(error, ChainError::MempoolFull(_)), "{}", error);
+    assert_eq!(blockchain.mempool.len(), 2);
+
+    // A higher fee rate evicts the cheapest pending transaction instead
+    blockchain.add_to_mempool(payment(3, 0.3)).unwrap();
+    assert_eq!(blockchain.mempool.len(), 2);
+    assert!(blockchain.mempool.iter().all(|tx| tx.id != cheapest.id));
+    assert_eq!(blockchain.first_seen(&cheapest.id), None);
+}
+
+#[test]
+fn builder_configures_every_setting() {
+    let genesis_time = Utc::now() - Duration::days(1);
+    let builder: BlockchainBuilder = Blockchain::builder(1, coins(40.0), Duration::seconds(5))
+        .difficulty_adjustment_interval(20)
+        .max_mempool_size(50)
+        .max_mempool_size_bytes(64_000)
+        .genesis_timestamp(genesis_time)
+        .chain_id(7)
+        .coinbase_maturity(2)
+        .halving_interval(3)
+        .consensus(Consensus::ProofOfWork)
+        .ledger_model(LedgerModel::Account);
+    let mut blockchain = builder.build();
+
+    assert_eq!(blockchain.difficulty_adjustment_interval, 20);
+    assert_eq!(blockchain.max_mempool_size, 50);
+    assert_eq!(blockchain.max_mempool_size_bytes, 64_000);
+    assert_eq!(blockchain.chain_id, 7);
+    assert_eq!(blockchain.coinbase_maturity, 2);
+    assert_eq!(blockchain.halving_interval, Some(3));
+    assert_eq!(blockchain.target_block_time, Duration::seconds(5));
+
+    // The custom genesis gives the network its own genesis hash, which the chain validates against
+    let genesis = blockchain.get_latest_block().clone();
+    assert_eq!(genesis.timestamp, genesis_time);
+    assert_eq!(blockchain.genesis_hash, genesis.hash);
+    assert_ne!(genesis.hash, Blockchain::genesis_block(1).hash);
+
+    let (_, miner) = create_keypair();
+    for _ in 0..4 {
+        blockchain.mine_pending_transactions(&miner).unwrap();
+    }
+    assert!(blockchain.validate_chain());
+    assert_eq!(blockchain.get_balance(&miner), coins(40.0 * 2.0 + 20.0 * 2.0));
+    assert_eq!(blockchain.spendable_balance(&miner), coins(40.0 * 2.0));
+    assert!(blockchain.validate_external_chain(&blockchain.chain).is_ok());
+    assert!(Blockchain::new(1, coins(40.0), Duration::seconds(5)).validate_external_chain(&blockchain.chain).is_err());
+}
+
+#[test]
+fn builder_settings_survive_reloading_from_blocks_store_and_log() {
+    let builder = Blockchain::builder(1, coins(40.0), Duration::seconds(5))
+        .difficulty_adjustment_interval(20)
+        .genesis_timestamp(Utc::now() - Duration::days(1))
+        .chain_id(7)
+        .coinbase_maturity(2)
+        .halving_interval(2);
+    let mut blockchain = builder.clone().build();
+    let path = temp_path("builder-reload.log");
+    let path = path.to_str().unwrap();
+    blockchain.open_block_log(path).unwrap();
+    blockchain.attach_store(Box::new(MemoryChainStore::new())).unwrap();
+    let (_, miner) = create_keypair();
+    for _ in 0..3 {
+        blockchain.mine_pending_transactions(&miner).unwrap();
+    }
+
+    let store = blockchain.detach_store().unwrap();
+    let reloads = [
+        builder.clone().build_from_blocks(blockchain.chain.clone()).unwrap(),
+        builder.clone().build_from_store(store).unwrap(),
+        builder.clone().build_from_log(path).unwrap(),
+    ];
+    for reloaded in &reloads {
+        assert_eq!(reloaded.get_latest_block().hash, blockchain.get_latest_block().hash);
+        assert_eq!(reloaded.genesis_hash, blockchain.genesis_hash);
+        assert_eq!(reloaded.difficulty_adjustment_interval, 20);
+        assert_eq!(reloaded.chain_id, 7);
+        assert_eq!(reloaded.coinbase_maturity, 2);
+        assert_eq!(reloaded.halving_interval, Some(2));
+        assert_eq!(reloaded.get_balance(&miner), coins(40.0 + 20.0 * 2.0));
+        assert_eq!(reloaded.spendable_balance(&miner), coins(40.0));
+    }
+
+    // The default settings reject the custom genesis and the halved rewards
+    assert!(Blockchain::from_blocks(blockchain.chain.clone(), 1, coins(40.0), Duration::seconds(5)).is_err());
+    assert!(Blockchain::replay_log(path, coins(40.0), Duration::seconds(5)).is_err());
+    std::fs::remove_file(path).unwrap();
+}